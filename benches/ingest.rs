@@ -0,0 +1,144 @@
+//! Ingest and provenance benchmarks for the in-memory knowledge base.
+//!
+//! Run with `cargo bench`. Throughput is reported as nodes/sec (lines ingested)
+//! and edges/sec (structural edges created) for ingest, and nodes/sec (nodes in
+//! the graph) for the contamination searches.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use liasiondb::{KnowledgeBase, Node};
+use std::hint::black_box;
+
+const DOCUMENT_SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+/// Fractions of lines that differ between the original and re-ingested document
+const CHANGE_RATIOS: [f64; 4] = [0.0, 0.1, 0.5, 1.0];
+
+/// Builds a document of `lines` lines where every `change_every`-th line belongs to revision 1
+fn document(lines: usize, change_every: Option<usize>) -> String {
+    (0..lines)
+        .map(|i| {
+            let revision = match change_every {
+                Some(every) if i % every == 0 => 1,
+                _ => 0,
+            };
+            format!("line {} of revision {}", i, revision)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn change_every(ratio: f64) -> Option<usize> {
+    (ratio > 0.0).then(|| (1.0 / ratio).round() as usize)
+}
+
+fn bench_fresh_ingest(c: &mut Criterion) {
+    for (group_name, per_line) in [("insert_markdown/nodes", 0), ("insert_markdown/edges", 1)] {
+        let mut group = c.benchmark_group(group_name);
+        for lines in DOCUMENT_SIZES {
+            let doc = document(lines, None);
+            // A fresh ingest creates one node per line, and one edge per line plus
+            // the directory -> file edge
+            group.throughput(Throughput::Elements((lines + per_line) as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(lines), &doc, |b, doc| {
+                b.iter_batched(
+                    KnowledgeBase::new,
+                    |mut kb| {
+                        let dir_idx = kb.insert_directory("docs");
+                        let doc = black_box(doc);
+                        kb.insert_markdown(doc, "doc.md", dir_idx, vec![], 0, "version-0")
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+        group.finish();
+    }
+}
+
+fn bench_reingest(c: &mut Criterion) {
+    let lines = 1_000;
+    let original = document(lines, None);
+
+    let mut group = c.benchmark_group("reingest");
+    group.throughput(Throughput::Elements(lines as u64));
+    for ratio in CHANGE_RATIOS {
+        let revision = document(lines, change_every(ratio));
+        group.bench_with_input(BenchmarkId::from_parameter(ratio), &revision, |b, revision| {
+            b.iter_batched(
+                || {
+                    let mut kb = KnowledgeBase::new();
+                    let dir_idx = kb.insert_directory("docs");
+                    kb.insert_markdown(&original, "doc.md", dir_idx, vec![], 0, "version-0");
+                    (kb, dir_idx)
+                },
+                |(mut kb, dir_idx)| {
+                    let revision = black_box(revision);
+                    kb.insert_markdown(revision, "doc.md", dir_idx, vec![], 1, "version-1")
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Builds a chain of files where each file references the first few nodes of the previous one.
+///
+/// Returns the knowledge base, the first node of the first file (the root source), and
+/// the last node of the last file (the most derived node).
+fn provenance_chain(files: usize, lines: usize) -> (KnowledgeBase, usize, usize) {
+    let mut kb = KnowledgeBase::new();
+    let dir_idx = kb.insert_directory("docs");
+
+    let mut references: Vec<Node> = Vec::new();
+    let mut root_source = None;
+    let mut last_node = 0;
+
+    for file in 0..files {
+        let filename = format!("doc{}.md", file);
+        let content = (0..lines)
+            .map(|line| format!("line {} of {}", line, filename))
+            .collect::<Vec<_>>()
+            .join("\n");
+        kb.insert_markdown(
+            &content,
+            &filename,
+            dir_idx,
+            references,
+            file as i32,
+            &format!("version-{}", file),
+        );
+
+        let (_, node_indices) = kb.read_file(&filename).unwrap();
+        root_source.get_or_insert(node_indices[0]);
+        last_node = *node_indices.last().unwrap();
+        references = node_indices
+            .iter()
+            .take(5)
+            .map(|idx| kb.nodes().get_index(*idx).unwrap().clone())
+            .collect();
+    }
+
+    (kb, root_source.unwrap(), last_node)
+}
+
+fn bench_contamination(c: &mut Criterion) {
+    let mut group = c.benchmark_group("contamination");
+    for files in [10, 100] {
+        let (kb, root_source, last_node) = provenance_chain(files, 50);
+        group.throughput(Throughput::Elements(kb.node_count() as u64));
+
+        group.bench_function(BenchmarkId::new("contaminated_forward", files), |b| {
+            b.iter(|| kb.find_contaminated_nodes(black_box(root_source)))
+        });
+        // Backward search ranges over the reverse reference index per visited node,
+        // so it should keep pace with the forward search
+        group.bench_function(BenchmarkId::new("referenced_backward", files), |b| {
+            b.iter(|| kb.find_referenced_nodes(black_box(last_node)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_fresh_ingest, bench_reingest, bench_contamination);
+criterion_main!(benches);
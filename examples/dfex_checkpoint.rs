@@ -0,0 +1,27 @@
+use liasiondb::DataFusionKnowledgeBase;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let doc = "doc.md";
+    let org = "myorg";
+    let kb = DataFusionKnowledgeBase::new().await?.with_defaults(doc, org);
+
+    let content_vec: Vec<&str> = vec!["<ORIGIN_doc.md>", "# First draft", "This is text"];
+    println!("{:?}", kb.insert(&content_vec).await?);
+    kb.checkpoint("draft", doc, org).await?;
+
+    let content_vec: Vec<&str> =
+        vec!["<ORIGIN_doc.md>", "# Final version", "This is text", "More text"];
+    println!("{:?}", kb.insert(&content_vec).await?);
+
+    println!("----Latest----");
+    println!("{:?}", kb.trace_latest("<ORIGIN_doc.md>", doc, org).await?);
+
+    println!("----At checkpoint 'draft'----");
+    println!("{:?}", kb.trace_at_checkpoint("<ORIGIN_doc.md>", doc, org, "draft").await?);
+
+    println!("----At missing checkpoint----");
+    println!("{:?}", kb.trace_at_checkpoint("<ORIGIN_doc.md>", doc, org, "nope").await?);
+
+    Ok(())
+}
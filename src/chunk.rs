@@ -0,0 +1,260 @@
+//! Chunkers decide how ingested content is split into content nodes.
+//!
+//! Each chunk becomes one node of the file's edge chain, so chunk granularity is
+//! retrieval granularity: search, embeddings, and provenance all work per chunk.
+//! Reconstruction puts every chunk on its own line, so only `LineChunker` and
+//! `BudgetChunker` round-trip content exactly (apart from dropped empty lines, and
+//! lines `BudgetChunker` has to cut).
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Splits content into the chunks that become a file's content nodes, in order.
+///
+/// Chunks may be empty; ingest skips them.
+pub trait Chunker: Debug + Send + Sync {
+    fn chunk<'a>(&self, content: &'a str) -> Vec<&'a str>;
+}
+
+/// One chunk per line. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LineChunker;
+
+impl Chunker for LineChunker {
+    fn chunk<'a>(&self, content: &'a str) -> Vec<&'a str> {
+        content.split('\n').collect()
+    }
+}
+
+/// One chunk per paragraph, i.e. per run of lines between blank lines. A
+/// paragraph's lines stay together in one node, separated by `\n`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParagraphChunker;
+
+impl Chunker for ParagraphChunker {
+    fn chunk<'a>(&self, content: &'a str) -> Vec<&'a str> {
+        let mut chunks = Vec::new();
+        // Byte offset where the current paragraph starts, if one is open
+        let mut start = None;
+        let mut offset = 0;
+
+        for line in content.split_inclusive('\n') {
+            if line.trim().is_empty() {
+                if let Some(start) = start.take() {
+                    chunks.push(content[start..offset].trim_end());
+                }
+            } else if start.is_none() {
+                start = Some(offset);
+            }
+            offset += line.len();
+        }
+        if let Some(start) = start {
+            chunks.push(content[start..].trim_end());
+        }
+
+        chunks
+    }
+}
+
+/// One chunk per sentence. A sentence ends at `.`, `!`, or `?` followed by
+/// whitespace, or at the end of a line, so headings and list items without
+/// punctuation are chunks of their own. Surrounding whitespace is trimmed.
+///
+/// This is a simple punctuation-based splitter: abbreviations like "e.g. this"
+/// end a sentence too.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SentenceChunker;
+
+impl Chunker for SentenceChunker {
+    fn chunk<'a>(&self, content: &'a str) -> Vec<&'a str> {
+        let mut chunks = Vec::new();
+
+        for line in content.split('\n') {
+            let mut start = 0;
+            let mut chars = line.char_indices().peekable();
+            while let Some((idx, ch)) = chars.next() {
+                let ends_sentence = matches!(ch, '.' | '!' | '?')
+                    && chars.peek().is_none_or(|(_, next)| next.is_whitespace());
+                if ends_sentence {
+                    let end = idx + ch.len_utf8();
+                    chunks.push(line[start..end].trim());
+                    start = end;
+                }
+            }
+            chunks.push(line[start..].trim());
+        }
+
+        chunks
+    }
+}
+
+/// Packs whole lines into chunks of at most `max_chars` characters, for
+/// retrieval-sized nodes. A line joins the current chunk unless that would take the
+/// chunk over the budget, in which case it starts the next one. A line longer than
+/// the budget is cut into budget-sized pieces, the last of which later lines can
+/// join, so no chunk is ever over the budget.
+///
+/// Chunks keep the newlines between their lines, so reading the file back returns
+/// the content as written, except that the pieces of a cut line read back on
+/// separate lines and a blank line left as a chunk of its own is dropped.
+#[derive(Debug, Clone, Copy)]
+pub struct BudgetChunker {
+    /// Most characters per chunk, counting the newlines between its lines. A
+    /// budget of 0 counts as 1.
+    pub max_chars: usize,
+}
+
+impl Chunker for BudgetChunker {
+    fn chunk<'a>(&self, content: &'a str) -> Vec<&'a str> {
+        let max_chars = self.max_chars.max(1);
+        let mut chunks = Vec::new();
+        // Byte offset where the current chunk starts, and its length in characters
+        let mut start = 0;
+        let mut chars = 0;
+        // Byte offset of the next line
+        let mut offset = 0;
+
+        for (line_idx, line) in content.split('\n').enumerate() {
+            let line_start = offset;
+            offset += line.len() + 1;
+            if line_idx > 0 {
+                let line_chars = line.chars().count();
+                if chars + 1 + line_chars <= max_chars {
+                    chars += 1 + line_chars;
+                    continue;
+                }
+                // End the chunk before the newline, which reads put back between chunks
+                chunks.push(&content[start..line_start - 1]);
+            }
+
+            // The line starts a chunk, cut into pieces if it is over the budget
+            start = line_start;
+            chars = 0;
+            for (idx, _) in line.char_indices() {
+                if chars == max_chars {
+                    chunks.push(&content[start..line_start + idx]);
+                    start = line_start + idx;
+                    chars = 0;
+                }
+                chars += 1;
+            }
+        }
+        chunks.push(&content[start..]);
+
+        chunks
+    }
+}
+
+/// Looks up a built-in chunker by name: `line`, `paragraph`, `sentence`, or
+/// `budget:N` for a `BudgetChunker` of N characters.
+pub fn chunker_by_name(name: &str) -> Option<Arc<dyn Chunker>> {
+    if let Some(max_chars) = name.strip_prefix("budget:") {
+        let max_chars = max_chars.parse().ok().filter(|max_chars| *max_chars > 0)?;
+        return Some(Arc::new(BudgetChunker { max_chars }));
+    }
+    match name {
+        "line" => Some(Arc::new(LineChunker)),
+        "paragraph" => Some(Arc::new(ParagraphChunker)),
+        "sentence" => Some(Arc::new(SentenceChunker)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that chunks are in-order slices of `content` separated by nothing (a
+    /// cut line) or a single newline, and rebuilds the content from them.
+    fn rejoin(content: &str, chunks: &[&str]) -> String {
+        let base = content.as_ptr() as usize;
+        let mut rebuilt = String::new();
+        let mut end = 0;
+        for chunk in chunks {
+            let start = chunk.as_ptr() as usize - base;
+            let gap = &content[end..start];
+            assert!(gap.is_empty() || gap == "\n", "gap {:?} before {:?}", gap, chunk);
+            rebuilt.push_str(gap);
+            rebuilt.push_str(chunk);
+            end = start + chunk.len();
+        }
+        assert_eq!(end, content.len());
+        rebuilt
+    }
+
+    fn non_empty<'a>(chunks: Vec<&'a str>) -> Vec<&'a str> {
+        chunks.into_iter().filter(|chunk| !chunk.is_empty()).collect()
+    }
+
+    #[test]
+    fn sentence_splits_at_terminal_punctuation() {
+        assert_eq!(non_empty(SentenceChunker.chunk("A. B. C.")), ["A.", "B.", "C."]);
+        assert_eq!(
+            non_empty(SentenceChunker.chunk("Really? Yes! Done")),
+            ["Really?", "Yes!", "Done"]
+        );
+    }
+
+    #[test]
+    fn sentence_splits_after_abbreviations() {
+        // Only punctuation followed by whitespace ends a sentence, so "e.g." is cut
+        // once, after its last dot, and decimals stay whole
+        assert_eq!(
+            non_empty(SentenceChunker.chunk("Use e.g. this one. Pi is 3.14 here.")),
+            ["Use e.g.", "this one.", "Pi is 3.14 here."]
+        );
+    }
+
+    #[test]
+    fn sentence_trims_surrounding_whitespace() {
+        assert_eq!(
+            non_empty(SentenceChunker.chunk("  A.   B.  \n\tC.  \n   ")),
+            ["A.", "B.", "C."]
+        );
+        assert!(non_empty(SentenceChunker.chunk("   \n ")).is_empty());
+    }
+
+    #[test]
+    fn sentence_ends_at_line_breaks() {
+        assert_eq!(
+            non_empty(SentenceChunker.chunk("# Heading\n- item\nText. More")),
+            ["# Heading", "- item", "Text.", "More"]
+        );
+    }
+
+    #[test]
+    fn budget_chunks_stay_within_budget() {
+        let content = "short\nlines pack\n\n\n\ntogether\n0123456789abcdefghij\nend\nü".repeat(3);
+        for max_chars in [1, 3, 7, 12, 40] {
+            let chunks = BudgetChunker { max_chars }.chunk(&content);
+            for chunk in &chunks {
+                assert!(chunk.chars().count() <= max_chars, "{:?} over {}", chunk, max_chars);
+            }
+            assert_eq!(rejoin(&content, &chunks), content);
+        }
+    }
+
+    #[test]
+    fn budget_cuts_single_over_long_line() {
+        let chunks = BudgetChunker { max_chars: 4 }.chunk("abcdefghij\nk");
+        assert_eq!(chunks, ["abcd", "efgh", "ij\nk"]);
+
+        // Characters are counted, not bytes
+        let chunks = BudgetChunker { max_chars: 2 }.chunk("äöüß");
+        assert_eq!(chunks, ["äö", "üß"]);
+    }
+
+    #[test]
+    fn budget_chunks_join_back_to_content() {
+        let content = "# Title\n\nFirst paragraph\nstill first\n\nSecond\n";
+        let chunks = BudgetChunker { max_chars: 20 }.chunk(content);
+        assert_eq!(chunks, ["# Title\n", "First paragraph", "still first\n\nSecond\n"]);
+        assert_eq!(chunks.join("\n"), content);
+    }
+
+    #[test]
+    fn budget_chunks_empty_input() {
+        let chunks = BudgetChunker { max_chars: 10 }.chunk("");
+        assert!(chunks.iter().all(|chunk| chunk.is_empty()));
+    }
+}
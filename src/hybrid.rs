@@ -0,0 +1,890 @@
+//! A DataFusion-backed knowledge base, ported from the `dfex_hybrid` example.
+//!
+//! Nodes and edges are rows of the SQL tables `kb.nodes` and `kb.edges`, keyed by a
+//! hash of each line's content, doc, and org. In-memory `BTreeSet` indices of the
+//! stored keys guard the inserts, so a batch only writes rows that are new. This is
+//! a separate backend from `KnowledgeBase` and is not yet wired into the server.
+//!
+//! Edge rows are numbered in insert order, and the latest path follows the
+//! highest-numbered edge out of each node. Named checkpoints in `kb.checkpoints`
+//! record the number reached, so a doc can be read back as it was then by ignoring
+//! later edges. `trace_reachable` follows
+//! every edge instead, forward to what a line led to or backward to its ancestors.
+
+use crate::{Clock, SystemClock};
+use datafusion::arrow::util::display::array_value_to_string;
+use datafusion::error::DataFusionError;
+use datafusion::prelude::SessionContext;
+use rapidhash::fast::SeedableState;
+use std::collections::BTreeSet;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Most edges `trace_latest` and `trace_at_checkpoint` follow. The recursive query
+/// behind them would otherwise never end on a path that loops back on itself.
+pub const MAX_TRACE_DEPTH: usize = 10_000;
+
+/// Errors from the DataFusion-backed knowledge base.
+#[derive(Debug)]
+pub enum HybridError {
+    /// A SQL statement failed to plan or execute
+    DataFusion(DataFusionError),
+    /// `insert` was called before `with_defaults`
+    MissingDefaults,
+    /// A batch insert failed with a permanent error, or kept failing until the
+    /// `RetryPolicy` ran out of attempts
+    InsertFailed {
+        table: &'static str,
+        attempts: u32,
+        source: DataFusionError,
+    },
+}
+
+impl std::fmt::Display for HybridError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HybridError::DataFusion(err) => write!(f, "datafusion query failed: {}", err),
+            HybridError::MissingDefaults => {
+                write!(f, "no default doc and org set, call with_defaults")
+            }
+            HybridError::InsertFailed {
+                table,
+                attempts,
+                source,
+            } => write!(
+                f,
+                "insert into {} failed after {} attempts: {}",
+                table, attempts, source
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HybridError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HybridError::DataFusion(err) | HybridError::InsertFailed { source: err, .. } => {
+                Some(err)
+            }
+            HybridError::MissingDefaults => None,
+        }
+    }
+}
+
+impl From<DataFusionError> for HybridError {
+    fn from(err: DataFusionError) -> Self {
+        HybridError::DataFusion(err)
+    }
+}
+
+/// Rows written by one `DataFusionKnowledgeBase::unique_insert`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchInsertStats {
+    /// Number of new node rows
+    pub nodes_added: usize,
+    /// Number of new edge rows
+    pub edges_added: usize,
+}
+
+/// How `DataFusionKnowledgeBase` retries a batch insert that failed with a transient
+/// error, i.e. an I/O error, exhausted resources, or an error from an external
+/// source. Other errors, such as a malformed query, fail at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first; 1 disables retries
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled before each further one
+    pub initial_backoff: Duration,
+    /// Longest delay between two attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Which way `DataFusionKnowledgeBase::trace_reachable` follows edges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceDirection {
+    /// From a node to the nodes after it (`o_id` to `d_id`), like contamination
+    Forward,
+    /// From a node to the nodes before it (`d_id` to `o_id`), like
+    /// `KnowledgeBase::find_referenced_nodes`
+    Backward,
+}
+
+impl TraceDirection {
+    /// The edge columns joined on and followed to, in that order.
+    fn columns(self) -> (&'static str, &'static str) {
+        match self {
+            TraceDirection::Forward => ("o_id", "d_id"),
+            TraceDirection::Backward => ("d_id", "o_id"),
+        }
+    }
+}
+
+/// A node found by `DataFusionKnowledgeBase::trace_reachable`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracedNode {
+    pub content: String,
+    /// Fewest edges between the origin and this node
+    pub depth: usize,
+}
+
+/// Knowledge base stored in DataFusion tables, deduplicated by in-memory indices.
+pub struct DataFusionKnowledgeBase {
+    ctx: SessionContext,
+    node_index: RwLock<BTreeSet<u64>>,
+    edge_index: RwLock<BTreeSet<(u64, u64)>>,
+    /// Sequence number the next edge row is written with
+    edge_seq: AtomicU64,
+    default_doc: Option<String>,
+    default_org: Option<String>,
+    retry_policy: RetryPolicy,
+    /// Seed of the IDs returned by `doc_id` (`None` = the fixed seed)
+    hash_seed: Option<u64>,
+    /// Source of the `time` written with every row
+    clock: Arc<dyn Clock>,
+}
+
+impl DataFusionKnowledgeBase {
+    /// Creates the `kb` schema with empty `nodes` and `edges` tables.
+    pub async fn new() -> Result<Self, HybridError> {
+        let ctx = SessionContext::new();
+
+        ctx.sql("CREATE SCHEMA kb").await?.collect().await?;
+
+        ctx.sql(
+            r#"
+            CREATE TABLE kb.nodes (
+                id VARCHAR(64),
+                content VARCHAR,
+                doc VARCHAR,
+                org VARCHAR,
+                time TIMESTAMP
+            )
+        "#,
+        )
+        .await?
+        .collect()
+        .await?;
+
+        ctx.sql(
+            r#"
+            CREATE TABLE kb.edges (
+                id VARCHAR(129),
+                o_id VARCHAR(64),
+                d_id VARCHAR(64),
+                time TIMESTAMP,
+                seq BIGINT
+            )
+        "#,
+        )
+        .await?
+        .collect()
+        .await?;
+
+        ctx.sql(
+            r#"
+            CREATE TABLE kb.checkpoints (
+                name VARCHAR,
+                doc VARCHAR,
+                org VARCHAR,
+                time TIMESTAMP,
+                seq BIGINT
+            )
+        "#,
+        )
+        .await?
+        .collect()
+        .await?;
+
+        Ok(Self {
+            ctx,
+            node_index: RwLock::new(BTreeSet::new()),
+            edge_index: RwLock::new(BTreeSet::new()),
+            edge_seq: AtomicU64::new(0),
+            default_doc: None,
+            default_org: None,
+            retry_policy: RetryPolicy::default(),
+            hash_seed: None,
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Sets the doc and org used by `insert`, so repeated inserts don't re-specify them.
+    /// `unique_insert` can still be called with explicit values to override them.
+    pub fn with_defaults(mut self, doc: &str, org: &str) -> Self {
+        self.default_doc = Some(doc.to_string());
+        self.default_org = Some(org.to_string());
+        self
+    }
+
+    /// Sets how failed batch inserts are retried, instead of `RetryPolicy::default`.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sets the seed `doc_id` hashes docs with, see `seeded_document_id`.
+    pub fn with_hash_seed(mut self, hash_seed: u64) -> Self {
+        self.hash_seed = Some(hash_seed);
+        self
+    }
+
+    /// Sets the clock rows are timestamped by, instead of `SystemClock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The session holding the `kb` tables, for running queries against them.
+    pub fn context(&self) -> &SessionContext {
+        &self.ctx
+    }
+
+    /// Returns a doc's stable ID, the same as `KnowledgeBase::file_id` for a file of
+    /// that name in a knowledge base with the same hash seed.
+    pub fn doc_id(&self, doc: &str) -> u64 {
+        crate::seeded_document_id(doc, self.hash_seed)
+    }
+
+    /// Returns the key a line is stored under for a doc and org.
+    pub fn node_id(content: &str, doc: &str, org: &str) -> u64 {
+        SeedableState::fixed().hash_one(format!("{content}_{doc}_{org}"))
+    }
+
+    /// Inserts content using the default doc and org set by `with_defaults`.
+    pub async fn insert(&self, content_vec: &[&str]) -> Result<BatchInsertStats, HybridError> {
+        let (Some(doc), Some(org)) = (&self.default_doc, &self.default_org) else {
+            return Err(HybridError::MissingDefaults);
+        };
+        self.unique_insert(content_vec, doc, org).await
+    }
+
+    /// Inserts a chain of lines for a doc and org, one node per line and an edge
+    /// between each pair of consecutive lines.
+    ///
+    /// Nodes and edges that are already stored, or repeated within the batch, are
+    /// written once. The index write locks are held from filtering through the
+    /// inserts, so concurrent calls with overlapping lines can't both find a row
+    /// missing and write it twice. Concurrent inserts therefore run one at a time.
+    ///
+    /// # Returns
+    /// How many node and edge rows were written
+    pub async fn unique_insert(
+        &self,
+        content_vec: &[&str],
+        doc: &str,
+        org: &str,
+    ) -> Result<BatchInsertStats, HybridError> {
+        // Compute all hashes upfront
+        let hash_vec: Vec<u64> = content_vec
+            .iter()
+            .map(|content| Self::node_id(content, doc, org))
+            .collect();
+
+        // Always lock nodes before edges, so concurrent calls can't deadlock
+        let mut node_idx = self.node_index.write().await;
+        let mut edge_idx = self.edge_index.write().await;
+
+        // Filter new nodes
+        let mut seen = BTreeSet::new();
+        let new_nodes: Vec<(&str, u64)> = content_vec
+            .iter()
+            .zip(hash_vec.iter())
+            .filter(|(_, hash)| !node_idx.contains(*hash) && seen.insert(**hash))
+            .map(|(content, hash)| (*content, *hash))
+            .collect();
+
+        // Filter new edges
+        let mut seen = BTreeSet::new();
+        let new_edges: Vec<(u64, u64)> = hash_vec
+            .windows(2)
+            .map(|w| (w[0], w[1]))
+            .filter(|edge| !edge_idx.contains(edge) && seen.insert(*edge))
+            .collect();
+
+        // Batch insert new nodes
+        if !new_nodes.is_empty() {
+            self.batch_insert_nodes(&new_nodes, doc, org).await?;
+
+            // Update node index
+            node_idx.extend(new_nodes.iter().map(|(_, hash)| *hash));
+        }
+
+        // Batch insert new edges
+        if !new_edges.is_empty() {
+            self.batch_insert_edges(&new_edges).await?;
+
+            // Update edge index
+            edge_idx.extend(new_edges.iter().copied());
+        }
+
+        Ok(BatchInsertStats {
+            nodes_added: new_nodes.len(),
+            edges_added: new_edges.len(),
+        })
+    }
+
+    /// Names the current state of a doc, so it can be read back later with
+    /// `trace_at_checkpoint`. Setting a name again moves it to the current state.
+    ///
+    /// The checkpoint records the sequence number the next edge will get, so every
+    /// edge inserted before it counts and none inserted after, however close in time.
+    pub async fn checkpoint(&self, name: &str, doc: &str, org: &str) -> Result<(), HybridError> {
+        // Inserts hold the edge index lock, so no batch is half-written while reading
+        let edge_idx = self.edge_index.read().await;
+        let seq = self.edge_seq.load(Ordering::SeqCst);
+        drop(edge_idx);
+        let query = format!(
+            "INSERT INTO kb.checkpoints VALUES ('{}', '{}', '{}', {}, {seq})",
+            sql_quote(name),
+            sql_quote(doc),
+            sql_quote(org),
+            self.now_sql(),
+        );
+        self.ctx.sql(&query).await?.collect().await?;
+        Ok(())
+    }
+
+    /// Follows the newest edge out of each node from the line `origin`, returning
+    /// the contents along the path ordered by depth, `origin` first.
+    ///
+    /// Stops before revisiting a node, and after `MAX_TRACE_DEPTH` edges.
+    pub async fn trace_latest(
+        &self,
+        origin: &str,
+        doc: &str,
+        org: &str,
+    ) -> Result<Vec<String>, HybridError> {
+        self.trace(origin, doc, org, None).await
+    }
+
+    /// Reads a doc as it was at a named checkpoint: like `trace_latest`, but only
+    /// following edges inserted before the checkpoint was set.
+    ///
+    /// # Returns
+    /// The contents along the path, or `None` if the checkpoint doesn't exist
+    pub async fn trace_at_checkpoint(
+        &self,
+        origin: &str,
+        doc: &str,
+        org: &str,
+        name: &str,
+    ) -> Result<Option<Vec<String>>, HybridError> {
+        let Some(seq) = self.checkpoint_seq(name, doc, org).await? else {
+            return Ok(None);
+        };
+        Ok(Some(self.trace(origin, doc, org, Some(seq)).await?))
+    }
+
+    /// Finds every node reachable from the line `origin` by following edges in
+    /// `direction`, up to `max_depth` edges away, including `origin` itself at depth 0.
+    ///
+    /// Every path is expanded, not just the newest edge out of each node, so
+    /// `max_depth` also bounds the work on graphs with many branches or cycles.
+    ///
+    /// # Returns
+    /// Each node once at its smallest depth, ordered by depth and then content
+    pub async fn trace_reachable(
+        &self,
+        origin: &str,
+        doc: &str,
+        org: &str,
+        direction: TraceDirection,
+        max_depth: usize,
+    ) -> Result<Vec<TracedNode>, HybridError> {
+        let o_node = Self::node_id(origin, doc, org);
+        let (from_column, to_column) = direction.columns();
+        let query = format!(
+            r#"
+            WITH RECURSIVE reached(node_id, depth) AS (
+                SELECT '{o_node}' as node_id, 0 as depth
+                UNION ALL
+                SELECT kb.edges.{to_column} as node_id, reached.depth + 1 as depth
+                FROM reached
+                INNER JOIN kb.edges ON reached.node_id = kb.edges.{from_column}
+                WHERE reached.depth < {max_depth}
+            )
+            SELECT kb.nodes.content, MIN(reached.depth) as depth FROM reached
+            INNER JOIN kb.nodes ON reached.node_id = kb.nodes.id
+            GROUP BY kb.nodes.id, kb.nodes.content
+            ORDER BY depth, kb.nodes.content
+        "#
+        );
+
+        let mut nodes = Vec::new();
+        for batch in self.ctx.sql(&query).await?.collect().await? {
+            for row in 0..batch.num_rows() {
+                let content = array_value_to_string(batch.column(0), row)
+                    .map_err(DataFusionError::from)?;
+                let depth = array_value_to_string(batch.column(1), row)
+                    .map_err(DataFusionError::from)?;
+                nodes.push(TracedNode {
+                    content,
+                    depth: depth.parse().unwrap_or_default(),
+                });
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Returns the edge sequence number a checkpoint was last set at.
+    async fn checkpoint_seq(
+        &self,
+        name: &str,
+        doc: &str,
+        org: &str,
+    ) -> Result<Option<u64>, HybridError> {
+        let query = format!(
+            "SELECT MAX(seq) FROM kb.checkpoints WHERE name = '{}' AND doc = '{}' AND org = '{}'",
+            sql_quote(name),
+            sql_quote(doc),
+            sql_quote(org),
+        );
+        let batches = self.ctx.sql(&query).await?.collect().await?;
+        let Some(column) = batches.first().map(|batch| batch.column(0)) else {
+            return Ok(None);
+        };
+        if column.is_empty() || column.is_null(0) {
+            return Ok(None);
+        }
+        let seq = array_value_to_string(column, 0).map_err(DataFusionError::from)?;
+        Ok(seq.parse().ok())
+    }
+
+    /// Follows the newest edge out of each node, ignoring edges numbered `until` or
+    /// later.
+    async fn trace(
+        &self,
+        origin: &str,
+        doc: &str,
+        org: &str,
+        until: Option<u64>,
+    ) -> Result<Vec<String>, HybridError> {
+        let o_node = Self::node_id(origin, doc, org);
+        let seq_filter = match until {
+            Some(seq) => format!("WHERE seq < {seq}"),
+            None => String::new(),
+        };
+        let query = format!(
+            r#"
+            WITH RECURSIVE nodes(node_1, depth) AS (
+                SELECT '{o_node}' as node_1, 0 as depth
+                UNION ALL
+                SELECT subq.d_id as node_1, nodes.depth + 1 as depth
+                FROM nodes
+                INNER JOIN (
+                    SELECT o_id, d_id,
+                        ROW_NUMBER() OVER(PARTITION BY o_id ORDER BY seq DESC) as row_num
+                    FROM kb.edges
+                    {seq_filter}
+                ) subq ON nodes.node_1 = subq.o_id
+                WHERE subq.row_num = 1 AND nodes.depth < {MAX_TRACE_DEPTH}
+            )
+            SELECT nodes.node_1, kb.nodes.content FROM nodes
+            LEFT JOIN kb.nodes ON nodes.node_1 = kb.nodes.id
+            ORDER BY depth
+        "#
+        );
+
+        let mut visited = BTreeSet::new();
+        let mut contents = Vec::new();
+        'batches: for batch in self.ctx.sql(&query).await?.collect().await? {
+            for row in 0..batch.num_rows() {
+                let node_id =
+                    array_value_to_string(batch.column(0), row).map_err(DataFusionError::from)?;
+                // A cycle repeats the path until the depth limit, so cut it at the first repeat
+                if !visited.insert(node_id) {
+                    break 'batches;
+                }
+                let content =
+                    array_value_to_string(batch.column(1), row).map_err(DataFusionError::from)?;
+                contents.push(content);
+            }
+        }
+        Ok(contents)
+    }
+
+    async fn batch_insert_nodes(
+        &self,
+        nodes: &[(&str, u64)],
+        doc: &str,
+        org: &str,
+    ) -> Result<(), HybridError> {
+        let (doc, org) = (sql_quote(doc), sql_quote(org));
+        let now = self.now_sql();
+        let insert_elements: String = nodes
+            .iter()
+            .map(|(content, hash)| {
+                format!("('{hash}','{}','{doc}','{org}', {now})", sql_quote(content))
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+
+        self.insert_with_retry("kb.nodes", "id, content, doc, org, time", &insert_elements)
+            .await
+    }
+
+    async fn batch_insert_edges(&self, edges: &[(u64, u64)]) -> Result<(), HybridError> {
+        // Numbered in batch order, so a later edge out of the same node wins
+        let first_seq = self.edge_seq.fetch_add(edges.len() as u64, Ordering::SeqCst);
+        let now = self.now_sql();
+        let insert_elements: String = edges
+            .iter()
+            .zip(first_seq..)
+            .map(|((o, d), seq)| format!("('{o}_{d}', '{o}', '{d}', {now}, {seq})"))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        self.insert_with_retry("kb.edges", "id, o_id, d_id, time, seq", &insert_elements)
+            .await
+    }
+
+    /// The clock's current time as a SQL timestamp, for the `time` column of a row.
+    fn now_sql(&self) -> String {
+        format!("to_timestamp_millis({})", self.clock.unix_millis())
+    }
+
+    /// Inserts rows given as a SQL `VALUES` list, retrying transient failures with
+    /// exponential backoff as set by the retry policy. Each failed attempt is logged.
+    ///
+    /// A multi-row insert isn't atomic: an attempt can fail after some of its rows
+    /// were written. Retries therefore only write the rows whose `id` isn't stored
+    /// yet, so a retried batch still ends up with every row exactly once.
+    async fn insert_with_retry(
+        &self,
+        table: &'static str,
+        columns: &str,
+        values: &str,
+    ) -> Result<(), HybridError> {
+        let policy = self.retry_policy;
+        let mut backoff = policy.initial_backoff;
+        let mut attempts = 1;
+        loop {
+            let query = if attempts == 1 {
+                format!("INSERT INTO {table} VALUES {values}")
+            } else {
+                format!(
+                    "INSERT INTO {table} SELECT * FROM (VALUES {values}) AS batch({columns}) \
+                     WHERE batch.id NOT IN (SELECT id FROM {table})"
+                )
+            };
+            let result = match self.ctx.sql(&query).await {
+                Ok(dataframe) => dataframe.collect().await.map(|_| ()),
+                Err(err) => Err(err),
+            };
+            let err = match result {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            if attempts >= policy.max_attempts || !is_transient(&err) {
+                tracing::warn!(
+                    "Insert into {} failed on attempt {}, giving up: {}",
+                    table,
+                    attempts,
+                    err
+                );
+                return Err(HybridError::InsertFailed {
+                    table,
+                    attempts,
+                    source: err,
+                });
+            }
+            tracing::warn!(
+                "Insert into {} failed on attempt {} of {}, retrying in {:?}: {}",
+                table,
+                attempts,
+                policy.max_attempts,
+                backoff,
+                err
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(policy.max_backoff);
+            attempts += 1;
+        }
+    }
+}
+
+/// Whether an error may go away on retry, as opposed to e.g. a planning error.
+fn is_transient(err: &DataFusionError) -> bool {
+    matches!(
+        err.find_root(),
+        DataFusionError::IoError(_)
+            | DataFusionError::ResourcesExhausted(_)
+            | DataFusionError::External(_)
+    )
+}
+
+/// Escapes a value for a single-quoted SQL string literal.
+fn sql_quote(value: &str) -> String {
+    value.replace('\'', "''")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::SchemaRef;
+    use datafusion::catalog::{Session, TableProvider};
+    use datafusion::logical_expr::dml::InsertOp;
+    use datafusion::logical_expr::{Expr, TableType};
+    use datafusion::physical_plan::{ExecutionPlan, collect};
+    use crate::FixedClock;
+    use std::any::Any;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Wraps a table so its next `failures` inserts write their rows and then fail
+    /// with an I/O error, like a write that is cut off before it is acknowledged.
+    #[derive(Debug)]
+    struct FlakyTable {
+        inner: Arc<dyn TableProvider>,
+        failures: AtomicU32,
+    }
+
+    #[async_trait::async_trait]
+    impl TableProvider for FlakyTable {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn schema(&self) -> SchemaRef {
+            self.inner.schema()
+        }
+
+        fn table_type(&self) -> TableType {
+            self.inner.table_type()
+        }
+
+        async fn scan(
+            &self,
+            state: &dyn Session,
+            projection: Option<&Vec<usize>>,
+            filters: &[Expr],
+            limit: Option<usize>,
+        ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+            self.inner.scan(state, projection, filters, limit).await
+        }
+
+        async fn insert_into(
+            &self,
+            state: &dyn Session,
+            input: Arc<dyn ExecutionPlan>,
+            insert_op: InsertOp,
+        ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+            let plan = self.inner.insert_into(state, input, insert_op).await?;
+            let fail = self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1))
+                .is_ok();
+            if !fail {
+                return Ok(plan);
+            }
+            collect(plan, state.task_ctx()).await?;
+            Err(DataFusionError::IoError(std::io::Error::other("connection reset")))
+        }
+    }
+
+    /// Makes the next `failures` inserts into `table` fail after writing their rows.
+    async fn make_flaky(kb: &DataFusionKnowledgeBase, table: &str, failures: u32) {
+        let inner = kb.context().table_provider(table).await.unwrap();
+        kb.context().deregister_table(table).unwrap();
+        let flaky = FlakyTable {
+            inner,
+            failures: AtomicU32::new(failures),
+        };
+        kb.context().register_table(table, Arc::new(flaky)).unwrap();
+    }
+
+    fn quick_retries(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }
+    }
+
+    async fn count_rows(kb: &DataFusionKnowledgeBase, table: &str) -> usize {
+        let query = format!("SELECT COUNT(*) FROM kb.{table}");
+        let batches = kb.context().sql(&query).await.unwrap().collect().await.unwrap();
+        array_value_to_string(batches[0].column(0), 0).unwrap().parse().unwrap()
+    }
+
+    async fn count_stamped(kb: &DataFusionKnowledgeBase, table: &str, millis: u64) -> usize {
+        let query =
+            format!("SELECT COUNT(*) FROM kb.{table} WHERE time = to_timestamp_millis({millis})");
+        let batches = kb.context().sql(&query).await.unwrap().collect().await.unwrap();
+        array_value_to_string(batches[0].column(0), 0).unwrap().parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn overlapping_inserts_write_each_row_once() {
+        let kb = DataFusionKnowledgeBase::new().await.unwrap().with_defaults("doc", "org");
+
+        let stats = kb.insert(&["intro", "body"]).await.unwrap();
+        assert_eq!(stats, BatchInsertStats { nodes_added: 2, edges_added: 1 });
+
+        // "body" is already stored, only "outro" and the edge into it are new
+        let stats = kb.insert(&["body", "outro"]).await.unwrap();
+        assert_eq!(stats, BatchInsertStats { nodes_added: 1, edges_added: 1 });
+
+        let stats = kb.insert(&["intro", "body", "outro"]).await.unwrap();
+        assert_eq!(stats, BatchInsertStats { nodes_added: 0, edges_added: 0 });
+
+        assert_eq!(count_rows(&kb, "nodes").await, 3);
+        assert_eq!(count_rows(&kb, "edges").await, 2);
+        assert_eq!(
+            kb.trace_latest("intro", "doc", "org").await.unwrap(),
+            vec!["intro", "body", "outro"]
+        );
+    }
+
+    #[tokio::test]
+    async fn repeats_within_a_batch_are_written_once() {
+        let kb = DataFusionKnowledgeBase::new().await.unwrap();
+
+        let stats = kb.unique_insert(&["p", "q", "p", "q"], "doc", "org").await.unwrap();
+        assert_eq!(stats, BatchInsertStats { nodes_added: 2, edges_added: 2 });
+        assert_eq!(count_rows(&kb, "nodes").await, 2);
+        assert_eq!(count_rows(&kb, "edges").await, 2);
+
+        // The same lines in another doc are different nodes
+        let stats = kb.unique_insert(&["p", "q"], "other", "org").await.unwrap();
+        assert_eq!(stats, BatchInsertStats { nodes_added: 2, edges_added: 1 });
+        assert_eq!(count_rows(&kb, "nodes").await, 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_overlapping_inserts_write_each_row_once() {
+        let kb = std::sync::Arc::new(DataFusionKnowledgeBase::new().await.unwrap());
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let kb = kb.clone();
+                tokio::spawn(async move {
+                    kb.unique_insert(&["intro", "body", "outro"], "doc", "org").await
+                })
+            })
+            .collect();
+        let mut totals = BatchInsertStats::default();
+        for task in tasks {
+            let stats = task.await.unwrap().unwrap();
+            totals.nodes_added += stats.nodes_added;
+            totals.edges_added += stats.edges_added;
+        }
+
+        assert_eq!(totals, BatchInsertStats { nodes_added: 3, edges_added: 2 });
+        assert_eq!(count_rows(&kb, "nodes").await, 3);
+        assert_eq!(count_rows(&kb, "edges").await, 2);
+    }
+
+    #[tokio::test]
+    async fn retried_insert_writes_each_row_once() {
+        let kb = DataFusionKnowledgeBase::new()
+            .await
+            .unwrap()
+            .with_defaults("doc", "org")
+            .with_retry_policy(quick_retries(3));
+        make_flaky(&kb, "kb.nodes", 1).await;
+        make_flaky(&kb, "kb.edges", 2).await;
+
+        let stats = kb.insert(&["intro", "body", "outro"]).await.unwrap();
+
+        assert_eq!(stats, BatchInsertStats { nodes_added: 3, edges_added: 2 });
+        assert_eq!(count_rows(&kb, "nodes").await, 3);
+        assert_eq!(count_rows(&kb, "edges").await, 2);
+        assert_eq!(
+            kb.trace_latest("intro", "doc", "org").await.unwrap(),
+            vec!["intro", "body", "outro"]
+        );
+    }
+
+    #[tokio::test]
+    async fn insert_gives_up_after_the_last_attempt() {
+        let kb = DataFusionKnowledgeBase::new()
+            .await
+            .unwrap()
+            .with_defaults("doc", "org")
+            .with_retry_policy(quick_retries(2));
+        make_flaky(&kb, "kb.nodes", 2).await;
+
+        let err = kb.insert(&["intro"]).await.unwrap_err();
+
+        assert!(matches!(
+            err,
+            HybridError::InsertFailed { table: "kb.nodes", attempts: 2, .. }
+        ));
+        // Both attempts wrote the row before failing, but the retry skipped it
+        assert_eq!(count_rows(&kb, "nodes").await, 1);
+    }
+
+    #[tokio::test]
+    async fn checkpoints_read_back_older_states() {
+        let kb = DataFusionKnowledgeBase::new().await.unwrap().with_defaults("doc", "org");
+
+        kb.insert(&["<ORIGIN>", "# Draft", "text"]).await.unwrap();
+        kb.checkpoint("draft", "doc", "org").await.unwrap();
+        // No pause: edges inserted in the same instant still order after the checkpoint
+        kb.insert(&["<ORIGIN>", "# Review", "text"]).await.unwrap();
+        kb.checkpoint("review", "doc", "org").await.unwrap();
+        kb.insert(&["<ORIGIN>", "# Final", "text", "more"]).await.unwrap();
+
+        for (name, heading) in [("draft", "# Draft"), ("review", "# Review")] {
+            let contents = kb.trace_at_checkpoint("<ORIGIN>", "doc", "org", name).await.unwrap();
+            let expected = vec!["<ORIGIN>".to_string(), heading.to_string(), "text".to_string()];
+            assert_eq!(contents, Some(expected));
+        }
+        let missing = kb.trace_at_checkpoint("<ORIGIN>", "doc", "org", "missing").await.unwrap();
+        assert_eq!(missing, None);
+        assert_eq!(
+            kb.trace_latest("<ORIGIN>", "doc", "org").await.unwrap(),
+            vec!["<ORIGIN>", "# Final", "text", "more"]
+        );
+    }
+
+    #[tokio::test]
+    async fn trace_stops_at_a_cycle() {
+        let kb = DataFusionKnowledgeBase::new().await.unwrap().with_defaults("doc", "org");
+
+        kb.insert(&["a", "b", "c", "b"]).await.unwrap();
+
+        assert_eq!(kb.trace_latest("a", "doc", "org").await.unwrap(), vec!["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn rows_are_stamped_by_the_clock() {
+        let clock = Arc::new(FixedClock::from_unix_millis(1_760_572_800_000));
+        let kb = DataFusionKnowledgeBase::new()
+            .await
+            .unwrap()
+            .with_defaults("doc", "org")
+            .with_clock(clock.clone());
+
+        kb.insert(&["a", "b"]).await.unwrap();
+        kb.checkpoint("first", "doc", "org").await.unwrap();
+        clock.advance(Duration::from_secs(1));
+        kb.insert(&["b", "c"]).await.unwrap();
+
+        assert_eq!(count_stamped(&kb, "nodes", 1_760_572_800_000).await, 2);
+        assert_eq!(count_stamped(&kb, "edges", 1_760_572_800_000).await, 1);
+        assert_eq!(count_stamped(&kb, "checkpoints", 1_760_572_800_000).await, 1);
+        assert_eq!(count_stamped(&kb, "nodes", 1_760_572_801_000).await, 1);
+        assert_eq!(count_stamped(&kb, "edges", 1_760_572_801_000).await, 1);
+    }
+
+    #[tokio::test]
+    async fn insert_without_defaults_fails() {
+        let kb = DataFusionKnowledgeBase::new().await.unwrap();
+        assert!(matches!(kb.insert(&["a"]).await, Err(HybridError::MissingDefaults)));
+        assert_eq!(count_rows(&kb, "nodes").await, 0);
+    }
+}
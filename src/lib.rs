@@ -0,0 +1,5229 @@
+//! Core graph data structures for LiasionDB: the knowledge base CRDT, its nodes
+//! and edges, and the ledger used to track reads. The HTTP server lives in `main.rs`.
+//!
+//! Serializable types emit their fields in declaration order, and collections are
+//! serialized as arrays in a fixed order, so output is stable for snapshot tests.
+//! Edge tables should be exposed as arrays sorted by `(from, to)` key, never as maps.
+
+pub mod chunk;
+pub mod clock;
+pub mod hybrid;
+pub mod store;
+pub mod wal;
+
+pub use chunk::{
+    BudgetChunker, Chunker, LineChunker, ParagraphChunker, SentenceChunker, chunker_by_name,
+};
+pub use clock::{Clock, FixedClock, SystemClock};
+pub use hybrid::{
+    BatchInsertStats, DataFusionKnowledgeBase, HybridError, MAX_TRACE_DEPTH, RetryPolicy,
+    TraceDirection, TracedNode,
+};
+pub use store::KnowledgeStore;
+#[cfg(feature = "persistent")]
+pub use store::{SledStore, SledStoreError};
+pub use wal::{WalRecovery, WriteAheadLog};
+
+use indexmap::IndexSet;
+use rapidhash::fast::SeedableState;
+use serde::{Deserialize, Serialize};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+
+/// Represents a content node in the knowledge graph.
+/// Nodes are uniquely identified by their kind, content, and source filename. Under
+/// `NodeIdentity::ContentOnly`, content nodes are stored with an empty filename;
+/// under `NodeIdentity::ContentFileAndPosition`, they also carry their position.
+///
+/// The kind keeps marker nodes apart from content that happens to look like a
+/// marker: a reference node reading `DIR: src` is not the `src` directory node.
+#[derive(Debug, Hash, Eq, Clone, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+pub struct Node {
+    pub content: String,
+    pub filename: String,
+    /// 0-based position of the chunk in its file, if part of the node's identity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+    /// `Directory`, `File`, or `Tombstone` for marker nodes, `Content` otherwise
+    #[serde(default, skip_serializing_if = "NodeKind::is_content")]
+    pub kind: NodeKind,
+}
+
+impl Node {
+    /// Creates a content (or reference) node.
+    pub fn new(content: String, filename: String) -> Self {
+        Self {
+            content,
+            filename,
+            position: None,
+            kind: NodeKind::Content,
+        }
+    }
+
+    /// Creates a content node keyed on its position as well, see
+    /// `NodeIdentity::ContentFileAndPosition`.
+    pub fn at_position(content: String, filename: String, position: usize) -> Self {
+        Self {
+            position: Some(position),
+            ..Self::new(content, filename)
+        }
+    }
+
+    /// Creates the DIR marker node of a directory.
+    pub fn directory(directory_path: &str) -> Self {
+        Self::marker(NodeKind::Directory, format!("DIR: {}", directory_path), "")
+    }
+
+    /// Creates the FILE marker node heading a file's chain.
+    pub fn file(filename: &str) -> Self {
+        Self::marker(NodeKind::File, format!("FILE: {}", filename), filename)
+    }
+
+    /// Creates the TOMBSTONE marker node of a deleted file.
+    pub fn tombstone(filename: &str) -> Self {
+        Self::marker(NodeKind::Tombstone, format!("TOMBSTONE: {}", filename), filename)
+    }
+
+    fn marker(kind: NodeKind, content: String, filename: &str) -> Self {
+        Self {
+            kind,
+            ..Self::new(content, filename.to_string())
+        }
+    }
+
+    /// Returns true for DIR marker nodes.
+    pub fn is_directory(&self) -> bool {
+        self.kind == NodeKind::Directory
+    }
+
+    /// Returns true for FILE marker nodes.
+    pub fn is_file(&self) -> bool {
+        self.kind == NodeKind::File
+    }
+
+    /// Returns true for synthetic DIR/FILE/TOMBSTONE marker nodes rather than content.
+    pub fn is_marker(&self) -> bool {
+        !self.kind.is_content()
+    }
+
+    /// Classifies the node by its stored kind. Content without a filename is
+    /// classified as `Reference`; see `KnowledgeBase::node_kind` for a
+    /// classification that accounts for `NodeIdentity::ContentOnly`.
+    pub fn node_kind(&self) -> NodeKind {
+        match self.kind {
+            NodeKind::Content if self.filename.is_empty() => NodeKind::Reference,
+            kind => kind,
+        }
+    }
+
+    /// Returns the content without a DIR/FILE/TOMBSTONE marker prefix, e.g. the
+    /// path of a FILE node. Content nodes are returned as stored.
+    pub fn display_content(&self) -> &str {
+        let prefix = match self.kind {
+            NodeKind::Directory => "DIR: ",
+            NodeKind::File => "FILE: ",
+            NodeKind::Tombstone => "TOMBSTONE: ",
+            NodeKind::Content | NodeKind::Reference => "",
+        };
+        self.content.strip_prefix(prefix).unwrap_or(&self.content)
+    }
+
+    /// Returns the path of a FILE marker node.
+    pub fn file_path(&self) -> Option<&str> {
+        self.is_file().then(|| self.display_content())
+    }
+
+    /// Returns the node's content address: a hash of its kind, content, filename,
+    /// and position as 16 hex digits. Unlike its index, it still finds the node
+    /// after the knowledge base is cleared and the node is ingested again.
+    pub fn content_id(&self) -> String {
+        format!("{:016x}", SeedableState::fixed().hash_one(self))
+    }
+}
+
+/// What a node represents, as stored in `Node::kind` and classified by
+/// `Node::node_kind`.
+#[derive(
+    Debug, Clone, Copy, Default, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeKind {
+    /// DIR marker of a directory
+    Directory,
+    /// FILE marker heading a file's chain
+    File,
+    /// TOMBSTONE marker of a deleted file
+    Tombstone,
+    /// A line of a file
+    #[default]
+    Content,
+    /// Content not keyed to any file
+    Reference,
+}
+
+impl NodeKind {
+    /// Returns true for content and reference nodes, i.e. anything but a marker.
+    pub fn is_content(&self) -> bool {
+        matches!(self, NodeKind::Content | NodeKind::Reference)
+    }
+}
+
+/// Represents a Structural directed edge between two nodes in the knowledge graph.
+/// Edges track the version/timestamp when they were created and can be tagged.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Edge {
+    /// Version number or timestamp for CRDT conflict resolution
+    pub version: i32,
+    /// Optional tag for categorizing edges
+    pub tag: String,
+    /// Confidence in the edge, used by `traverse_weighted`; LWW resolution ignores it
+    #[serde(default = "default_edge_weight")]
+    pub weight: f32,
+}
+
+impl Edge {
+    pub fn new(version: i32, tag: String) -> Self {
+        Self::weighted(version, tag, DEFAULT_EDGE_WEIGHT)
+    }
+
+    pub fn weighted(version: i32, tag: String, weight: f32) -> Self {
+        Self {
+            version,
+            tag,
+            weight,
+        }
+    }
+}
+
+/// Weight of edges created without an explicit weight
+pub const DEFAULT_EDGE_WEIGHT: f32 = 1.0;
+
+fn default_edge_weight() -> f32 {
+    DEFAULT_EDGE_WEIGHT
+}
+
+/// Scores an edge for `traverse_weighted` as its version scaled by its weight, so
+/// with default weights the traversal follows the latest path.
+pub fn weighted_score(edge: &Edge) -> f32 {
+    edge.version as f32 * edge.weight
+}
+
+/// Tag of the structural edge that marks a file as deleted
+pub const TOMBSTONE_TAG: &str = "tombstone";
+
+/// Outcome of deleting a file from the knowledge base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeleteOutcome {
+    /// The file was deleted; its tombstone edge has this version
+    Deleted { version: i32 },
+    /// The file exists in the graph but was already deleted
+    AlreadyDeleted,
+    /// No file with this name was ever written
+    NotFound,
+}
+
+/// Identifies which edge table an edge belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeTable {
+    /// Sequential structural edges (`edge_table`)
+    Structural,
+    /// Provenance reference edges (`ref_table`)
+    Reference,
+}
+
+/// A conflict found while merging two knowledge bases.
+/// Both sides hold the same `(from, to)` edge with equal versions but different
+/// tags, so Last-Write-Wins cannot cleanly decide between them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MergeConflict {
+    /// Table the conflicting edge belongs to
+    pub table: EdgeTable,
+    /// Edge key (from, to) in terms of the merged-into knowledge base's indices
+    pub edge_key: (usize, usize),
+    /// Edge that was kept from the local knowledge base
+    pub local: Edge,
+    /// Conflicting edge from the other knowledge base
+    pub incoming: Edge,
+}
+
+/// Preview of what merging another knowledge base would do, from `merge_plan`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MergePlan {
+    /// Number of nodes that would be added
+    pub nodes_added: usize,
+    /// Number of structural edges that would be added
+    pub edges_added: usize,
+    /// Number of reference edges that would be added
+    pub references_added: usize,
+    /// Conflicts the merge would report
+    pub conflicts: Vec<MergeConflict>,
+    /// Files whose latest path would change, including files that would appear
+    pub changed_files: Vec<String>,
+}
+
+/// How to handle a line that exceeds the per-node content length cap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OversizePolicy {
+    /// Reject the whole ingest
+    #[default]
+    Reject,
+    /// Split the line into fixed-size chunks chained as separate nodes
+    Split,
+}
+
+/// How content nodes are keyed in the node table.
+///
+/// This is a fundamental model choice and is fixed when the knowledge base is created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeIdentity {
+    /// Nodes are keyed on content and filename, so identical lines in different
+    /// files are distinct nodes. Every file has its own chain of nodes.
+    #[default]
+    ContentAndFile,
+    /// Content nodes are keyed on content alone, so identical lines are shared
+    /// across files and common boilerplate is stored once. A node no longer names
+    /// its file; which files contain it is only recorded by the structural edges
+    /// leading to it. Every file also keeps its own copy of the edges of its chain,
+    /// so reading a file never continues into another file's continuation of a
+    /// shared line.
+    ContentOnly,
+    /// Content nodes are keyed on content, filename, and position in the file, so
+    /// repeated lines within a file (e.g. `---` separators) stay distinct and the
+    /// chain never loops back on itself. Inserting a line shifts the position of
+    /// every line after it, so those lines become new nodes on the next write.
+    ContentFileAndPosition,
+}
+
+/// What to do when an ingest creates an edge that already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EdgeInsertPolicy {
+    /// Keep the first recorded edge and ignore later versions of it
+    PreserveFirst,
+    /// Replace the stored edge when the new one has a higher version (LWW)
+    #[default]
+    LatestWins,
+}
+
+/// Options controlling how content is turned into nodes on ingest.
+#[derive(Debug, Clone, Default)]
+pub struct IngestOptions {
+    /// Maximum content length of a single node in bytes (`None` = unlimited)
+    pub max_node_len: Option<usize>,
+    /// What to do with lines longer than `max_node_len`
+    pub oversize_policy: OversizePolicy,
+    /// What to do with edges that already exist
+    pub edge_insert_policy: EdgeInsertPolicy,
+    /// Normalization applied to each line before it becomes a node, e.g. to
+    /// canonicalize content for better dedup. The original line is discarded.
+    pub transform: Option<fn(&str) -> String>,
+    /// Weight of the structural edges created (`None` = `DEFAULT_EDGE_WEIGHT`)
+    pub edge_weight: Option<f32>,
+    /// How content is split into nodes (`None` = `LineChunker`)
+    pub chunker: Option<Arc<dyn Chunker>>,
+    /// Out-degree above which a node on a written chain counts towards
+    /// `IngestStats::high_fan_out_nodes` (`None` = no check)
+    pub max_fan_out: Option<usize>,
+    /// Most outgoing structural edges kept per node, or per node and file under
+    /// `NodeIdentity::ContentOnly`. Ingest evicts the lowest-version edges of the
+    /// written chain's nodes beyond this (`None` = unlimited)
+    pub max_fork_width: Option<usize>,
+}
+
+impl IngestOptions {
+    /// Builds the structural edge that ingest creates with these options.
+    fn structural_edge(&self, version: i32, tag: &str) -> Edge {
+        Edge::weighted(
+            version,
+            tag.to_string(),
+            self.edge_weight.unwrap_or(DEFAULT_EDGE_WEIGHT),
+        )
+    }
+}
+
+/// Errors that can occur while ingesting content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IngestError {
+    /// A line exceeded the per-node length cap under `OversizePolicy::Reject`. With
+    /// another chunker than `LineChunker`, `line` is the 1-based chunk number.
+    NodeTooLarge { line: usize, len: usize, max_len: usize },
+    /// An explicit version was negative
+    NegativeVersion { version: i32 },
+    /// An explicit version did not exceed the file's current highest version
+    StaleVersion { version: i32, max_version: i32 },
+}
+
+impl std::fmt::Display for IngestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IngestError::NodeTooLarge { line, len, max_len } => write!(
+                f,
+                "line {} is {} bytes, exceeding the node limit of {} bytes",
+                line, len, max_len
+            ),
+            IngestError::NegativeVersion { version } => {
+                write!(f, "version {} is negative", version)
+            }
+            IngestError::StaleVersion {
+                version,
+                max_version,
+            } => write!(
+                f,
+                "version {} does not exceed the file's current version {}",
+                version, max_version
+            ),
+        }
+    }
+}
+
+impl std::error::Error for IngestError {}
+
+/// Errors that can occur while renaming a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameError {
+    /// No live file with the old name exists
+    NotFound,
+    /// A live file with the new name already exists
+    AlreadyExists,
+}
+
+impl std::fmt::Display for RenameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenameError::NotFound => write!(f, "file to rename does not exist"),
+            RenameError::AlreadyExists => write!(f, "a file with the new name already exists"),
+        }
+    }
+}
+
+impl std::error::Error for RenameError {}
+
+/// One operation of an atomic transaction, see `apply_transaction`.
+#[derive(Debug, Clone)]
+pub enum TransactionOp {
+    /// Write a file, like `write_file`
+    Write {
+        path: String,
+        content: String,
+        reference_nodes: Vec<Node>,
+    },
+    /// Delete a file, like `delete_file`
+    Delete { path: String },
+    /// Record that node `from` influenced node `to`
+    Reference { from: usize, to: usize },
+}
+
+/// Result of one operation of a committed transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TransactionOpResult {
+    Write { path: String, stats: IngestStats },
+    Delete { path: String },
+    /// `added` is false if the reference edge already existed
+    Reference { from: usize, to: usize, added: bool },
+}
+
+/// Why a transaction operation failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionOpError {
+    /// The written content was rejected
+    Ingest(IngestError),
+    /// The file to delete was never written
+    FileNotFound,
+    /// The file to delete was already deleted
+    AlreadyDeleted,
+    /// A reference names a node index that is out of range
+    NodeNotFound,
+    /// The file was already written or deleted earlier in the transaction
+    DuplicatePath,
+}
+
+/// A failed transaction: the first failing operation and why it failed.
+/// Nothing from the transaction was applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionError {
+    /// Position of the failing operation in the transaction
+    pub op_index: usize,
+    pub error: TransactionOpError,
+}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "operation {} failed: ", self.op_index)?;
+        match &self.error {
+            TransactionOpError::Ingest(err) => write!(f, "{}", err),
+            TransactionOpError::FileNotFound => write!(f, "file not found"),
+            TransactionOpError::AlreadyDeleted => write!(f, "file already deleted"),
+            TransactionOpError::NodeNotFound => write!(f, "node index out of range"),
+            TransactionOpError::DuplicatePath => {
+                write!(f, "file is touched more than once in the transaction")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+/// A reference edge to assert with `KnowledgeBase::assert_references`: the node
+/// with content `from` influenced node `to`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BulkReference {
+    pub from: String,
+    pub to: usize,
+}
+
+/// What `KnowledgeBase::assert_references` changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BulkReferenceStats {
+    /// Version assigned to the created edges
+    pub version: i32,
+    /// Number of new reference edges
+    pub created: usize,
+    /// Number of references whose edge already existed
+    pub skipped: usize,
+    /// Number of reference nodes created for `from` contents not in the graph
+    pub nodes_added: usize,
+}
+
+/// A bulk reference whose `to` index is out of range. Nothing was applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BulkReferenceError {
+    /// Position of the offending reference in the request
+    pub index: usize,
+    pub to: usize,
+}
+
+impl std::fmt::Display for BulkReferenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "reference {}: node index {} out of range", self.index, self.to)
+    }
+}
+
+impl std::error::Error for BulkReferenceError {}
+
+/// Everything influenced by a set of source nodes, as computed by
+/// `KnowledgeBase::impact_report`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImpactReport {
+    /// The source nodes the report starts from
+    pub sources: Vec<usize>,
+    /// Number of nodes influenced by the sources, not counting the sources themselves
+    pub affected_nodes: usize,
+    /// Affected nodes per file, sorted by filename
+    pub files: Vec<FileImpact>,
+    /// Most reference hops from the nearest source to an affected node (0 if none)
+    pub max_depth: usize,
+}
+
+/// Number of nodes in one file affected by a source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileImpact {
+    pub filename: String,
+    pub affected_nodes: usize,
+}
+
+/// A node that influenced a file, as listed by `KnowledgeBase::file_references`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileReference {
+    pub node_idx: usize,
+    /// 1-based numbers of the lines it influenced in the file's latest content
+    pub lines: Vec<usize>,
+}
+
+/// Content split into lines and checked against `IngestOptions`, ready to be written
+/// with `KnowledgeBase::write_prepared`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreparedContent {
+    lines: Vec<String>,
+    /// Positions of the lines that are chunks of a split oversized line
+    split_chunks: BTreeSet<usize>,
+}
+
+impl PreparedContent {
+    /// The lines that will become content nodes, in order.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+}
+
+/// Statistics describing what a single ingest added to the knowledge base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct IngestStats {
+    /// Index of the file node
+    pub file_idx: usize,
+    /// Index of the directory node the file was linked under
+    pub parent_idx: usize,
+    /// Version assigned to the edges created by this ingest
+    pub version: i32,
+    /// Number of new nodes (including directory and file nodes)
+    pub nodes_added: usize,
+    /// Number of new structural edges
+    pub edges_added: usize,
+    /// Number of new reference edges
+    pub references_added: usize,
+    /// Number of nodes on the written chain whose structural out-degree exceeds
+    /// `IngestOptions::max_fan_out`, i.e. lines with too many divergent successors
+    pub high_fan_out_nodes: usize,
+    /// Number of structural edges evicted under `IngestOptions::max_fork_width`
+    pub edges_evicted: usize,
+}
+
+/// One page of structural edges, as returned by `KnowledgeBase::edges_in_range`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdgePage {
+    /// The edges, sorted by edge key
+    pub edges: Vec<((usize, usize), Edge)>,
+    /// Key of the edge the next page starts at, to pass to `edges_in_range_from`, if
+    /// the page stopped before the end of the range
+    pub next: Option<(usize, usize)>,
+}
+
+/// One line of a line-level diff, as computed by `diff_lines`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum DiffLine {
+    /// The line is in both sides
+    Common { line: String },
+    /// The line is only in the old side
+    Removed { line: String },
+    /// The line is only in the new side
+    Added { line: String },
+}
+
+/// Diffs two texts line by line with `diff_lines`. Lines are split on `\n`, and
+/// empty text has no lines.
+pub fn diff_text(old: &str, new: &str) -> Vec<DiffLine> {
+    let lines = |text: &str| -> Vec<&str> {
+        if text.is_empty() {
+            Vec::new()
+        } else {
+            text.split('\n').collect()
+        }
+    };
+    diff_lines(&lines(old), &lines(new))
+}
+
+/// Computes a minimal line-level diff turning `old` into `new`, using Myers' algorithm.
+///
+/// Runs in O((N + M) * D) time for N and M lines and D differing lines, so similar
+/// inputs are cheap even when long. Within a changed region, removed lines come
+/// before added ones.
+pub fn diff_lines(old: &[&str], new: &[&str]) -> Vec<DiffLine> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    let offset = max;
+    // Furthest x reached on each diagonal k = x - y, indexed by k + offset
+    let mut furthest = vec![0isize; 2 * max as usize + 2];
+    let mut trace = Vec::new();
+    let at = |furthest: &[isize], k: isize| furthest[(k + offset) as usize];
+    // Whether diagonal k is best reached by a step down (an added line) from k + 1
+    let steps_down = |furthest: &[isize], k: isize, d: isize| {
+        k == -d || (k != d && at(furthest, k - 1) < at(furthest, k + 1))
+    };
+
+    // Searches forward for the shortest edit script, keeping each round's state
+    'search: for d in 0..=max {
+        trace.push(furthest.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if steps_down(&furthest, k, d) {
+                at(&furthest, k + 1)
+            } else {
+                at(&furthest, k - 1) + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[(k + offset) as usize] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walks back from the end through the saved rounds to recover the script
+    let mut lines = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, furthest) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let down = steps_down(furthest, k, d);
+        let prev_k = if down { k + 1 } else { k - 1 };
+        let prev_x = at(furthest, prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            lines.push(DiffLine::Common {
+                line: old[x as usize - 1].to_string(),
+            });
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if down {
+                lines.push(DiffLine::Added {
+                    line: new[y as usize - 1].to_string(),
+                });
+            } else {
+                lines.push(DiffLine::Removed {
+                    line: old[x as usize - 1].to_string(),
+                });
+            }
+            x = prev_x;
+            y = prev_y;
+        }
+    }
+
+    lines.reverse();
+    lines
+}
+
+/// Returns the stable ID of a document: a file here, a `doc` in
+/// `DataFusionKnowledgeBase`. It is a fixed-seed rapidhash of the name, so both
+/// backends and every run agree on it.
+pub fn document_id(name: &str) -> u64 {
+    seeded_document_id(name, None)
+}
+
+/// Returns a document's stable ID like `document_id`, hashed with `seed` instead of
+/// the fixed seed if one is given. Deployments that share IDs must share the seed.
+pub fn seeded_document_id(name: &str, seed: Option<u64>) -> u64 {
+    match seed {
+        Some(seed) => SeedableState::new(seed).hash_one(name),
+        None => SeedableState::fixed().hash_one(name),
+    }
+}
+
+/// Replaces curly single and double quotes with straight ones. Meant as a dedup key,
+/// see `KnowledgeBase::set_dedup_key`.
+pub fn straighten_quotes(line: &str) -> String {
+    line.chars()
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' => '\'',
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' => '"',
+            c => c,
+        })
+        .collect()
+}
+
+/// Longest node label in `KnowledgeBase::file_to_dot` output, in characters
+pub const DOT_LABEL_MAX_CHARS: usize = 80;
+
+/// Replaces node content with placeholders in exports that shouldn't reveal text.
+///
+/// Equal content gets the same placeholder, numbered in order of first appearance,
+/// so repeated lines can still be recognized. Placeholders aren't derived from the
+/// content, so unlike a hash they can't be reversed by guessing short lines.
+///
+/// One redactor numbers content across everything it redacted, so sharing it across
+/// the files of an export, see `KnowledgeBase::read_file_redacted`, keeps the
+/// placeholders consistent between them.
+#[derive(Debug, Default)]
+pub struct Redactor {
+    placeholders: HashMap<String, usize>,
+}
+
+impl Redactor {
+    /// Returns the placeholder for `content`, `redacted-N`.
+    pub fn redact(&mut self, content: &str) -> String {
+        let next = self.placeholders.len() + 1;
+        let number = *self.placeholders.entry(content.to_string()).or_insert(next);
+        format!("redacted-{}", number)
+    }
+}
+
+/// Escapes text for a double-quoted DOT string.
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Splits an oversized line into chunks of at most `max_len` bytes on char boundaries.
+fn split_oversized(line: &str, max_len: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = line;
+
+    while rest.len() > max_len {
+        let mut end = max_len;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        // Always make progress, even if a single char exceeds the limit
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+
+    chunks.push(rest);
+    chunks
+}
+
+/// Provenance of a node: where it sits in its document and what influenced it.
+/// Reference ancestors are nested recursively, forming a tree.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProvenanceTree {
+    /// The node this provenance describes
+    pub node_idx: usize,
+    /// Structural predecessors, nearest first, following the latest incoming edge
+    /// back to the root (usually the directory node)
+    pub structural: Vec<usize>,
+    /// Nodes that directly influenced this node, each with its own provenance.
+    /// A node appears at most once in the whole tree.
+    pub references: Vec<ProvenanceTree>,
+}
+
+/// Upper bound on edges followed by `traverse_latest_path_with_meta`
+pub const MAX_TRAVERSAL_HOPS: usize = 1_000_000;
+
+/// Why a latest-path traversal stopped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TerminationReason {
+    /// The last node has no outgoing structural edges; the document is complete
+    #[default]
+    EndOfChain,
+    /// The next node was already on the path
+    Cycle,
+    /// The latest edge leads to a tombstone, so the file is deleted
+    Tombstone,
+    /// `MAX_TRAVERSAL_HOPS` edges were followed
+    HopLimit,
+}
+
+impl TerminationReason {
+    /// Returns the reason as a snake_case string, matching its serialized form.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TerminationReason::EndOfChain => "end_of_chain",
+            TerminationReason::Cycle => "cycle",
+            TerminationReason::Tombstone => "tombstone",
+            TerminationReason::HopLimit => "hop_limit",
+        }
+    }
+}
+
+/// Metadata describing a latest-path traversal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TraversalMeta {
+    /// Number of edges followed
+    pub hops: usize,
+    /// Whether the traversal stopped before the end of the chain, on a cycle or the hop limit
+    pub truncated: bool,
+    /// Why the traversal stopped
+    pub termination_reason: TerminationReason,
+    /// Largest number of outgoing structural edges seen at any node on the path
+    pub max_branch_factor: usize,
+}
+
+/// Ledger file that tracks which nodes have been read.
+/// This is a single .ledger file that accumulates node IDs as files are read.
+/// When writing, these nodes are used as references.
+///
+/// Nodes are recorded by `Node::content_id` rather than by index, so the ledger
+/// still refers to the same nodes if indices change between a read and a write.
+/// Ledgers from before content IDs, which held `node_indices`, read as empty.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Ledger {
+    /// Content IDs of the nodes that have been read
+    #[serde(default)]
+    pub node_ids: Vec<String>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self {
+            node_ids: Vec::new(),
+        }
+    }
+
+    pub fn add_nodes(&mut self, nodes: Vec<String>) {
+        self.node_ids.extend(nodes);
+        // Remove duplicates while preserving order
+        let mut seen = std::collections::HashSet::new();
+        self.node_ids.retain(|id| seen.insert(id.clone()));
+    }
+
+    /// Unions another ledger's node IDs into this one, preserving order and
+    /// dropping duplicates.
+    pub fn merge(&mut self, other: &Ledger) {
+        self.add_nodes(other.node_ids.clone());
+    }
+
+    /// Returns the last `max` node IDs, i.e. the most recently added ones, or all
+    /// of them if `max` is `None`. A node that is read again keeps the position of
+    /// its first read.
+    pub fn recent_nodes(&self, max: Option<usize>) -> &[String] {
+        let skip = max.map_or(0, |max| self.node_ids.len().saturating_sub(max));
+        &self.node_ids[skip..]
+    }
+}
+
+/// Version of the snapshot format written by `save_to_path`.
+///
+/// Bump this whenever the serialized form of `Node`, `Edge`, or the snapshot itself
+/// changes; `load_from_path` rejects snapshots with a different version.
+pub const SCHEMA_VERSION: u32 = 8;
+
+/// A single edge of an edge table, as stored in a snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EdgeRecord {
+    pub from: usize,
+    pub to: usize,
+    pub version: i32,
+    pub tag: String,
+    #[serde(default = "default_edge_weight")]
+    pub weight: f32,
+}
+
+impl EdgeRecord {
+    fn new((from, to): (usize, usize), edge: &Edge) -> Self {
+        Self {
+            from,
+            to,
+            version: edge.version,
+            tag: edge.tag.clone(),
+            weight: edge.weight,
+        }
+    }
+
+    fn into_edge(self) -> Edge {
+        Edge::weighted(self.version, self.tag, self.weight)
+    }
+}
+
+/// A single change to a knowledge base, as journaled for the write-ahead log.
+///
+/// Records describe table-level effects rather than operations, so replaying them
+/// does not depend on ingest options and reproduces node indices exactly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WalRecord {
+    /// A new node was appended at the next index
+    Node { node: Node },
+    /// An edge was inserted or overwritten
+    PutEdge { table: EdgeTable, edge: EdgeRecord },
+    /// An edge was removed
+    RemoveEdge { table: EdgeTable, from: usize, to: usize },
+    /// An embedding was stored for a node
+    Embedding { idx: usize, embedding: Vec<f32> },
+    /// A named checkpoint was set on a file
+    Checkpoint { filename: String, name: String, version: i32 },
+    /// A file node's read count reached `count`
+    ReadCount { idx: usize, count: u64 },
+    /// A file's own copy of an edge of its chain was stored, see `NodeIdentity::ContentOnly`
+    PutFileEdge { file_idx: usize, edge: EdgeRecord },
+    /// A file's own copy of an edge was removed
+    RemoveFileEdge { file_idx: usize, from: usize, to: usize },
+    /// Everything was cleared
+    Clear,
+}
+
+/// On-disk form of a knowledge base. Edge tables are stored as arrays sorted by key.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    schema_version: u32,
+    node_identity: NodeIdentity,
+    nodes: Vec<Node>,
+    edges: Vec<EdgeRecord>,
+    references: Vec<EdgeRecord>,
+    /// `(node index, embedding)` pairs sorted by node index
+    embeddings: Vec<(usize, Vec<f32>)>,
+    /// `(file node index, read count)` pairs sorted by node index, for read files only
+    read_counts: Vec<(usize, u64)>,
+    /// `(filename, checkpoint name, version)` triples sorted by filename and name
+    checkpoints: Vec<(String, String, i32)>,
+    /// `KnowledgeBase::version_floor`, which can exceed every stored edge's version
+    /// after edges were removed
+    #[serde(default)]
+    version_floor: i32,
+    /// `(file node index, edge)` pairs of `KnowledgeBase::file_edges`, sorted by key
+    file_edges: Vec<(usize, EdgeRecord)>,
+}
+
+impl Snapshot {
+    /// Checks the whole snapshot before anything is loaded from it: nodes are unique,
+    /// edges point at listed nodes and are listed once, versions are not negative,
+    /// weights are finite, and embeddings are valid and of one dimension. Read counts
+    /// are not checked, since counts for anything but a file node are dropped.
+    ///
+    /// # Returns
+    /// `SnapshotError::Invalid` for the first problem found, with its location
+    fn validate(&self) -> Result<(), SnapshotError> {
+        let invalid =
+            |location: String, reason: String| SnapshotError::Invalid { location, reason };
+        let node_count = self.nodes.len();
+
+        let mut first_index: HashMap<&Node, usize> = HashMap::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if let Some(first) = first_index.insert(node, idx) {
+                return Err(invalid(
+                    format!("nodes[{}]", idx),
+                    format!("duplicate of nodes[{}]", first),
+                ));
+            }
+        }
+
+        for (field, records) in [("edges", &self.edges), ("references", &self.references)] {
+            let mut seen = BTreeSet::new();
+            for (position, record) in records.iter().enumerate() {
+                let location = |member: &str| format!("{}[{}]{}", field, position, member);
+                for (member, idx) in [(".from", record.from), (".to", record.to)] {
+                    if idx >= node_count {
+                        return Err(invalid(
+                            location(member),
+                            format!("node {} is out of range ({} nodes)", idx, node_count),
+                        ));
+                    }
+                }
+                if record.version < 0 {
+                    return Err(invalid(
+                        location(".version"),
+                        format!("version {} is negative", record.version),
+                    ));
+                }
+                if !record.weight.is_finite() {
+                    return Err(invalid(location(".weight"), "weight is not finite".to_string()));
+                }
+                if !seen.insert((record.from, record.to)) {
+                    return Err(invalid(
+                        location(""),
+                        format!("edge ({}, {}) is listed twice", record.from, record.to),
+                    ));
+                }
+            }
+        }
+
+        let mut seen = BTreeSet::new();
+        for (position, (file_idx, record)) in self.file_edges.iter().enumerate() {
+            let location = |member: &str| format!("file_edges[{}]{}", position, member);
+            for (member, idx) in [(".0", *file_idx), (".1.from", record.from), (".1.to", record.to)]
+            {
+                if idx >= node_count {
+                    return Err(invalid(
+                        location(member),
+                        format!("node {} is out of range ({} nodes)", idx, node_count),
+                    ));
+                }
+            }
+            if record.version < 0 {
+                return Err(invalid(
+                    location(".1.version"),
+                    format!("version {} is negative", record.version),
+                ));
+            }
+            if !record.weight.is_finite() {
+                return Err(invalid(location(".1.weight"), "weight is not finite".to_string()));
+            }
+            if !seen.insert((*file_idx, record.from, record.to)) {
+                return Err(invalid(
+                    location(""),
+                    format!(
+                        "edge ({}, {}) of file {} is listed twice",
+                        record.from, record.to, file_idx
+                    ),
+                ));
+            }
+        }
+
+        let mut dimension = None;
+        for (position, (idx, embedding)) in self.embeddings.iter().enumerate() {
+            let location = format!("embeddings[{}]", position);
+            if *idx >= node_count {
+                return Err(invalid(
+                    location,
+                    format!("node {} is out of range ({} nodes)", idx, node_count),
+                ));
+            }
+            if embedding.is_empty() || !embedding.iter().all(|value| value.is_finite()) {
+                return Err(invalid(location, EmbeddingError::Invalid.to_string()));
+            }
+            let expected = *dimension.get_or_insert(embedding.len());
+            if embedding.len() != expected {
+                let err = EmbeddingError::DimensionMismatch {
+                    expected,
+                    found: embedding.len(),
+                };
+                return Err(invalid(location, err.to_string()));
+            }
+        }
+
+        for (position, (_, _, version)) in self.checkpoints.iter().enumerate() {
+            if *version < 0 {
+                return Err(invalid(
+                    format!("checkpoints[{}]", position),
+                    format!("version {} is negative", version),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of times a file was read. Incremented atomically, so reads can count
+/// themselves while only borrowing the knowledge base immutably.
+#[derive(Debug, Default)]
+struct ReadCount {
+    count: AtomicU64,
+    /// The count as of the last `WalRecord::ReadCount` taken from the journal
+    journaled: u64,
+}
+
+impl ReadCount {
+    /// Creates a count that is already persisted, e.g. loaded from a snapshot.
+    fn persisted(count: u64) -> Self {
+        Self {
+            count: AtomicU64::new(count),
+            journaled: count,
+        }
+    }
+}
+
+impl Clone for ReadCount {
+    fn clone(&self) -> Self {
+        Self {
+            count: AtomicU64::new(self.count.load(Ordering::Relaxed)),
+            journaled: self.journaled,
+        }
+    }
+}
+
+/// Errors that can occur while saving or loading a snapshot.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The snapshot file could not be read or written
+    Io(std::io::Error),
+    /// The snapshot could not be encoded or is not valid snapshot JSON
+    Json(serde_json::Error),
+    /// The snapshot was written with a different `SCHEMA_VERSION`
+    IncompatibleSchema { found: u32, expected: u32 },
+    /// The snapshot, or a write-ahead log record replayed over it, failed validation
+    /// before loading; `location` is the offending entry, e.g. `edges[3].to`
+    Invalid { location: String, reason: String },
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "snapshot I/O failed: {}", err),
+            SnapshotError::Json(err) => write!(f, "snapshot JSON is invalid: {}", err),
+            SnapshotError::IncompatibleSchema { found, expected } => write!(
+                f,
+                "snapshot has schema version {}, expected {}",
+                found, expected
+            ),
+            SnapshotError::Invalid { location, reason } => {
+                write!(f, "snapshot is invalid at {}: {}", location, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// Errors that can occur while storing an embedding.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmbeddingError {
+    /// The node index is out of range
+    NodeNotFound,
+    /// The embedding has no components or contains NaN/infinite values
+    Invalid,
+    /// The embedding's length differs from the embeddings already stored
+    DimensionMismatch { expected: usize, found: usize },
+}
+
+impl std::fmt::Display for EmbeddingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmbeddingError::NodeNotFound => write!(f, "node index out of range"),
+            EmbeddingError::Invalid => {
+                write!(f, "embedding must be non-empty and contain only finite values")
+            }
+            EmbeddingError::DimensionMismatch { expected, found } => write!(
+                f,
+                "embedding has {} dimensions, expected {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EmbeddingError {}
+
+/// A graph-based CRDT for tracking provenance and relationships in a knowledge base.
+///
+/// This structure maintains a directed graph where:
+/// - Nodes represent content chunks (e.g., paragraphs from markdown files)
+/// - Edges represent sequential relationships with version tracking
+///
+/// The CRDT uses Last-Write-Wins (LWW) semantics based on version numbers.
+#[derive(Debug, Clone)]
+pub struct KnowledgeBase {
+    /// Maps node index pairs (from, to) to edges
+    edge_table: BTreeMap<(usize, usize), Edge>,
+    /// Under `NodeIdentity::ContentOnly`, each file's own copy of the structural edges
+    /// between the content nodes of its chain, keyed `(file node, from, to)`. Content
+    /// nodes are shared across files there, so `edge_table` alone can't tell which
+    /// file an edge out of a shared line belongs to. Empty in the other modes.
+    file_edges: BTreeMap<(usize, usize, usize), Edge>,
+    /// Maps from reference nodes to nodes
+    ref_table: BTreeMap<(usize, usize), Edge>,
+    /// Reverse index of `ref_table` holding `(to, from)` for every reference edge,
+    /// so a node's incoming references can be found by range
+    ref_index: BTreeSet<(usize, usize)>,
+    /// Ordered set of unique nodes
+    node_table: IndexSet<Node>,
+    /// How content nodes are keyed in `node_table`
+    node_identity: NodeIdentity,
+    /// Externally computed vector embeddings by node index, all of one dimension
+    embeddings: BTreeMap<usize, Vec<f32>>,
+    /// Changes not yet taken by `take_journal`, or `None` if journaling is off
+    journal: Option<Vec<WalRecord>>,
+    /// Normalization deciding which content nodes count as duplicates, if any
+    dedup_key: Option<fn(&str) -> String>,
+    /// Index of the first node stored under each normalized node, when `dedup_key` is set
+    dedup_index: HashMap<Node, usize>,
+    /// Seed of the document IDs returned by `file_id` (`None` = the fixed seed)
+    hash_seed: Option<u64>,
+    /// Number of changes made so far, journaled or not, see `change_count`
+    change_count: u64,
+    /// Read counts by file node index; every file node has one
+    read_counts: BTreeMap<usize, ReadCount>,
+    /// Versions of named checkpoints by `(filename, checkpoint name)`
+    checkpoints: HashMap<(String, String), i32>,
+    /// One above the highest version any edge was stored with. It never decreases,
+    /// even when edges are removed, so `next_version` always wins LWW.
+    version_floor: i32,
+    /// Edges as they were before each change made by a running `apply_transaction`,
+    /// oldest first, or `None` outside a transaction
+    undo_log: Option<Vec<Undo>>,
+}
+
+/// An edge as it was before a change, kept so a failed transaction can put it back.
+#[derive(Debug, Clone)]
+enum Undo {
+    Edge {
+        table: EdgeTable,
+        edge_key: (usize, usize),
+        previous: Option<Edge>,
+    },
+    FileEdge {
+        key: (usize, usize, usize),
+        previous: Option<Edge>,
+    },
+}
+
+impl KnowledgeBase {
+    /// Creates a new empty knowledge base.
+    pub fn new() -> Self {
+        Self::with_node_identity(NodeIdentity::default())
+    }
+
+    /// Creates a new empty knowledge base that keys content nodes as given.
+    ///
+    /// See `NodeIdentity` for the tradeoffs between the modes.
+    pub fn with_node_identity(node_identity: NodeIdentity) -> Self {
+        Self {
+            edge_table: BTreeMap::new(),
+            file_edges: BTreeMap::new(),
+            ref_table: BTreeMap::new(),
+            ref_index: BTreeSet::new(),
+            node_table: IndexSet::new(),
+            node_identity,
+            embeddings: BTreeMap::new(),
+            journal: None,
+            dedup_key: None,
+            dedup_index: HashMap::new(),
+            hash_seed: None,
+            change_count: 0,
+            read_counts: BTreeMap::new(),
+            checkpoints: HashMap::new(),
+            version_floor: 0,
+            undo_log: None,
+        }
+    }
+
+    /// Sets the normalization that decides which content nodes are duplicates.
+    ///
+    /// With a dedup key, a content node whose normalized content and filename match an
+    /// existing node is not stored again; the existing node, with its original
+    /// content, is used in its place. E.g. with `straighten_quotes`, a line with curly
+    /// quotes and the same line with straight quotes become one node, displayed as
+    /// whichever was ingested first. DIR/FILE/TOMBSTONE markers are never normalized.
+    ///
+    /// This changes graph topology: files that differ only by the normalization
+    /// share nodes and therefore edges, like lines shared between files do. The key
+    /// isn't saved in snapshots, so set it again after loading one; existing nodes
+    /// are indexed under it, keeping the first of any that now collide.
+    pub fn set_dedup_key(&mut self, dedup_key: Option<fn(&str) -> String>) {
+        self.dedup_key = dedup_key;
+        self.dedup_index.clear();
+        for idx in 0..self.node_table.len() {
+            if let Some(key) = self.dedup_node(&self.node_table[idx]) {
+                self.dedup_index.entry(key).or_insert(idx);
+            }
+        }
+    }
+
+    /// Sets the seed `file_id` hashes filenames with, see `seeded_document_id`. Like
+    /// the dedup key, the seed isn't saved in snapshots.
+    pub fn set_hash_seed(&mut self, hash_seed: Option<u64>) {
+        self.hash_seed = hash_seed;
+    }
+
+    /// Returns the normalized node a node is deduplicated under, or `None` if no
+    /// dedup key is set or the node is a marker.
+    fn dedup_node(&self, node: &Node) -> Option<Node> {
+        let dedup_key = self.dedup_key?;
+        (!node.is_marker()).then(|| Node {
+            content: dedup_key(&node.content),
+            filename: node.filename.clone(),
+            position: node.position,
+            kind: node.kind,
+        })
+    }
+
+    /// Returns the index of a node, or of the node it is deduplicated with.
+    pub fn node_index(&self, node: &Node) -> Option<usize> {
+        match self.dedup_node(node) {
+            Some(key) => self.dedup_index.get(&key).copied(),
+            None => self.node_table.get_index_of(node),
+        }
+    }
+
+    /// Returns how content nodes are keyed in this knowledge base.
+    pub fn node_identity(&self) -> NodeIdentity {
+        self.node_identity
+    }
+
+    /// Removes every node, structural edge, and reference edge. The node identity
+    /// mode is kept.
+    pub fn clear(&mut self) {
+        self.node_table.clear();
+        self.edge_table.clear();
+        self.file_edges.clear();
+        self.ref_table.clear();
+        self.ref_index.clear();
+        self.embeddings.clear();
+        self.dedup_index.clear();
+        self.read_counts.clear();
+        self.checkpoints.clear();
+        self.change_count += 1;
+        // Pending changes are moot once everything is cleared
+        if let Some(journal) = &mut self.journal {
+            *journal = vec![WalRecord::Clear];
+        }
+    }
+
+    /// Replaces the whole contents with another knowledge base's, e.g. one loaded with
+    /// `load_from_json`, keeping this one's dedup key, hash seed, and journaling.
+    ///
+    /// The journal is emptied rather than recording the new contents, so a
+    /// write-ahead log must be compacted right after to persist them.
+    pub fn replace_with(&mut self, mut other: KnowledgeBase) {
+        other.set_dedup_key(self.dedup_key);
+        other.hash_seed = self.hash_seed;
+        other.change_count = self.change_count + 1;
+        other.journal = self.journal.as_ref().map(|_| Vec::new());
+        *self = other;
+    }
+
+    /// Starts recording every change as a `WalRecord`, for a write-ahead log.
+    /// Journaling is off by default, since the journal grows until it is taken.
+    pub fn start_journal(&mut self) {
+        self.journal.get_or_insert_with(Vec::new);
+    }
+
+    /// Takes the changes recorded since the last call, oldest first. Empty if
+    /// journaling is off.
+    ///
+    /// Reads are counted under a shared borrow, so they can't be journaled as they
+    /// happen. Instead, every read count that changed since the last call is taken
+    /// along as a `WalRecord::ReadCount` at the end. Counted reads alone don't make
+    /// `has_journal_records` true.
+    pub fn take_journal(&mut self) -> Vec<WalRecord> {
+        let Some(journal) = &mut self.journal else {
+            return Vec::new();
+        };
+        for (idx, read_count) in &mut self.read_counts {
+            let count = *read_count.count.get_mut();
+            if count != read_count.journaled {
+                journal.push(WalRecord::ReadCount { idx: *idx, count });
+                read_count.journaled = count;
+            }
+        }
+        std::mem::take(journal)
+    }
+
+    /// Returns true if the journal holds changes that haven't been taken.
+    pub fn has_journal_records(&self) -> bool {
+        self.journal.as_ref().is_some_and(|journal| !journal.is_empty())
+    }
+
+    /// Returns a counter that grows with every change to the nodes, edges,
+    /// embeddings, or checkpoints, so callers can tell whether an operation changed
+    /// anything. Reads, including counted ones, don't change it.
+    pub fn change_count(&self) -> u64 {
+        self.change_count
+    }
+
+    /// Counts a change and records it if journaling is on.
+    fn record(&mut self, record: impl FnOnce() -> WalRecord) {
+        self.change_count += 1;
+        if let Some(journal) = &mut self.journal {
+            journal.push(record());
+        }
+    }
+
+    /// Keeps the state an edge had before a change while a transaction runs.
+    fn remember(&mut self, undo: impl FnOnce() -> Undo) {
+        if let Some(undo_log) = &mut self.undo_log {
+            undo_log.push(undo());
+        }
+    }
+
+    /// Reapplies a change recorded by the journal, e.g. when replaying a write-ahead
+    /// log over the snapshot it was recorded after.
+    ///
+    /// Records are applied to the tables directly and are not journaled again.
+    /// Fails if the record doesn't fit this knowledge base, e.g. a node that
+    /// already exists or an edge to a node that doesn't.
+    pub fn apply_wal_record(&mut self, record: WalRecord) -> Result<(), SnapshotError> {
+        match record {
+            WalRecord::Node { node } => {
+                let dedup_node = self.dedup_node(&node);
+                let is_file = node.is_file();
+                let (idx, is_new) = self.node_table.insert_full(node);
+                if !is_new {
+                    return Err(SnapshotError::Invalid {
+                        location: format!("node {}", idx),
+                        reason: "node is already in the knowledge base".to_string(),
+                    });
+                }
+                if is_file {
+                    self.read_counts.insert(idx, ReadCount::default());
+                }
+                if let Some(key) = dedup_node {
+                    self.dedup_index.entry(key).or_insert(idx);
+                }
+            }
+            WalRecord::PutEdge { table, edge } => {
+                let node_count = self.node_table.len();
+                if edge.from >= node_count || edge.to >= node_count {
+                    return Err(SnapshotError::Invalid {
+                        location: format!("edge ({}, {})", edge.from, edge.to),
+                        reason: format!("node is out of range ({} nodes)", node_count),
+                    });
+                }
+                if table == EdgeTable::Reference {
+                    self.ref_index.insert((edge.to, edge.from));
+                }
+                self.note_version(edge.version);
+                self.table_mut(table)
+                    .insert((edge.from, edge.to), edge.into_edge());
+            }
+            WalRecord::RemoveEdge { table, from, to } => {
+                match table {
+                    EdgeTable::Structural => self.forget_file_edges((from, to)),
+                    EdgeTable::Reference => {
+                        self.ref_index.remove(&(to, from));
+                    }
+                }
+                self.table_mut(table).remove(&(from, to));
+            }
+            WalRecord::PutFileEdge { file_idx, edge } => {
+                let node_count = self.node_table.len();
+                if file_idx >= node_count || edge.from >= node_count || edge.to >= node_count {
+                    return Err(SnapshotError::Invalid {
+                        location: format!("edge ({}, {}) of file {}", edge.from, edge.to, file_idx),
+                        reason: format!("node is out of range ({} nodes)", node_count),
+                    });
+                }
+                self.file_edges
+                    .insert((file_idx, edge.from, edge.to), edge.into_edge());
+            }
+            WalRecord::RemoveFileEdge { file_idx, from, to } => {
+                self.file_edges.remove(&(file_idx, from, to));
+            }
+            WalRecord::Embedding { idx, embedding } => {
+                if idx >= self.node_table.len() {
+                    return Err(SnapshotError::Invalid {
+                        location: format!("embedding of node {}", idx),
+                        reason: EmbeddingError::NodeNotFound.to_string(),
+                    });
+                }
+                self.embeddings.insert(idx, embedding);
+            }
+            WalRecord::Clear => {
+                self.node_table.clear();
+                self.edge_table.clear();
+                self.file_edges.clear();
+                self.ref_table.clear();
+                self.ref_index.clear();
+                self.embeddings.clear();
+                self.dedup_index.clear();
+                self.read_counts.clear();
+                self.checkpoints.clear();
+            }
+            WalRecord::Checkpoint {
+                filename,
+                name,
+                version,
+            } => {
+                self.checkpoints.insert((filename, name), version);
+            }
+            WalRecord::ReadCount { idx, count } => {
+                // Like in snapshots, counts for anything but a file node are dropped
+                if let Some(read_count) = self.read_counts.get_mut(&idx) {
+                    *read_count = ReadCount::persisted(count);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Attaches a vector embedding to a node, replacing any previous one.
+    ///
+    /// Embeddings are computed externally. All embeddings in a knowledge base must
+    /// have the same dimension; the first one stored sets it.
+    pub fn set_embedding(&mut self, idx: usize, embedding: Vec<f32>) -> Result<(), EmbeddingError> {
+        if idx >= self.node_table.len() {
+            return Err(EmbeddingError::NodeNotFound);
+        }
+        if embedding.is_empty() || !embedding.iter().all(|value| value.is_finite()) {
+            return Err(EmbeddingError::Invalid);
+        }
+        // Replacing the only stored embedding may change the dimension
+        let expected = self
+            .embeddings
+            .iter()
+            .find(|(other_idx, _)| **other_idx != idx)
+            .map(|(_, other)| other.len());
+        if let Some(expected) = expected.filter(|expected| *expected != embedding.len()) {
+            return Err(EmbeddingError::DimensionMismatch {
+                expected,
+                found: embedding.len(),
+            });
+        }
+
+        self.record(|| WalRecord::Embedding {
+            idx,
+            embedding: embedding.clone(),
+        });
+        self.embeddings.insert(idx, embedding);
+        Ok(())
+    }
+
+    /// Returns the embedding stored for a node.
+    pub fn embedding(&self, idx: usize) -> Option<&[f32]> {
+        self.embeddings.get(&idx).map(Vec::as_slice)
+    }
+
+    /// Finds the `k` nodes whose embeddings are most similar to `query` by cosine similarity.
+    ///
+    /// This is a brute-force scan over every stored embedding, so it costs O(N * d) per
+    /// query. Embeddings with zero length are never returned.
+    ///
+    /// # Returns
+    /// Up to `k` pairs of (node index, similarity), most similar first, or an error if
+    /// the query is empty, non-finite, zero, or of a different dimension
+    pub fn nearest(&self, query: &[f32], k: usize) -> Result<Vec<(usize, f32)>, EmbeddingError> {
+        let norm = |vector: &[f32]| vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+
+        let query_norm = norm(query);
+        if query.is_empty() || !query_norm.is_finite() || query_norm == 0.0 {
+            return Err(EmbeddingError::Invalid);
+        }
+        if let Some(expected) = self.embedding_dimension().filter(|dim| *dim != query.len()) {
+            return Err(EmbeddingError::DimensionMismatch {
+                expected,
+                found: query.len(),
+            });
+        }
+
+        let mut scored: Vec<(usize, f32)> = self
+            .embeddings
+            .iter()
+            .filter_map(|(idx, embedding)| {
+                let embedding_norm = norm(embedding);
+                if embedding_norm == 0.0 {
+                    return None;
+                }
+                let dot: f32 = query.iter().zip(embedding).map(|(a, b)| a * b).sum();
+                Some((*idx, dot / (query_norm * embedding_norm)))
+            })
+            .collect();
+
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.truncate(k);
+        Ok(scored)
+    }
+
+    /// Returns the dimension of stored embeddings, or `None` if there are none.
+    pub fn embedding_dimension(&self) -> Option<usize> {
+        self.embeddings.values().next().map(Vec::len)
+    }
+
+    /// Inserts a directory node into the knowledge base.
+    ///
+    /// # Arguments
+    /// * `directory_path` - Path of the directory (e.g., "src/components")
+    ///
+    /// # Returns
+    /// The index of the created directory node
+    pub fn insert_directory(&mut self, directory_path: &str) -> usize {
+        let dir_node = Node::directory(directory_path);
+        self.push_node(dir_node).0
+    }
+
+    /// Inserts a generic node into the knowledge base.
+    ///
+    /// # Arguments
+    /// * `content` - Content of the node
+    /// * `filename` - Source filename for provenance tracking
+    ///
+    /// # Returns
+    /// The index of the created node
+    pub fn insert_node(&mut self, content: &str, filename: &str) -> usize {
+        let node = Node::new(content.to_string(), filename.to_string());
+        self.push_node(node).0
+    }
+
+    /// Inserts a node unless it already exists, journaling new nodes.
+    ///
+    /// # Returns
+    /// The node's index and whether it was newly inserted
+    fn push_node(&mut self, node: Node) -> (usize, bool) {
+        let dedup_node = self.dedup_node(&node);
+        if let Some(idx) = dedup_node.as_ref().and_then(|key| self.dedup_index.get(key)) {
+            return (*idx, false);
+        }
+
+        let (idx, is_new) = self.node_table.insert_full(node);
+        if let Some(key) = dedup_node {
+            self.dedup_index.insert(key, idx);
+        }
+        if is_new && self.node_table[idx].is_file() {
+            self.read_counts.insert(idx, ReadCount::default());
+        }
+        if is_new {
+            self.change_count += 1;
+            let node = &self.node_table[idx];
+            if let Some(journal) = &mut self.journal {
+                journal.push(WalRecord::Node { node: node.clone() });
+            }
+        }
+        (idx, is_new)
+    }
+
+    /// Inserts markdown content into the knowledge base.
+    ///
+    /// The markdown is split by newlines. Each line becomes a node, and sequential 
+    /// nodes are connected by edges with the given version.
+    /// A file node is created and linked to the parent node, then all content nodes
+    /// are linked sequentially starting from the file node.
+    ///
+    /// # Arguments
+    /// * `markdown_content` - Raw markdown text to process
+    /// * `filename` - Source filename for provenance tracking
+    /// * `parent_idx` - Index of the parent node (e.g., directory node)
+    /// * `version` - Version number for CRDT conflict resolution (higher = newer)
+    /// * `tag` - Tag to apply to all edges created from this content
+    ///
+    /// # Returns
+    /// The index of the file node created
+    pub fn insert_markdown(
+        &mut self,
+        markdown_content: &str,
+        filename: &str,
+        parent_idx: usize,
+        reference_nodes: Vec<Node>,
+        version: i32,
+        tag: &str,
+    ) -> usize {
+        self.insert_markdown_with_options(
+            markdown_content,
+            filename,
+            parent_idx,
+            reference_nodes,
+            version,
+            tag,
+            &IngestOptions::default(),
+        )
+        .expect("default ingest options never reject content")
+    }
+
+    /// Inserts markdown content into the knowledge base using the given ingest options.
+    ///
+    /// Behaves like `insert_markdown`, but applies the chunker, line transform,
+    /// per-node length cap, and edge insert policy from `options`. The chunker
+    /// replaces the split into lines; the transform and cap then apply to each chunk
+    /// as they would to a line. The cap is checked against transformed lines. Lines
+    /// longer than `max_node_len` either reject the whole ingest (nothing is
+    /// inserted) or are split into fixed-size chunks that are chained as consecutive
+    /// nodes. Splitting changes the content: each chunk is read back on its own line,
+    /// so a read returns the long line broken up by newlines.
+    ///
+    /// # Returns
+    /// The index of the file node created, or an `IngestError` if content was rejected
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert_markdown_with_options(
+        &mut self,
+        markdown_content: &str,
+        filename: &str,
+        parent_idx: usize,
+        reference_nodes: Vec<Node>,
+        version: i32,
+        tag: &str,
+        options: &IngestOptions,
+    ) -> Result<usize, IngestError> {
+        let prepared = Self::prepare_content(markdown_content, options)?;
+        let (file_idx, _) = self.insert_prepared(
+            prepared,
+            filename,
+            parent_idx,
+            reference_nodes,
+            options.structural_edge(version, tag),
+            options.edge_insert_policy,
+        );
+        Ok(file_idx)
+    }
+
+    /// Splits, transforms, and checks content for ingest without touching the
+    /// knowledge base, so the work can be done before taking a lock on it.
+    ///
+    /// # Returns
+    /// The lines to ingest, or an `IngestError` if content was rejected
+    pub fn prepare_content(
+        markdown_content: &str,
+        options: &IngestOptions,
+    ) -> Result<PreparedContent, IngestError> {
+        let chunks = match &options.chunker {
+            Some(chunker) => chunker.chunk(markdown_content),
+            None => LineChunker.chunk(markdown_content),
+        };
+        let mut lines = Vec::new();
+        let mut split_chunks = BTreeSet::new();
+        for (line_idx, line) in chunks.into_iter().enumerate() {
+            let line = match options.transform {
+                Some(transform) => transform(line),
+                None => line.to_string(),
+            };
+            if line.is_empty() {
+                continue;
+            }
+            match options.max_node_len {
+                Some(max_len) if line.len() > max_len => match options.oversize_policy {
+                    OversizePolicy::Reject => {
+                        return Err(IngestError::NodeTooLarge {
+                            line: line_idx + 1,
+                            len: line.len(),
+                            max_len,
+                        });
+                    }
+                    OversizePolicy::Split => {
+                        for chunk in split_oversized(&line, max_len) {
+                            split_chunks.insert(lines.len());
+                            lines.push(chunk.to_string());
+                        }
+                    }
+                },
+                _ => lines.push(line),
+            }
+        }
+        Ok(PreparedContent { lines, split_chunks })
+    }
+
+    /// Inserts prepared lines under a new file node; see `insert_markdown_with_options`.
+    ///
+    /// Every structural edge created is a copy of `edge`. Reference edges get its
+    /// version and tag but the default weight.
+    fn insert_prepared(
+        &mut self,
+        prepared: PreparedContent,
+        filename: &str,
+        parent_idx: usize,
+        reference_nodes: Vec<Node>,
+        edge: Edge,
+        policy: EdgeInsertPolicy,
+    ) -> (usize, Vec<usize>) {
+        // Create file node and link it to parent
+        let file_idx = self.push_node(Node::file(filename)).0;
+
+        // Create structural edge from parent to file
+        self.insert_edge(EdgeTable::Structural, (parent_idx, file_idx), edge.clone(), policy);
+
+        // Create content nodes from the lines. Split chunks are always keyed on their
+        // position, so repeated chunks of one line don't collapse into a single node.
+        let split_chunks = prepared.split_chunks;
+        let content_nodes: Vec<Node> = prepared
+            .lines
+            .into_iter()
+            .enumerate()
+            .map(|(position, line)| {
+                let node_filename = match self.node_identity {
+                    NodeIdentity::ContentOnly => String::new(),
+                    _ => filename.to_string(),
+                };
+                if split_chunks.contains(&position)
+                    || self.node_identity == NodeIdentity::ContentFileAndPosition
+                {
+                    Node::at_position(line, node_filename, position)
+                } else {
+                    Node::new(line, node_filename)
+                }
+            })
+            .collect();
+
+        if content_nodes.is_empty() {
+            return (file_idx, Vec::new());
+        }
+
+        let mut new_node_indices = Vec::new();
+        // Nodes the written chain leaves through a structural edge
+        let mut chain = vec![file_idx];
+
+        // Insert first content node and link it from file node
+        let first_content_idx = self.push_node(content_nodes[0].clone()).0;
+        new_node_indices.push(first_content_idx);
+
+        // Link file node to first content node
+        self.insert_edge(
+            EdgeTable::Structural,
+            (file_idx, first_content_idx),
+            edge.clone(),
+            policy,
+        );
+
+        // Insert remaining nodes and create edges
+        for window in content_nodes.windows(2) {
+            let from_node = &window[0];
+            let to_node = &window[1];
+
+            let (to_idx, is_new) = self.push_node(to_node.clone());
+
+            let from_idx = self.node_index(from_node).unwrap();
+            if is_new {
+                new_node_indices.push(to_idx)
+            };
+            chain.push(from_idx);
+
+            // Existing edges are kept, preserving divergent paths; only the
+            // `max_fork_width` eviction after the write removes any
+            self.insert_edge(EdgeTable::Structural, (from_idx, to_idx), edge.clone(), policy);
+            if self.node_identity == NodeIdentity::ContentOnly {
+                self.insert_file_edge(file_idx, (from_idx, to_idx), edge.clone(), policy);
+            }
+        }
+
+        // Insert references
+        let reference_edge = Edge::new(edge.version, edge.tag);
+        for reference_node in reference_nodes {
+            let from_idx = self.push_node(reference_node).0;
+            for to_idx in new_node_indices.iter().copied() {
+                let edge_key = (from_idx, to_idx);
+                self.insert_edge(EdgeTable::Reference, edge_key, reference_edge.clone(), policy);
+            }
+        }
+
+        (file_idx, chain)
+    }
+
+    /// Returns the number of structural edges leaving a node.
+    pub fn out_degree(&self, idx: usize) -> usize {
+        self.edge_table
+            .range((idx, usize::MIN)..(idx + 1, usize::MIN))
+            .count()
+    }
+
+    /// Lists nodes with more than `max_fan_out` outgoing structural edges as
+    /// `(node, out-degree)`, highest out-degree first and ties by index.
+    ///
+    /// Every divergent edit of a line adds a successor, so these are the lines
+    /// where the graph has diverged most and traversal has the most branches.
+    /// Directory nodes are left out, since their successors are their files.
+    pub fn high_fan_out_nodes(&self, max_fan_out: usize) -> Vec<(usize, usize)> {
+        let mut degrees: BTreeMap<usize, usize> = BTreeMap::new();
+        for (from_idx, _) in self.edge_table.keys() {
+            *degrees.entry(*from_idx).or_default() += 1;
+        }
+        let mut nodes: Vec<(usize, usize)> = degrees
+            .into_iter()
+            .filter(|(_, degree)| *degree > max_fan_out)
+            .filter(|(idx, _)| !self.node_table[*idx].is_directory())
+            .collect();
+        nodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        nodes
+    }
+
+    /// Records an ingested edge, resolving an existing edge with the same key by `policy`.
+    fn insert_edge(
+        &mut self,
+        table: EdgeTable,
+        edge_key: (usize, usize),
+        edge: Edge,
+        policy: EdgeInsertPolicy,
+    ) {
+        let replace = match self.table(table).get(&edge_key) {
+            None => true,
+            Some(existing) => {
+                policy == EdgeInsertPolicy::LatestWins && edge.version > existing.version
+            }
+        };
+        if replace {
+            self.set_edge(table, edge_key, edge);
+        }
+    }
+
+    /// Records a file's own copy of an edge of its chain, see `file_edges`, resolving
+    /// an existing copy by `policy` like `insert_edge`.
+    fn insert_file_edge(
+        &mut self,
+        file_idx: usize,
+        edge_key: (usize, usize),
+        edge: Edge,
+        policy: EdgeInsertPolicy,
+    ) {
+        let key = (file_idx, edge_key.0, edge_key.1);
+        let replace = match self.file_edges.get(&key) {
+            None => true,
+            Some(existing) => {
+                policy == EdgeInsertPolicy::LatestWins && edge.version > existing.version
+            }
+        };
+        if replace {
+            self.record(|| WalRecord::PutFileEdge {
+                file_idx,
+                edge: EdgeRecord::new(edge_key, &edge),
+            });
+            let previous = self.file_edges.insert(key, edge);
+            self.remember(|| Undo::FileEdge { key, previous });
+        }
+    }
+
+    /// Drops every file's copy of a structural edge that was removed.
+    fn forget_file_edges(&mut self, edge_key: (usize, usize)) {
+        if self.file_edges.is_empty() {
+            return;
+        }
+        let keys: Vec<(usize, usize, usize)> = self
+            .file_edges
+            .keys()
+            .filter(|(_, from_idx, to_idx)| (*from_idx, *to_idx) == edge_key)
+            .copied()
+            .collect();
+        for key in keys {
+            let previous = self.file_edges.remove(&key);
+            self.remember(|| Undo::FileEdge { key, previous });
+        }
+    }
+
+    /// Inserts or overwrites an edge, journaling the change.
+    ///
+    /// # Returns
+    /// The edge previously stored under the key, if any
+    fn set_edge(&mut self, table: EdgeTable, edge_key: (usize, usize), edge: Edge) -> Option<Edge> {
+        self.record(|| WalRecord::PutEdge {
+            table,
+            edge: EdgeRecord::new(edge_key, &edge),
+        });
+        if table == EdgeTable::Reference {
+            self.ref_index.insert((edge_key.1, edge_key.0));
+        }
+        self.note_version(edge.version);
+        let previous = self.table_mut(table).insert(edge_key, edge);
+        self.remember(|| Undo::Edge {
+            table,
+            edge_key,
+            previous: previous.clone(),
+        });
+        previous
+    }
+
+    /// Raises the version floor above a version an edge was stored with.
+    fn note_version(&mut self, version: i32) {
+        self.version_floor = self.version_floor.max(version.saturating_add(1));
+    }
+
+    /// Returns the version the next write, delete, or reference batch without an
+    /// explicit version gets: the edge count, as it always was, unless an edge was
+    /// stored at or above that, e.g. by a forced explicit version, or edges were
+    /// removed since. Then it is one above the highest version ever stored, so the
+    /// change still wins LWW and never reuses a version.
+    pub fn next_version(&self) -> i32 {
+        self.version_floor.max(self.edge_count() as i32)
+    }
+
+    /// Recomputes the reverse index of reference edges from `ref_table`.
+    ///
+    /// Every mutation keeps the index up to date, so this is only needed to repair
+    /// it, e.g. if a code path forgot to. `merge` and loading a snapshot rebuild it
+    /// anyway, since they fill `ref_table` in bulk.
+    ///
+    /// # Returns
+    /// True if the index had drifted from `ref_table`
+    pub fn rebuild_reverse_index(&mut self) -> bool {
+        let ref_index: BTreeSet<(usize, usize)> = self
+            .ref_table
+            .keys()
+            .map(|(from_idx, to_idx)| (*to_idx, *from_idx))
+            .collect();
+        let drifted = ref_index != self.ref_index;
+        self.ref_index = ref_index;
+        drifted
+    }
+
+    /// Returns the nodes with a reference edge to `idx`, in index order.
+    fn incoming_references(&self, idx: usize) -> impl Iterator<Item = usize> + '_ {
+        self.ref_index
+            .range((idx, usize::MIN)..(idx + 1, usize::MIN))
+            .map(|(_, from_idx)| *from_idx)
+    }
+
+    /// Writes a file into the knowledge base under its parent directory node.
+    ///
+    /// The directory node is derived from the filepath (or "." for top-level files)
+    /// and the version is taken from `next_version`.
+    ///
+    /// # Arguments
+    /// * `filepath` - Path of the file (e.g., "docs/readme.md")
+    /// * `content` - Raw markdown text to ingest
+    /// * `reference_nodes` - Nodes that were read while writing this file
+    /// * `options` - Ingest options applied to the content
+    ///
+    /// # Returns
+    /// Statistics about what the ingest added, or an `IngestError` if content was rejected
+    pub fn write_file(
+        &mut self,
+        filepath: &str,
+        content: &str,
+        reference_nodes: Vec<Node>,
+        options: &IngestOptions,
+    ) -> Result<IngestStats, IngestError> {
+        let prepared = Self::prepare_content(content, options)?;
+        self.write_prepared(filepath, prepared, reference_nodes, options, None, false)
+    }
+
+    /// Writes a file like `write_file`, but at an explicit version.
+    ///
+    /// The version must exceed the file's `max_version`, otherwise the new edges could
+    /// lose LWW to older ones and the write would be partly or wholly invisible.
+    /// `force` skips that check, e.g. to deliberately write below a newer version.
+    /// Negative versions are always rejected.
+    ///
+    /// # Returns
+    /// Statistics about what the ingest added, or an `IngestError` if the version or
+    /// content was rejected
+    pub fn write_file_at_version(
+        &mut self,
+        filepath: &str,
+        content: &str,
+        reference_nodes: Vec<Node>,
+        options: &IngestOptions,
+        version: i32,
+        force: bool,
+    ) -> Result<IngestStats, IngestError> {
+        let prepared = Self::prepare_content(content, options)?;
+        self.write_prepared(filepath, prepared, reference_nodes, options, Some(version), force)
+    }
+
+    /// Writes content prepared by `prepare_content`, like `write_file` or, given a
+    /// `version`, like `write_file_at_version`.
+    ///
+    /// `options` should be the ones the content was prepared with; only their edge
+    /// insert policy is applied here.
+    pub fn write_prepared(
+        &mut self,
+        filepath: &str,
+        prepared: PreparedContent,
+        reference_nodes: Vec<Node>,
+        options: &IngestOptions,
+        version: Option<i32>,
+        force: bool,
+    ) -> Result<IngestStats, IngestError> {
+        if let Some(version) = version {
+            if version < 0 {
+                return Err(IngestError::NegativeVersion { version });
+            }
+            if let Some(max_version) = self.max_version(filepath) {
+                if !force && version <= max_version {
+                    return Err(IngestError::StaleVersion {
+                        version,
+                        max_version,
+                    });
+                }
+            }
+        }
+        Ok(self.write_file_with_version(filepath, prepared, reference_nodes, options, version))
+    }
+
+    /// Returns the highest version of any structural edge reachable from a file,
+    /// including divergent branches and the tombstone of a deleted file.
+    /// Returns `None` if the file was never written.
+    pub fn max_version(&self, filename: &str) -> Option<i32> {
+        let file_idx = self.file_node_index(filename)?;
+        self.reachable_edges(file_idx)
+            .into_iter()
+            .map(|(_, edge)| edge.version)
+            .max()
+    }
+
+    /// Writes a file like `write_file`, at the given version instead of `next_version`.
+    fn write_file_with_version(
+        &mut self,
+        filepath: &str,
+        prepared: PreparedContent,
+        reference_nodes: Vec<Node>,
+        options: &IngestOptions,
+        version: Option<i32>,
+    ) -> IngestStats {
+        // Writing a deleted file brings it back
+        if let (Some(file_idx), Some(tombstone_idx)) =
+            (self.file_node_index(filepath), self.tombstone_index(filepath))
+        {
+            if self.is_tombstoned(file_idx) {
+                let edge_key = (file_idx, tombstone_idx);
+                let previous = self.edge_table.remove(&edge_key);
+                self.remember(|| Undo::Edge {
+                    table: EdgeTable::Structural,
+                    edge_key,
+                    previous,
+                });
+                self.record(|| WalRecord::RemoveEdge {
+                    table: EdgeTable::Structural,
+                    from: file_idx,
+                    to: tombstone_idx,
+                });
+            }
+        }
+
+        let nodes_before = self.node_table.len();
+        let edges_before = self.edge_table.len();
+        let references_before = self.ref_table.len();
+
+        let parent_idx = self.insert_directory(Self::directory_path(filepath));
+
+        // Get current highest version
+        let version = version.unwrap_or_else(|| self.next_version());
+
+        let (file_idx, mut chain) = self.insert_prepared(
+            prepared,
+            filepath,
+            parent_idx,
+            reference_nodes,
+            options.structural_edge(version, &format!("version-{}", version)),
+            options.edge_insert_policy,
+        );
+
+        // Counted before eviction, which can shrink the edge table
+        let edges_added = self.edge_table.len() - edges_before;
+
+        // A chain through a repeated line visits its node more than once
+        chain.sort_unstable();
+        chain.dedup();
+        let edges_evicted = options.max_fork_width.map_or(0, |max_fork_width| {
+            chain
+                .iter()
+                .map(|idx| self.evict_oldest_forks(file_idx, *idx, max_fork_width))
+                .sum()
+        });
+        let high_fan_out_nodes = options.max_fan_out.map_or(0, |max_fan_out| {
+            chain
+                .iter()
+                .filter(|idx| self.out_degree(**idx) > max_fan_out)
+                .count()
+        });
+
+        IngestStats {
+            file_idx,
+            parent_idx,
+            version,
+            nodes_added: self.node_table.len() - nodes_before,
+            edges_added,
+            references_added: self.ref_table.len() - references_before,
+            high_fan_out_nodes,
+            edges_evicted,
+        }
+    }
+
+    /// Evicts the lowest-version outgoing structural edges of a node until at most
+    /// `max_fork_width` remain, ties broken by target index. A width below 1 counts as 1,
+    /// so the newest edge always survives. Directory nodes are left alone, since their
+    /// successors are their files.
+    ///
+    /// Under `NodeIdentity::ContentOnly`, a content node's forks are counted in the
+    /// copies of `file_idx`, the file being written, since a line shared by several
+    /// files has a successor per file. Evicting one drops the file's copy, and the
+    /// structural edge too once no other file has a copy of it.
+    ///
+    /// Only edges are evicted: nodes that were only reachable through them stay in
+    /// the node table, and older versions that used them can no longer be read back.
+    ///
+    /// # Returns
+    /// The number of edges evicted
+    fn evict_oldest_forks(&mut self, file_idx: usize, idx: usize, max_fork_width: usize) -> usize {
+        if self.node_table[idx].is_directory() {
+            return 0;
+        }
+        let per_file = self.node_identity == NodeIdentity::ContentOnly && idx != file_idx;
+        let mut forks: Vec<((usize, usize), i32)> = if per_file {
+            self.file_edges
+                .range((file_idx, idx, usize::MIN)..(file_idx, idx + 1, usize::MIN))
+                .map(|((_, from_idx, to_idx), edge)| ((*from_idx, *to_idx), edge.version))
+                .collect()
+        } else {
+            self.edge_table
+                .range((idx, usize::MIN)..(idx + 1, usize::MIN))
+                .map(|(edge_key, edge)| (*edge_key, edge.version))
+                .collect()
+        };
+        let evicted = forks.len().saturating_sub(max_fork_width.max(1));
+        forks.sort_by_key(|(edge_key, version)| (*version, *edge_key));
+        for (edge_key, _) in forks.into_iter().take(evicted) {
+            if per_file {
+                let key = (file_idx, edge_key.0, edge_key.1);
+                let previous = self.file_edges.remove(&key);
+                self.remember(|| Undo::FileEdge { key, previous });
+                self.record(|| WalRecord::RemoveFileEdge {
+                    file_idx,
+                    from: edge_key.0,
+                    to: edge_key.1,
+                });
+                let shared = self
+                    .file_edges
+                    .keys()
+                    .any(|(_, from_idx, to_idx)| (*from_idx, *to_idx) == edge_key);
+                if shared {
+                    continue;
+                }
+            }
+            let previous = self.edge_table.remove(&edge_key);
+            self.remember(|| Undo::Edge {
+                table: EdgeTable::Structural,
+                edge_key,
+                previous,
+            });
+            self.forget_file_edges(edge_key);
+            self.record(|| WalRecord::RemoveEdge {
+                table: EdgeTable::Structural,
+                from: edge_key.0,
+                to: edge_key.1,
+            });
+        }
+        evicted
+    }
+
+    /// Returns the directory a file is written under: its parent path, or "." for
+    /// top-level files.
+    pub fn directory_path(filepath: &str) -> &str {
+        std::path::Path::new(filepath)
+            .parent()
+            .and_then(|p| p.to_str())
+            .filter(|dir_path| !dir_path.is_empty())
+            .unwrap_or(".")
+    }
+
+    /// Returns an immutable reference to the node table.
+    pub fn nodes(&self) -> &IndexSet<Node> {
+        &self.node_table
+    }
+
+    /// Returns an immutable reference to the edge table.
+    pub fn edges(&self) -> &BTreeMap<(usize, usize), Edge> {
+        &self.edge_table
+    }
+
+    /// Returns an immutable reference to the reference table.
+    pub fn references(&self) -> &BTreeMap<(usize, usize), Edge> {
+        &self.ref_table
+    }
+
+    /// Finds all structural edges whose tag starts with `prefix`, e.g. every
+    /// `version-` edge or an ingest batch's custom prefix.
+    ///
+    /// This scans the whole edge table; an index by tag could speed it up later.
+    ///
+    /// # Returns
+    /// Matching `((from, to), edge)` pairs sorted by edge key
+    pub fn edges_by_tag_prefix(&self, prefix: &str) -> Vec<((usize, usize), &Edge)> {
+        self.edge_table
+            .iter()
+            .filter(|(_, edge)| edge.tag.starts_with(prefix))
+            .map(|(edge_key, edge)| (*edge_key, edge))
+            .collect()
+    }
+
+    /// Returns a page of the structural edges whose source index is in
+    /// `from_idx_lo..from_idx_hi` and whose tag starts with `tag_prefix`, sorted by
+    /// edge key. This is a range query on the edge table that stops once the page
+    /// holds `max_edges` edges, so it only costs as much as the page, e.g. to mirror
+    /// the graph a few nodes at a time.
+    ///
+    /// A page holds at most `max_edges` edges (at least one), even if that splits a
+    /// source node's edges; continue from `EdgePage::next` with `edges_in_range_from`.
+    ///
+    /// # Returns
+    /// The page, empty if the range is empty or reversed
+    pub fn edges_in_range(
+        &self,
+        from_idx_lo: usize,
+        from_idx_hi: usize,
+        tag_prefix: &str,
+        max_edges: usize,
+    ) -> EdgePage {
+        self.edges_in_range_from((from_idx_lo, usize::MIN), from_idx_hi, tag_prefix, max_edges)
+    }
+
+    /// Returns a page like `edges_in_range`, starting at the edge key `start` rather
+    /// than at the first edge of a source node, e.g. the `EdgePage::next` of the
+    /// previous page.
+    pub fn edges_in_range_from(
+        &self,
+        start: (usize, usize),
+        from_idx_hi: usize,
+        tag_prefix: &str,
+        max_edges: usize,
+    ) -> EdgePage {
+        if start.0 >= from_idx_hi {
+            return EdgePage {
+                edges: Vec::new(),
+                next: None,
+            };
+        }
+        let mut matching = self
+            .edge_table
+            .range(start..(from_idx_hi, usize::MIN))
+            .filter(|(_, edge)| edge.tag.starts_with(tag_prefix));
+        let edges = matching
+            .by_ref()
+            .take(max_edges.max(1))
+            .map(|(edge_key, edge)| (*edge_key, edge.clone()))
+            .collect();
+        EdgePage {
+            edges,
+            next: matching.next().map(|(edge_key, _)| *edge_key),
+        }
+    }
+
+    /// Counts the edges of each tag across the structural and reference tables, e.g.
+    /// to see how many edges each ingest batch or tombstone produced.
+    pub fn tag_stats(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for edge in self.edge_table.values().chain(self.ref_table.values()) {
+            *counts.entry(edge.tag.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Traverses the graph starting from a given node index, following the
+    /// edges with the highest version numbers (most recent path). The traversal
+    /// stops before revisiting a node; see `traverse_latest_path_with_meta` to
+    /// find out why it stopped.
+    ///
+    /// Returns a vector of node indices representing the traversal path.
+    pub fn traverse_latest_path(&self, start_idx: usize) -> Vec<usize> {
+        std::iter::once(start_idx)
+            .chain(
+                self.traverse_latest_edges(start_idx)
+                    .into_iter()
+                    .map(|((_, to_idx), _)| to_idx),
+            )
+            .collect()
+    }
+
+    /// Traverses the same path as `traverse_latest_path`, returning the edges taken.
+    ///
+    /// Returns the `((from, to), edge)` pairs in traversal order; empty if the start
+    /// node has no outgoing structural edges. Stops before revisiting a node, so a
+    /// cycle on the latest path ends the traversal instead of looping forever.
+    pub fn traverse_latest_edges(&self, start_idx: usize) -> Vec<((usize, usize), Edge)> {
+        let mut edges = Vec::new();
+        let mut visited = std::collections::HashSet::from([start_idx]);
+        let mut current = self.next_traversal_edge(start_idx, start_idx);
+
+        while let Some((edge_key, edge)) = current {
+            if !visited.insert(edge_key.1) {
+                break;
+            }
+            edges.push((*edge_key, edge.clone()));
+            current = self.next_traversal_edge(start_idx, edge_key.1);
+        }
+
+        edges
+    }
+
+    /// Traverses the latest path like `traverse_latest_path`, also reporting `TraversalMeta`.
+    ///
+    /// This variant also does not follow tombstone edges and gives up after
+    /// `MAX_TRAVERSAL_HOPS`. It counts outgoing edges at every hop, so it is slightly
+    /// more expensive.
+    pub fn traverse_latest_path_with_meta(&self, start_idx: usize) -> (Vec<usize>, TraversalMeta) {
+        let mut path = Vec::new();
+        let mut visited = std::collections::HashSet::new();
+        let mut meta = TraversalMeta::default();
+        let mut current_idx = start_idx;
+
+        loop {
+            path.push(current_idx);
+            visited.insert(current_idx);
+
+            let branch_factor = self.traversal_edges(start_idx, current_idx).count();
+            meta.max_branch_factor = meta.max_branch_factor.max(branch_factor);
+
+            let reason = match self.next_traversal_edge(start_idx, current_idx) {
+                Some((_, edge)) if edge.tag == TOMBSTONE_TAG => TerminationReason::Tombstone,
+                Some(((_, to_idx), _)) if visited.contains(to_idx) => TerminationReason::Cycle,
+                Some(_) if path.len() > MAX_TRAVERSAL_HOPS => TerminationReason::HopLimit,
+                Some(((_, to_idx), _)) => {
+                    current_idx = *to_idx;
+                    continue;
+                }
+                None => TerminationReason::EndOfChain,
+            };
+            meta.termination_reason = reason;
+            meta.truncated =
+                matches!(reason, TerminationReason::Cycle | TerminationReason::HopLimit);
+            break;
+        }
+
+        meta.hops = path.len() - 1;
+        (path, meta)
+    }
+
+    /// Traverses the graph like `traverse_latest_path`, but prefers edges with a given tag.
+    ///
+    /// At each hop, the highest-version edge tagged `preferred_tag` is followed if the
+    /// node has any; otherwise the highest-version edge overall. This lets e.g.
+    /// `approved` edits win over newer drafts. Stops before revisiting a node.
+    ///
+    /// Returns a vector of node indices representing the traversal path.
+    pub fn traverse_preferring_tag(&self, start_idx: usize, preferred_tag: &str) -> Vec<usize> {
+        let mut path = vec![start_idx];
+        let mut visited = std::collections::HashSet::from([start_idx]);
+        let mut current_idx = start_idx;
+
+        loop {
+            let next_edge = self
+                .traversal_edges(start_idx, current_idx)
+                .filter(|(_, edge)| edge.tag == preferred_tag)
+                .max_by_key(|(_, edge)| edge.version)
+                .or_else(|| self.next_traversal_edge(start_idx, current_idx));
+
+            match next_edge {
+                Some(((_, to_idx), _)) if visited.insert(*to_idx) => {
+                    path.push(*to_idx);
+                    current_idx = *to_idx;
+                }
+                _ => break,
+            }
+        }
+
+        path
+    }
+
+    /// Traverses the graph like `traverse_latest_path`, but at each hop follows the
+    /// outgoing edge with the highest `score` instead of the highest version.
+    ///
+    /// With `weighted_score`, recency is weighed by edge confidence, so a trusted
+    /// edit can win over a newer, less trusted one. With default weights that
+    /// reproduces `traverse_latest_path`. Stops before revisiting a node.
+    ///
+    /// Returns a vector of node indices representing the traversal path.
+    pub fn traverse_weighted<F>(&self, start_idx: usize, score: F) -> Vec<usize>
+    where
+        F: Fn(&Edge) -> f32,
+    {
+        let mut path = vec![start_idx];
+        let mut visited = std::collections::HashSet::from([start_idx]);
+        let mut current_idx = start_idx;
+
+        loop {
+            let next_edge = self
+                .traversal_edges(start_idx, current_idx)
+                .max_by(|(_, a), (_, b)| score(a).total_cmp(&score(b)));
+
+            match next_edge {
+                Some(((_, to_idx), _)) if visited.insert(*to_idx) => {
+                    path.push(*to_idx);
+                    current_idx = *to_idx;
+                }
+                _ => break,
+            }
+        }
+
+        path
+    }
+
+    /// Traverses only the directory structure below a node, without content.
+    ///
+    /// Follows structural edges that lead to DIR or FILE nodes, breadth-first, and
+    /// does not continue past FILE nodes into their content chains. Deleted files
+    /// are skipped. This is a cheap way to reconstruct the directory tree.
+    ///
+    /// # Returns
+    /// The start node followed by every reachable DIR and FILE node
+    pub fn traverse_structure_only(&self, start_idx: usize) -> Vec<usize> {
+        use std::collections::{HashSet, VecDeque};
+
+        let mut visited = HashSet::from([start_idx]);
+        let mut queue = VecDeque::from([start_idx]);
+        let mut structure = Vec::new();
+
+        while let Some(current_idx) = queue.pop_front() {
+            structure.push(current_idx);
+            if self.node_table.get_index(current_idx).is_some_and(Node::is_file) {
+                continue;
+            }
+
+            for ((_, to_idx), _) in self
+                .edge_table
+                .range((current_idx, usize::MIN)..(current_idx + 1, usize::MIN))
+            {
+                let Some(node) = self.node_table.get_index(*to_idx) else {
+                    continue;
+                };
+                let is_live_file = node.is_file() && !self.is_tombstoned(*to_idx);
+                if (node.is_directory() || is_live_file) && visited.insert(*to_idx) {
+                    queue.push_back(*to_idx);
+                }
+            }
+        }
+
+        structure
+    }
+
+    /// Finds the structural edge that traversal of the latest path follows from a
+    /// node: the outgoing edge with the highest version. On a version tie, the edge
+    /// to the highest node index wins. Every latest-path traversal steps through this,
+    /// so it answers exactly which edge they take, except for traversals from a file
+    /// node under `NodeIdentity::ContentOnly`; see `next_traversal_edge`.
+    ///
+    /// # Returns
+    /// The edge and its `(from, to)` key, or `None` at a node without outgoing edges
+    pub fn next_structural_edge(&self, idx: usize) -> Option<(&(usize, usize), &Edge)> {
+        self.edge_table
+            .range((idx, usize::MIN)..(idx + 1, usize::MIN))
+            .max_by_key(|(_, edge)| edge.version)
+    }
+
+    /// Finds the edge a latest-path traversal that started at `start_idx` follows
+    /// from `idx`, like `next_structural_edge` among the edges of `traversal_edges`.
+    pub fn next_traversal_edge(
+        &self,
+        start_idx: usize,
+        idx: usize,
+    ) -> Option<(&(usize, usize), &Edge)> {
+        self.traversal_edges(start_idx, idx)
+            .max_by_key(|(_, edge)| edge.version)
+    }
+
+    /// Returns the structural edges out of `idx` that a traversal started at
+    /// `start_idx` chooses from, sorted by key.
+    ///
+    /// Under `NodeIdentity::ContentOnly`, a traversal from a file node only sees the
+    /// file's own copies of the edges out of its content nodes, with the version and
+    /// tag the file wrote them with, so it never continues into another file's chain
+    /// through a shared line. Otherwise these are all of the node's outgoing edges.
+    pub fn traversal_edges(
+        &self,
+        start_idx: usize,
+        idx: usize,
+    ) -> Box<dyn Iterator<Item = (&(usize, usize), &Edge)> + '_> {
+        let per_file = self.node_identity == NodeIdentity::ContentOnly
+            && idx != start_idx
+            && self.node_table.get_index(start_idx).is_some_and(Node::is_file);
+        if !per_file {
+            return Box::new(self.edge_table.range((idx, usize::MIN)..(idx + 1, usize::MIN)));
+        }
+        Box::new(
+            self.file_edges
+                .range((start_idx, idx, usize::MIN)..(start_idx, idx + 1, usize::MIN))
+                .filter_map(move |((_, from_idx, to_idx), edge)| {
+                    let (edge_key, _) = self.edge_table.get_key_value(&(*from_idx, *to_idx))?;
+                    Some((edge_key, edge))
+                }),
+        )
+    }
+
+    /// Pretty prints the traversal path starting from a given node.
+    pub fn print_latest_path(&self, start_idx: usize) {
+        let path = self.traverse_latest_path(start_idx);
+
+        for idx in path {
+            println!("{:?}", self.node_table.get_index(idx));
+        }
+    }
+
+    /// Performs breadth-first search to find all nodes "contaminated" by a given node.
+    /// This follows the reference edges forward (from the given node to all nodes it influenced).
+    ///
+    /// # Arguments
+    /// * `start_idx` - The index of the node to start the search from
+    ///
+    /// # Returns
+    /// A vector of node indices that are contaminated (influenced) by the starting node
+    pub fn find_contaminated_nodes(&self, start_idx: usize) -> Vec<usize> {
+        self.find_contaminated_since(start_idx, i32::MIN)
+    }
+
+    /// Finds nodes contaminated by a given node, like `find_contaminated_nodes`, but only
+    /// follows reference edges with `version >= min_version`.
+    ///
+    /// This scopes impact analysis to recent changes, e.g. after merging in a newer
+    /// version of a source.
+    ///
+    /// # Returns
+    /// The starting node followed by the nodes it influenced through recent references
+    pub fn find_contaminated_since(&self, start_idx: usize, min_version: i32) -> Vec<usize> {
+        use std::collections::{HashSet, VecDeque};
+
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        let mut contaminated = Vec::new();
+
+        queue.push_back(start_idx);
+        visited.insert(start_idx);
+
+        while let Some(current_idx) = queue.pop_front() {
+            contaminated.push(current_idx);
+
+            // Find all outgoing reference edges from current node
+            for ((from_idx, to_idx), edge) in self
+                .ref_table
+                .range((current_idx, usize::MIN)..(current_idx + 1, usize::MIN))
+            {
+                if edge.version < min_version {
+                    continue;
+                }
+                if *from_idx == current_idx && !visited.contains(to_idx) {
+                    visited.insert(*to_idx);
+                    queue.push_back(*to_idx);
+                }
+            }
+        }
+
+        contaminated
+    }
+
+    /// Summarizes everything influenced by one or more source nodes, e.g. to report
+    /// the impact of a retracted source.
+    ///
+    /// Follows reference edges forward like `find_contaminated_nodes`, in a single
+    /// breadth-first pass that counts affected nodes per file and tracks depth as it
+    /// goes. Nodes without a filename (e.g. under `NodeIdentity::ContentOnly`) count
+    /// toward `affected_nodes` but not toward any file.
+    pub fn impact_report(&self, sources: &[usize]) -> ImpactReport {
+        use std::collections::{HashSet, VecDeque};
+
+        let mut visited: HashSet<usize> = sources.iter().copied().collect();
+        let mut queue: VecDeque<(usize, usize)> =
+            sources.iter().map(|source_idx| (*source_idx, 0)).collect();
+        let mut files: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut affected_nodes = 0;
+        let mut max_depth = 0;
+
+        while let Some((current_idx, depth)) = queue.pop_front() {
+            if depth > 0 {
+                affected_nodes += 1;
+                max_depth = max_depth.max(depth);
+                if let Some(node) = self.node_table.get_index(current_idx) {
+                    if !node.filename.is_empty() {
+                        *files.entry(node.filename.as_str()).or_insert(0) += 1;
+                    }
+                }
+            }
+
+            for (_, to_idx) in self
+                .ref_table
+                .range((current_idx, usize::MIN)..(current_idx + 1, usize::MIN))
+                .map(|(edge_key, _)| edge_key)
+            {
+                if visited.insert(*to_idx) {
+                    queue.push_back((*to_idx, depth + 1));
+                }
+            }
+        }
+
+        ImpactReport {
+            sources: sources.to_vec(),
+            affected_nodes,
+            files: files
+                .into_iter()
+                .map(|(filename, affected_nodes)| FileImpact {
+                    filename: filename.to_string(),
+                    affected_nodes,
+                })
+                .collect(),
+            max_depth,
+        }
+    }
+
+    /// Performs breadth-first search to find all nodes referenced by a given node.
+    /// This follows the reference edges backward (from the given node to all nodes that influenced it).
+    ///
+    /// # Arguments
+    /// * `start_idx` - The index of the node to start the search from
+    ///
+    /// # Returns
+    /// A vector of node indices that are referenced (influenced) the starting node
+    pub fn find_referenced_nodes(&self, start_idx: usize) -> Vec<usize> {
+        use std::collections::{HashSet, VecDeque};
+
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        let mut referenced = Vec::new();
+
+        queue.push_back(start_idx);
+        visited.insert(start_idx);
+
+        while let Some(current_idx) = queue.pop_front() {
+            referenced.push(current_idx);
+
+            // Find all incoming reference edges to current node
+            for from_idx in self.incoming_references(current_idx) {
+                if visited.insert(from_idx) {
+                    queue.push_back(from_idx);
+                }
+            }
+        }
+
+        referenced
+    }
+
+    /// Lists every node that influenced a file's latest content, directly or through
+    /// other references, with the lines it influenced. This is the citation list for
+    /// a whole document, from `find_referenced_nodes` over each line's node.
+    ///
+    /// # Returns
+    /// The references sorted by node index, or `None` if the file doesn't exist
+    pub fn file_references(&self, filename: &str) -> Option<Vec<FileReference>> {
+        let (_, node_indices) = self.read_file(filename)?;
+
+        let mut lines_by_reference: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (line_idx, node_idx) in node_indices.into_iter().enumerate() {
+            // The first node found is the line's own node
+            for reference_idx in self.find_referenced_nodes(node_idx).into_iter().skip(1) {
+                lines_by_reference
+                    .entry(reference_idx)
+                    .or_default()
+                    .push(line_idx + 1);
+            }
+        }
+
+        Some(
+            lines_by_reference
+                .into_iter()
+                .map(|(node_idx, lines)| FileReference { node_idx, lines })
+                .collect(),
+        )
+    }
+
+    /// Finds where a node sits in the latest content of every file, as 1-based positions
+    /// on the file's latest path, i.e. the line numbers used by `file_references`.
+    /// A node can have several positions: under `NodeIdentity::ContentOnly` a line shared
+    /// across files is one node, and a line repeated within a file is one node in any mode.
+    ///
+    /// Every file is reconstructed to find them, so this costs as much as reading all files.
+    ///
+    /// # Returns
+    /// `(filename, position)` pairs sorted by filename, then position. Empty if the node
+    /// is out of range or not on any file's latest path, e.g. a reference node.
+    pub fn node_positions(&self, idx: usize) -> Vec<(String, usize)> {
+        if idx >= self.node_table.len() {
+            return Vec::new();
+        }
+        let mut positions = Vec::new();
+        for filename in self.list_files() {
+            let Some((_, node_indices)) = self.read_file(&filename) else {
+                continue;
+            };
+            for (line_idx, node_idx) in node_indices.into_iter().enumerate() {
+                if node_idx == idx {
+                    positions.push((filename.clone(), line_idx + 1));
+                }
+            }
+        }
+        positions
+    }
+
+    /// Reads a file's latest content with a `## References` section appended after a
+    /// `---` rule, listing each node from `file_references` as
+    /// `- [n{idx}] {content} ({filename}), lines {lines}`. Content spanning several
+    /// lines is joined onto one. Without references, the section reads `None`.
+    ///
+    /// # Returns
+    /// The content with its references, or `None` if the file doesn't exist
+    pub fn read_file_with_references(&self, filename: &str) -> Option<String> {
+        let mut document = self.read_file_text(filename)?;
+        let references = self.file_references(filename)?;
+
+        document.push_str("\n\n---\n\n## References\n\n");
+        if references.is_empty() {
+            document.push_str("None");
+        }
+        let items: Vec<String> = references
+            .iter()
+            .filter_map(|reference| {
+                let node = self.node_table.get_index(reference.node_idx)?;
+                let content = node.display_content().replace('\n', " ");
+                let source = if node.filename.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", node.filename)
+                };
+                let lines: Vec<String> = reference.lines.iter().map(usize::to_string).collect();
+                Some(format!(
+                    "- [n{}] {}{}, lines {}",
+                    reference.node_idx,
+                    content,
+                    source,
+                    lines.join(", ")
+                ))
+            })
+            .collect();
+        document.push_str(&items.join("\n"));
+        Some(document)
+    }
+
+    /// Returns a file's stable document ID, see `seeded_document_id`. Unlike the index
+    /// of its file node, it is the same in every knowledge base with the same hash
+    /// seed and in `DataFusionKnowledgeBase::doc_id`. The file doesn't have to exist.
+    pub fn file_id(&self, filename: &str) -> u64 {
+        seeded_document_id(filename, self.hash_seed)
+    }
+
+    /// Returns the content IDs of the nodes at `indices`, skipping any that are
+    /// out of range. See `Node::content_id`.
+    pub fn content_ids(&self, indices: &[usize]) -> Vec<String> {
+        indices
+            .iter()
+            .filter_map(|idx| self.node_table.get_index(*idx))
+            .map(Node::content_id)
+            .collect()
+    }
+
+    /// Resolves content IDs to the nodes they address, in order. IDs of nodes
+    /// that aren't in the knowledge base resolve to `None`.
+    pub fn resolve_content_ids(&self, ids: &[String]) -> Vec<Option<&Node>> {
+        let wanted: std::collections::HashSet<&str> = ids.iter().map(String::as_str).collect();
+        let found: HashMap<String, &Node> = self
+            .node_table
+            .iter()
+            .map(|node| (node.content_id(), node))
+            .filter(|(id, _)| wanted.contains(id.as_str()))
+            .collect();
+        ids.iter().map(|id| found.get(id).copied()).collect()
+    }
+
+    /// Classifies a node like `Node::node_kind`, except that under
+    /// `NodeIdentity::ContentOnly`, where no content node has a filename, all
+    /// unprefixed nodes are `Content`.
+    pub fn node_kind(&self, idx: usize) -> Option<NodeKind> {
+        let kind = self.node_table.get_index(idx)?.node_kind();
+        Some(match (kind, self.node_identity) {
+            (NodeKind::Reference, NodeIdentity::ContentOnly) => NodeKind::Content,
+            (kind, _) => kind,
+        })
+    }
+
+    /// Returns every content node keyed to a file, in insertion order: the nodes of
+    /// all its versions and divergent branches, not just the latest path that
+    /// `read_file` returns. Marker nodes are excluded.
+    ///
+    /// Content nodes only carry a filename under `NodeIdentity::ContentAndFile`, so
+    /// this is always empty in content-only mode.
+    pub fn nodes_for_file(&self, filename: &str) -> Vec<usize> {
+        self.node_table
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.filename == filename && !node.is_marker())
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Finds content nodes containing the query, case-insensitively, in insertion order.
+    /// DIR/FILE marker nodes are excluded.
+    pub fn search(&self, query: &str) -> Vec<usize> {
+        let query = query.to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        self.node_table
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| !node.is_marker() && node.content.to_lowercase().contains(&query))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Finds content nodes matching a regular expression, in insertion order.
+    ///
+    /// The regex crate matches in linear time, so no pattern can backtrack
+    /// catastrophically; limiting the size of compiled patterns is up to the caller.
+    pub fn search_regex(&self, regex: &regex::Regex) -> Vec<usize> {
+        if regex.as_str().is_empty() {
+            return Vec::new();
+        }
+
+        self.node_table
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| !node.is_marker() && regex.is_match(&node.content))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Finds content nodes containing the query, case-insensitively, ranked by relevance.
+    ///
+    /// The score is TF-like: the fraction of the node's content covered by matches,
+    /// plus a small bonus for an earlier first match. Ties keep insertion order.
+    ///
+    /// # Arguments
+    /// * `query` - Substring to search for
+    /// * `limit` - Maximum number of results
+    ///
+    /// # Returns
+    /// Pairs of (node index, score), best match first
+    pub fn search_ranked(&self, query: &str, limit: usize) -> Vec<(usize, f32)> {
+        let query = query.to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let mut ranked: Vec<(usize, f32)> = self
+            .node_table
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| !node.is_marker())
+            .filter_map(|(idx, node)| {
+                let content = node.content.to_lowercase();
+                let first_match = content.find(&query)?;
+                let matches = content.matches(&query).count();
+
+                let len = content.len() as f32;
+                let coverage = (matches * query.len()) as f32 / len;
+                let position_bonus = 0.1 * (1.0 - first_match as f32 / len);
+                Some((idx, coverage + position_bonus))
+            })
+            .collect();
+
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(limit);
+        ranked
+    }
+
+    /// Finds the most referenced nodes, i.e. the sources that influenced the most nodes.
+    ///
+    /// # Arguments
+    /// * `n` - Maximum number of nodes to return
+    ///
+    /// # Returns
+    /// Up to `n` pairs of (node index, reference edge count), highest count first
+    pub fn top_referenced(&self, n: usize) -> Vec<(usize, usize)> {
+        let mut counts: BTreeMap<usize, usize> = BTreeMap::new();
+        for (from_idx, _) in self.ref_table.keys() {
+            *counts.entry(*from_idx).or_insert(0) += 1;
+        }
+
+        let mut ranked: Vec<(usize, usize)> = counts.into_iter().collect();
+        // Highest count first, ties broken by node index for stable output
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked.truncate(n);
+        ranked
+    }
+
+    /// Returns the number of nodes in the knowledge base.
+    pub fn node_count(&self) -> usize {
+        self.node_table.len()
+    }
+
+    /// Returns the number of edges in the knowledge base.
+    pub fn edge_count(&self) -> usize {
+        self.edge_table.len()
+    }
+
+    /// Lists all unique filenames in the knowledge base.
+    pub fn list_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = self
+            .node_table
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| !self.is_tombstoned(*idx))
+            .filter_map(|(_, node)| node.file_path())
+            .map(str::to_string)
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// Lists files whose latest change is newer than `version`, with that change's
+    /// version (see `max_version`), sorted by filename.
+    ///
+    /// Deleted files are included, since their tombstone is a change too, so a
+    /// replica polling this learns about deletes as well as writes.
+    pub fn files_changed_since(&self, version: i32) -> Vec<(String, i32)> {
+        let mut files: Vec<(String, i32)> = self
+            .node_table
+            .iter()
+            .filter_map(Node::file_path)
+            .filter_map(|filename| Some((filename.to_string(), self.max_version(filename)?)))
+            .filter(|(_, max_version)| *max_version > version)
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// Returns the size of a file's latest content as `(lines, bytes)`, matching
+    /// the text `read_file` reconstructs, or `None` if the file doesn't exist.
+    pub fn file_size(&self, filename: &str) -> Option<(usize, usize)> {
+        let file_idx = self.file_index(filename)?;
+        let (lines, bytes) = self
+            .traverse_latest_path(file_idx)
+            .iter()
+            .skip(1)
+            .filter_map(|idx| self.node_table.get_index(*idx))
+            .fold((0, 0), |(lines, bytes), node| (lines + 1, bytes + node.content.len()));
+
+        // Lines are joined with a newline between each pair
+        Some((lines, bytes + lines.saturating_sub(1)))
+    }
+
+    /// Returns the index of the file node for a filename, if the file exists
+    /// and has not been deleted.
+    pub fn file_index(&self, filename: &str) -> Option<usize> {
+        self.file_node_index(filename)
+            .filter(|file_idx| !self.is_tombstoned(*file_idx))
+    }
+
+    /// Returns the index of the file node for a filename, including deleted files.
+    fn file_node_index(&self, filename: &str) -> Option<usize> {
+        self.node_table.get_index_of(&Node::file(filename))
+    }
+
+    /// Checks whether a file was deleted and not written again since.
+    pub fn is_deleted(&self, filename: &str) -> bool {
+        self.file_node_index(filename)
+            .is_some_and(|file_idx| self.is_tombstoned(file_idx))
+    }
+
+    /// Returns the index of a file's tombstone node, if it was ever deleted.
+    fn tombstone_index(&self, filename: &str) -> Option<usize> {
+        self.node_table.get_index_of(&Node::tombstone(filename))
+    }
+
+    /// Checks whether a file node's latest edge is a tombstone.
+    fn is_tombstoned(&self, file_idx: usize) -> bool {
+        self.next_structural_edge(file_idx)
+            .is_some_and(|(_, edge)| edge.tag == TOMBSTONE_TAG)
+    }
+
+    /// Deletes a file by pointing its file node at a tombstone node with a new,
+    /// highest version. The history stays in the graph, and writing the file
+    /// again removes the tombstone.
+    ///
+    /// # Returns
+    /// Whether the file was deleted, was already deleted, or never existed
+    pub fn delete_file(&mut self, filename: &str) -> DeleteOutcome {
+        self.delete_file_with_version(filename, None)
+    }
+
+    /// Deletes a file like `delete_file`, at the given version instead of `next_version`.
+    fn delete_file_with_version(&mut self, filename: &str, version: Option<i32>) -> DeleteOutcome {
+        let Some(file_idx) = self.file_node_index(filename) else {
+            return DeleteOutcome::NotFound;
+        };
+        if self.is_tombstoned(file_idx) {
+            return DeleteOutcome::AlreadyDeleted;
+        }
+
+        let version = version.unwrap_or_else(|| self.next_version());
+        let tombstone_idx = self.push_node(Node::tombstone(filename)).0;
+        // Overwrite any earlier tombstone edge so the delete wins LWW
+        self.set_edge(
+            EdgeTable::Structural,
+            (file_idx, tombstone_idx),
+            Edge::new(version, TOMBSTONE_TAG.to_string()),
+        );
+
+        DeleteOutcome::Deleted { version }
+    }
+
+    /// Applies several operations atomically, all at one shared version.
+    ///
+    /// Operations are applied in order, keeping the prior state of every edge they
+    /// change; on the first failure those edges are put back and the nodes added since
+    /// the start are dropped, so nothing is changed. A transaction therefore costs as
+    /// much as its operations, whatever the size of the knowledge base. Each file may
+    /// be written or deleted at most once per transaction, since two edits at the same
+    /// version would tie under LWW.
+    ///
+    /// # Returns
+    /// The shared version and one result per operation, or the first failure
+    pub fn apply_transaction(
+        &mut self,
+        ops: Vec<TransactionOp>,
+        options: &IngestOptions,
+    ) -> Result<(i32, Vec<TransactionOpResult>), TransactionError> {
+        let version = self.next_version();
+        let node_count = self.node_table.len();
+        let version_floor = self.version_floor;
+        let change_count = self.change_count;
+        let journal_len = self.journal.as_ref().map(Vec::len);
+
+        self.undo_log = Some(Vec::new());
+        let results = self.apply_transaction_ops(ops, options, version);
+        let undo_log = self.undo_log.take().unwrap_or_default();
+        if results.is_err() {
+            self.undo(undo_log);
+            self.node_table.truncate(node_count);
+            self.dedup_index.retain(|_, idx| *idx < node_count);
+            self.read_counts.retain(|idx, _| *idx < node_count);
+            self.version_floor = version_floor;
+            self.change_count = change_count;
+            if let (Some(journal), Some(journal_len)) = (&mut self.journal, journal_len) {
+                journal.truncate(journal_len);
+            }
+        }
+        results.map(|results| (version, results))
+    }
+
+    /// Puts back the edges changed since an undo log was started, newest change first.
+    fn undo(&mut self, undo_log: Vec<Undo>) {
+        for undo in undo_log.into_iter().rev() {
+            match undo {
+                Undo::Edge {
+                    table,
+                    edge_key,
+                    previous,
+                } => {
+                    if table == EdgeTable::Reference {
+                        let reverse_key = (edge_key.1, edge_key.0);
+                        if previous.is_some() {
+                            self.ref_index.insert(reverse_key);
+                        } else {
+                            self.ref_index.remove(&reverse_key);
+                        }
+                    }
+                    match previous {
+                        Some(edge) => self.table_mut(table).insert(edge_key, edge),
+                        None => self.table_mut(table).remove(&edge_key),
+                    };
+                }
+                Undo::FileEdge { key, previous } => {
+                    match previous {
+                        Some(edge) => self.file_edges.insert(key, edge),
+                        None => self.file_edges.remove(&key),
+                    };
+                }
+            }
+        }
+    }
+
+    /// Applies the operations of `apply_transaction` at `version`, stopping at the
+    /// first failure.
+    fn apply_transaction_ops(
+        &mut self,
+        ops: Vec<TransactionOp>,
+        options: &IngestOptions,
+        version: i32,
+    ) -> Result<Vec<TransactionOpResult>, TransactionError> {
+        let tag = format!("version-{}", version);
+        let mut touched_paths = std::collections::HashSet::new();
+        let mut results = Vec::new();
+
+        for (op_index, op) in ops.into_iter().enumerate() {
+            let fail = |error| TransactionError { op_index, error };
+
+            let result = match op {
+                TransactionOp::Write {
+                    path,
+                    content,
+                    reference_nodes,
+                } => {
+                    if !touched_paths.insert(path.clone()) {
+                        return Err(fail(TransactionOpError::DuplicatePath));
+                    }
+                    let prepared = Self::prepare_content(&content, options)
+                        .map_err(|err| fail(TransactionOpError::Ingest(err)))?;
+                    let stats = self.write_file_with_version(
+                        &path,
+                        prepared,
+                        reference_nodes,
+                        options,
+                        Some(version),
+                    );
+                    TransactionOpResult::Write { path, stats }
+                }
+                TransactionOp::Delete { path } => {
+                    if !touched_paths.insert(path.clone()) {
+                        return Err(fail(TransactionOpError::DuplicatePath));
+                    }
+                    match self.delete_file_with_version(&path, Some(version)) {
+                        DeleteOutcome::Deleted { .. } => TransactionOpResult::Delete { path },
+                        DeleteOutcome::NotFound => {
+                            return Err(fail(TransactionOpError::FileNotFound));
+                        }
+                        DeleteOutcome::AlreadyDeleted => {
+                            return Err(fail(TransactionOpError::AlreadyDeleted));
+                        }
+                    }
+                }
+                TransactionOp::Reference { from, to } => {
+                    if from >= self.node_table.len() || to >= self.node_table.len() {
+                        return Err(fail(TransactionOpError::NodeNotFound));
+                    }
+                    let added = !self.ref_table.contains_key(&(from, to));
+                    self.insert_edge(
+                        EdgeTable::Reference,
+                        (from, to),
+                        Edge::new(version, tag.clone()),
+                        options.edge_insert_policy,
+                    );
+                    TransactionOpResult::Reference { from, to, added }
+                }
+            };
+            results.push(result);
+        }
+
+        Ok(results)
+    }
+
+    /// Creates many reference edges at one version, e.g. provenance computed by an
+    /// external pipeline.
+    ///
+    /// Each `from` content resolves to the first non-marker node with that content,
+    /// or to a new reference node without a filename if there is none. Edges that
+    /// already exist are left untouched. All `to` indices are checked before anything
+    /// is applied.
+    ///
+    /// # Returns
+    /// Counts of created and skipped edges, or the first reference with an
+    /// out-of-range `to`
+    pub fn assert_references(
+        &mut self,
+        references: &[BulkReference],
+    ) -> Result<BulkReferenceStats, BulkReferenceError> {
+        if let Some((index, reference)) = references
+            .iter()
+            .enumerate()
+            .find(|(_, reference)| reference.to >= self.node_table.len())
+        {
+            return Err(BulkReferenceError { index, to: reference.to });
+        }
+
+        // Resolve every distinct `from` content with a single scan of the node table
+        let mut from_indices: HashMap<&str, Option<usize>> = references
+            .iter()
+            .map(|reference| (reference.from.as_str(), None))
+            .collect();
+        for (idx, node) in self.node_table.iter().enumerate() {
+            if node.is_marker() {
+                continue;
+            }
+            if let Some(slot @ None) = from_indices.get_mut(node.content.as_str()) {
+                *slot = Some(idx);
+            }
+        }
+
+        let version = self.next_version();
+        let edge = Edge::new(version, format!("version-{}", version));
+        let nodes_before = self.node_table.len();
+        let mut stats = BulkReferenceStats {
+            version,
+            created: 0,
+            skipped: 0,
+            nodes_added: 0,
+        };
+
+        for reference in references {
+            let from_idx = match from_indices[reference.from.as_str()] {
+                Some(idx) => idx,
+                None => {
+                    let node = Node::new(reference.from.clone(), String::new());
+                    let idx = self.push_node(node).0;
+                    from_indices.insert(reference.from.as_str(), Some(idx));
+                    idx
+                }
+            };
+            let edge_key = (from_idx, reference.to);
+            if self.ref_table.contains_key(&edge_key) {
+                stats.skipped += 1;
+            } else {
+                self.set_edge(EdgeTable::Reference, edge_key, edge.clone());
+                stats.created += 1;
+            }
+        }
+
+        stats.nodes_added = self.node_table.len() - nodes_before;
+        Ok(stats)
+    }
+
+    /// Renames a file, keeping its history reachable through provenance.
+    ///
+    /// Since the filename is part of every node, the latest content is written as a
+    /// new chain under `new_filename` at a new version, one node per old node, so
+    /// content ingested with another chunker keeps its granularity. Each old node gets
+    /// a reference edge to the node that replaces it, so provenance and contamination
+    /// searches lead back to the old chain. The old file is then deleted with a tombstone.
+    ///
+    /// # Returns
+    /// Statistics about the new file's ingest, including the linking references
+    pub fn rename_file(
+        &mut self,
+        old_filename: &str,
+        new_filename: &str,
+    ) -> Result<IngestStats, RenameError> {
+        let (_, old_indices) = self
+            .read_file(old_filename)
+            .ok_or(RenameError::NotFound)?;
+        if self.file_index(new_filename).is_some() {
+            return Err(RenameError::AlreadyExists);
+        }
+
+        // The content was accepted once already, so it is neither re-chunked nor
+        // re-checked against the cap
+        let prepared = PreparedContent {
+            lines: old_indices
+                .iter()
+                .map(|idx| self.node_table[*idx].content.clone())
+                .collect(),
+            split_chunks: old_indices
+                .iter()
+                .enumerate()
+                .filter(|(_, idx)| self.node_table[**idx].position.is_some())
+                .map(|(position, _)| position)
+                .collect(),
+        };
+        let mut stats = self.write_file_with_version(
+            new_filename,
+            prepared,
+            Vec::new(),
+            &IngestOptions::default(),
+            None,
+        );
+
+        let (_, new_indices) = self.read_file(new_filename).unwrap_or_default();
+        for (old_idx, new_idx) in old_indices.into_iter().zip(new_indices) {
+            // Content-only nodes are shared, so an unchanged line needs no link
+            if old_idx == new_idx {
+                continue;
+            }
+            if self
+                .set_edge(
+                    EdgeTable::Reference,
+                    (old_idx, new_idx),
+                    Edge::new(stats.version, format!("version-{}", stats.version)),
+                )
+                .is_none()
+            {
+                stats.references_added += 1;
+            }
+        }
+
+        self.delete_file(old_filename);
+        Ok(stats)
+    }
+
+    /// Reconciles a file's latest content to a single chunk granularity, e.g. after it
+    /// was ingested line by line and later paragraph by paragraph.
+    ///
+    /// The nodes of the latest path are re-chunked with `chunker` and written as a new
+    /// chain at a new version, merging nodes the chunker puts together and splitting
+    /// nodes it breaks apart. Nodes the chunker leaves as they are are reused. Each old
+    /// node gets a reference edge to every new node that took over part of its
+    /// content, so provenance and contamination searches lead back through it, as
+    /// with `rename_file`.
+    ///
+    /// This is lossy:
+    /// - Ingest drops blank lines, so the paragraph breaks between line nodes are
+    ///   gone. Adjacent single-line nodes are joined by a newline and end up in one
+    ///   paragraph, while a node spanning several lines is kept apart from its
+    ///   neighbours by a blank line.
+    /// - Only the latest path is rewritten. Older versions and divergent branches
+    ///   keep their granularity and can still be read at their versions.
+    /// - A merged node inherits the provenance of all its parts, so a reference that
+    ///   influenced one line now reaches the whole paragraph.
+    ///
+    /// # Returns
+    /// Statistics about the rewrite, or `None` if the file doesn't exist
+    pub fn normalize_granularity(
+        &mut self,
+        filename: &str,
+        chunker: &dyn Chunker,
+    ) -> Option<IngestStats> {
+        let (_, old_indices) = self.read_file(filename)?;
+
+        // Rebuild the text, remembering the byte range each old node occupies in it
+        let mut text = String::new();
+        let mut old_spans = Vec::with_capacity(old_indices.len());
+        let mut previous_multiline = false;
+        for (position, old_idx) in old_indices.iter().enumerate() {
+            let content = &self.node_table[*old_idx].content;
+            let multiline = content.contains('\n');
+            if position > 0 {
+                text.push_str(if multiline || previous_multiline { "\n\n" } else { "\n" });
+            }
+            old_spans.push((*old_idx, text.len()..text.len() + content.len()));
+            text.push_str(content);
+            previous_multiline = multiline;
+        }
+
+        // Chunks are slices of `text`, so their address gives their byte range
+        let chunks: Vec<(String, std::ops::Range<usize>)> = chunker
+            .chunk(&text)
+            .into_iter()
+            .filter(|chunk| !chunk.is_empty())
+            .map(|chunk| {
+                let start = (chunk.as_ptr() as usize).saturating_sub(text.as_ptr() as usize);
+                (chunk.to_string(), start..start + chunk.len())
+            })
+            .collect();
+
+        let prepared = PreparedContent {
+            lines: chunks.iter().map(|(chunk, _)| chunk.clone()).collect(),
+            split_chunks: BTreeSet::new(),
+        };
+        // The content was accepted once already, so it is not re-checked against the cap
+        let mut stats = self
+            .write_prepared(filename, prepared, Vec::new(), &IngestOptions::default(), None, false)
+            .expect("default ingest options never reject content");
+
+        let (_, new_indices) = self.read_file(filename).unwrap_or_default();
+        let edge = Edge::new(stats.version, format!("version-{}", stats.version));
+        for ((_, range), new_idx) in chunks.iter().zip(new_indices) {
+            for (old_idx, old_range) in &old_spans {
+                let overlaps = old_range.start < range.end && range.start < old_range.end;
+                // A node the chunker left as it was needs no link to itself
+                if !overlaps || *old_idx == new_idx {
+                    continue;
+                }
+                if self
+                    .set_edge(EdgeTable::Reference, (*old_idx, new_idx), edge.clone())
+                    .is_none()
+                {
+                    stats.references_added += 1;
+                }
+            }
+        }
+
+        Some(stats)
+    }
+
+    /// Reconstructs a markdown file from the knowledge base by traversing from a file node.
+    /// Returns both the markdown content and the node indices that composed it.
+    pub fn read_file(&self, filename: &str) -> Option<(String, Vec<usize>)> {
+        // Find the file node
+        let file_idx = self.file_index(filename)?;
+
+        // Traverse from the file node to get all content
+        let path = self.traverse_latest_path(file_idx);
+        
+        // Skip the first node (FILE node itself) and collect content
+        let mut node_indices = Vec::new();
+        let mut markdown_parts = Vec::new();
+        
+        for idx in path.iter().skip(1) {
+            if let Some(node) = self.node_table.get_index(*idx) {
+                let content = &node.content;
+                markdown_parts.push(content.clone());
+                node_indices.push(*idx);
+            }
+        }
+
+        // Join markdown lines back together
+        let markdown = markdown_parts.join("\n");
+        Some((markdown, node_indices))
+    }
+
+    /// Reads a file like `read_file`, but follows the latest path like
+    /// `traverse_latest_path_with_meta` and also returns its `TraversalMeta`. The
+    /// content and the metadata come from one traversal, so they always agree.
+    pub fn read_file_with_meta(&self, filename: &str) -> Option<(String, Vec<usize>, TraversalMeta)> {
+        let file_idx = self.file_index(filename)?;
+        let (path, meta) = self.traverse_latest_path_with_meta(file_idx);
+
+        // Skip the FILE node itself
+        let node_indices: Vec<usize> = path.into_iter().skip(1).collect();
+        let markdown = node_indices
+            .iter()
+            .map(|idx| self.node_table[*idx].content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some((markdown, node_indices, meta))
+    }
+
+    /// Reconstructs the content downstream of any node, such as a section heading.
+    ///
+    /// Follows the latest path from `start_idx` like `traverse_latest_path_with_meta`,
+    /// so cycles, tombstones, and `MAX_TRAVERSAL_HOPS` end it, and joins the content
+    /// of the nodes on it, skipping DIR/FILE/TOMBSTONE markers. Starting from a FILE
+    /// node gives the same text as `read_file_text`.
+    ///
+    /// # Returns
+    /// The joined content and traversal metadata, or `None` if `start_idx` is out of range
+    pub fn reconstruct_from(&self, start_idx: usize) -> Option<(String, TraversalMeta)> {
+        if start_idx >= self.node_table.len() {
+            return None;
+        }
+
+        let (path, meta) = self.traverse_latest_path_with_meta(start_idx);
+        let content = path
+            .iter()
+            .filter_map(|idx| self.node_table.get_index(*idx))
+            .filter(|node| !node.is_marker())
+            .map(|node| node.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some((content, meta))
+    }
+
+    /// Reconstructs a file as it was at a version: at every hop, only edges with at
+    /// most that version are considered. Deleted files can be read at versions
+    /// before their deletion.
+    ///
+    /// # Returns
+    /// The content, or `None` if the file had no content or was deleted at that version
+    pub fn read_file_at_version(&self, filename: &str, version: i32) -> Option<String> {
+        let file_idx = self.file_node_index(filename)?;
+        let next_edge = |idx: usize| {
+            self.traversal_edges(file_idx, idx)
+                .filter(|(_, edge)| edge.version <= version)
+                .max_by_key(|(_, edge)| edge.version)
+        };
+
+        let mut current = next_edge(file_idx);
+        if current.is_none_or(|(_, edge)| edge.tag == TOMBSTONE_TAG) {
+            return None;
+        }
+        let mut lines = Vec::new();
+        let mut visited = std::collections::HashSet::from([file_idx]);
+        while let Some(((_, to_idx), _)) = current {
+            // Stop on a cycle, like `traverse_latest_edges`
+            if !visited.insert(*to_idx) {
+                break;
+            }
+            if let Some(node) = self.node_table.get_index(*to_idx) {
+                lines.push(node.content.as_str());
+            }
+            current = next_edge(*to_idx);
+        }
+
+        Some(lines.join("\n"))
+    }
+
+    /// Names a file's current state, so it can be read back later with
+    /// `read_file_at_checkpoint` without remembering its version. Setting an
+    /// existing name again moves it to the current state.
+    ///
+    /// # Returns
+    /// The version the checkpoint refers to, or `None` if the file doesn't exist
+    pub fn checkpoint(&mut self, filename: &str, name: &str) -> Option<i32> {
+        self.file_index(filename)?;
+        let version = self.max_version(filename)?;
+        self.record(|| WalRecord::Checkpoint {
+            filename: filename.to_string(),
+            name: name.to_string(),
+            version,
+        });
+        self.checkpoints
+            .insert((filename.to_string(), name.to_string()), version);
+        Some(version)
+    }
+
+    /// Returns the version of a named checkpoint of a file.
+    pub fn checkpoint_version(&self, filename: &str, name: &str) -> Option<i32> {
+        self.checkpoints
+            .get(&(filename.to_string(), name.to_string()))
+            .copied()
+    }
+
+    /// Lists a file's checkpoints as `(name, version)` pairs, oldest version first.
+    pub fn checkpoints(&self, filename: &str) -> Vec<(String, i32)> {
+        let mut checkpoints: Vec<(String, i32)> = self
+            .checkpoints
+            .iter()
+            .filter(|((checkpoint_file, _), _)| checkpoint_file == filename)
+            .map(|((_, name), version)| (name.clone(), *version))
+            .collect();
+        checkpoints.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        checkpoints
+    }
+
+    /// Reconstructs a file as it was when a named checkpoint was set.
+    ///
+    /// # Returns
+    /// The content, or `None` if there is no such checkpoint
+    pub fn read_file_at_checkpoint(&self, filename: &str, name: &str) -> Option<String> {
+        let version = self.checkpoint_version(filename, name)?;
+        self.read_file_at_version(filename, version)
+    }
+
+    /// Reconstructs only the text of a markdown file, without collecting node indices.
+    /// Use this instead of `read_file` when provenance is not needed.
+    pub fn read_file_text(&self, filename: &str) -> Option<String> {
+        let file_idx = self.file_index(filename)?;
+
+        // Walk the latest path directly, skipping the FILE node itself
+        let mut markdown = String::new();
+        let mut visited = std::collections::HashSet::from([file_idx]);
+        let mut current = self.next_traversal_edge(file_idx, file_idx);
+        while let Some(((_, to_idx), _)) = current {
+            // Stop on a cycle, like `traverse_latest_edges`
+            if !visited.insert(*to_idx) {
+                break;
+            }
+            if let Some(node) = self.node_table.get_index(*to_idx) {
+                if !markdown.is_empty() {
+                    markdown.push('\n');
+                }
+                markdown.push_str(&node.content);
+            }
+            current = self.next_traversal_edge(file_idx, *to_idx);
+        }
+
+        Some(markdown)
+    }
+
+    /// Reconstructs a file like `read_file_text`, with the content of each node
+    /// replaced by its `redactor` placeholder, one node per line.
+    pub fn read_file_redacted(&self, filename: &str, redactor: &mut Redactor) -> Option<String> {
+        let (_, node_indices) = self.read_file(filename)?;
+        let lines: Vec<String> = node_indices
+            .into_iter()
+            .map(|idx| redactor.redact(&self.node_table[idx].content))
+            .collect();
+        Some(lines.join("\n"))
+    }
+
+    /// Counts a read of a file. Takes `&self` and is a single atomic increment, so
+    /// it's cheap to call for every read under a shared lock.
+    ///
+    /// # Returns
+    /// False if the file doesn't exist
+    pub fn record_read(&self, filename: &str) -> bool {
+        let read_count = self
+            .file_index(filename)
+            .and_then(|file_idx| self.read_counts.get(&file_idx));
+        if let Some(read_count) = read_count {
+            read_count.count.fetch_add(1, Ordering::Relaxed);
+        }
+        read_count.is_some()
+    }
+
+    /// Returns how often a file was read, as counted by `record_read`.
+    pub fn read_count(&self, filename: &str) -> u64 {
+        self.file_node_index(filename)
+            .and_then(|file_idx| self.read_counts.get(&file_idx))
+            .map_or(0, |read_count| read_count.count.load(Ordering::Relaxed))
+    }
+
+    /// Lists the `n` most read existing files with their read counts, most read
+    /// first and ties by filename. Files that were never read are left out.
+    pub fn most_read_files(&self, n: usize) -> Vec<(String, u64)> {
+        let mut files: Vec<(String, u64)> = self
+            .read_counts
+            .iter()
+            .filter(|(file_idx, _)| !self.is_tombstoned(**file_idx))
+            .filter_map(|(file_idx, read_count)| {
+                let count = read_count.count.load(Ordering::Relaxed);
+                let node = self.node_table.get_index(*file_idx)?;
+                (count > 0).then(|| (node.filename.clone(), count))
+            })
+            .collect();
+        files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        files.truncate(n);
+        files
+    }
+
+    /// Diffs the latest content of two files line by line, from `old` to `new`.
+    ///
+    /// # Returns
+    /// The diff from `diff_lines`, or `None` if either file doesn't exist
+    pub fn diff_files(&self, old: &str, new: &str) -> Option<Vec<DiffLine>> {
+        let old_text = self.read_file_text(old)?;
+        let new_text = self.read_file_text(new)?;
+        Some(diff_text(&old_text, &new_text))
+    }
+
+    /// Reconstructs a file with each line annotated by the edge that led to it,
+    /// following the latest path like `read_file_with_meta`.
+    ///
+    /// # Returns
+    /// `(content, version, tag)` per line of the latest path and the traversal's
+    /// `TraversalMeta`, or `None` if the file doesn't exist
+    pub fn read_file_annotated(
+        &self,
+        filename: &str,
+    ) -> Option<(Vec<(String, i32, String)>, TraversalMeta)> {
+        let file_idx = self.file_index(filename)?;
+        let (path, meta) = self.traverse_latest_path_with_meta(file_idx);
+
+        let lines = path
+            .windows(2)
+            .filter_map(|hop| {
+                let (_, edge) = self
+                    .traversal_edges(file_idx, hop[0])
+                    .find(|((_, to_idx), _)| *to_idx == hop[1])?;
+                let node = self.node_table.get_index(hop[1])?;
+                Some((node.content.clone(), edge.version, edge.tag.clone()))
+            })
+            .collect();
+        Some((lines, meta))
+    }
+
+    /// Collects every structural edge reachable from a node via breadth-first search.
+    fn reachable_edges(&self, start_idx: usize) -> Vec<(&(usize, usize), &Edge)> {
+        use std::collections::{HashSet, VecDeque};
+
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        let mut edges = Vec::new();
+
+        queue.push_back(start_idx);
+        visited.insert(start_idx);
+
+        while let Some(current_idx) = queue.pop_front() {
+            for (key, edge) in self
+                .edge_table
+                .range((current_idx, usize::MIN)..(current_idx + 1, usize::MIN))
+            {
+                edges.push((key, edge));
+                if visited.insert(key.1) {
+                    queue.push_back(key.1);
+                }
+            }
+        }
+
+        edges
+    }
+
+    /// Renders a file's subgraph as a Graphviz DOT document, to visualize one
+    /// document's version graph in isolation.
+    ///
+    /// The subgraph holds every node reachable from the file node through structural
+    /// edges, including divergent branches and a tombstone, plus the reference edges
+    /// into or out of those nodes and the nodes at their other ends. Structural edges
+    /// are labeled with their version and tag; reference edges are dashed. Labels
+    /// are cut to `DOT_LABEL_MAX_CHARS` characters.
+    ///
+    /// With `redact`, content nodes are labeled `redacted-N` instead, the same N for
+    /// the same content, so the topology can be shared without the text.
+    ///
+    /// # Returns
+    /// The DOT source, or `None` if the file was never written
+    pub fn file_to_dot(&self, filename: &str, redact: bool) -> Option<String> {
+        use std::fmt::Write;
+
+        let file_idx = self.file_node_index(filename)?;
+        let structural = self.reachable_edges(file_idx);
+        let mut chain = BTreeSet::from([file_idx]);
+        chain.extend(structural.iter().map(|((_, to_idx), _)| *to_idx));
+        let references: Vec<_> = self
+            .ref_table
+            .iter()
+            .filter(|((from_idx, to_idx), _)| chain.contains(from_idx) || chain.contains(to_idx))
+            .collect();
+        let mut nodes = chain.clone();
+        nodes.extend(references.iter().flat_map(|((from_idx, to_idx), _)| [*from_idx, *to_idx]));
+
+        let mut redactor = redact.then(Redactor::default);
+        let mut dot = String::new();
+        writeln!(dot, "digraph \"{}\" {{", dot_escape(filename)).unwrap();
+        for idx in nodes {
+            let Some(node) = self.node_table.get_index(idx) else {
+                continue;
+            };
+            // Markers only hold paths, so they are never redacted
+            let label: String = match redactor.as_mut() {
+                Some(redactor) if node.kind.is_content() => redactor.redact(&node.content),
+                _ => node.content.chars().take(DOT_LABEL_MAX_CHARS).collect(),
+            };
+            // Nodes outside the file are referenced from or by it
+            let style = if chain.contains(&idx) { "" } else { ", style=dotted" };
+            writeln!(dot, "  n{} [label=\"{}\"{}];", idx, dot_escape(&label), style).unwrap();
+        }
+        for ((from_idx, to_idx), edge) in structural {
+            let label = format!("v{} {}", edge.version, edge.tag);
+            writeln!(dot, "  n{} -> n{} [label=\"{}\"];", from_idx, to_idx, dot_escape(&label))
+                .unwrap();
+        }
+        for ((from_idx, to_idx), _) in references {
+            writeln!(dot, "  n{} -> n{} [style=dashed];", from_idx, to_idx).unwrap();
+        }
+        dot.push_str("}\n");
+        Some(dot)
+    }
+
+    /// Exports the reference graph as a W3C PROV-JSON document.
+    ///
+    /// Every node with a reference edge becomes an entity `liasion:n{idx}`, labelled
+    /// with its content. Every reference edge `from -> to` becomes a `wasDerivedFrom`
+    /// with `to` as the generated and `from` as the used entity. PROV times must be
+    /// dates, so the edge version, the graph's logical generation time, is recorded
+    /// as the `liasion:version` attribute.
+    ///
+    /// With `redact`, content nodes are labelled `redacted-N` instead, as in
+    /// `file_to_dot`; indices, kinds, filenames, and versions are kept.
+    pub fn to_prov_json(&self, redact: bool) -> serde_json::Value {
+        let entity_id = |idx: usize| format!("liasion:n{}", idx);
+        let mut redactor = redact.then(Redactor::default);
+
+        let mut entities = serde_json::Map::new();
+        let nodes: BTreeSet<usize> = self
+            .ref_table
+            .keys()
+            .flat_map(|(from_idx, to_idx)| [*from_idx, *to_idx])
+            .collect();
+        for idx in nodes {
+            let Some(node) = self.node_table.get_index(idx) else {
+                continue;
+            };
+            let label = match redactor.as_mut() {
+                Some(redactor) if node.kind.is_content() => redactor.redact(&node.content),
+                _ => node.display_content().to_string(),
+            };
+            let mut entity = serde_json::json!({
+                "prov:label": label,
+                "liasion:kind": self.node_kind(idx),
+            });
+            if !node.filename.is_empty() {
+                entity["liasion:filename"] = serde_json::json!(node.filename);
+            }
+            entities.insert(entity_id(idx), entity);
+        }
+
+        let mut derivations = serde_json::Map::new();
+        for ((from_idx, to_idx), edge) in &self.ref_table {
+            derivations.insert(
+                format!("_:d{}_{}", from_idx, to_idx),
+                serde_json::json!({
+                    "prov:generatedEntity": entity_id(*to_idx),
+                    "prov:usedEntity": entity_id(*from_idx),
+                    "liasion:version": edge.version,
+                    "liasion:tag": edge.tag,
+                }),
+            );
+        }
+
+        serde_json::json!({
+            "prefix": { "liasion": "urn:liasiondb:" },
+            "entity": entities,
+            "wasDerivedFrom": derivations,
+        })
+    }
+
+    /// Counts how many structural edges of a file's chain were introduced at each version.
+    ///
+    /// Scans all edges reachable from the file node, so divergent versions are included,
+    /// not just the latest path. Returns an empty map if the file doesn't exist.
+    pub fn version_histogram(&self, filename: &str) -> BTreeMap<i32, usize> {
+        let mut histogram = BTreeMap::new();
+        if let Some(file_idx) = self.file_index(filename) {
+            for (_, edge) in self.reachable_edges(file_idx) {
+                *histogram.entry(edge.version).or_insert(0) += 1;
+            }
+        }
+        histogram
+    }
+
+    /// Returns true if a file's versions have diverged, i.e. some node reachable from
+    /// the file node has more than one outgoing structural edge. A tombstone edge
+    /// doesn't count as a fork. Returns false for files that were never written.
+    pub fn is_forked(&self, filename: &str) -> bool {
+        let Some(file_idx) = self.file_node_index(filename) else {
+            return false;
+        };
+        // `reachable_edges` lists each node's outgoing edges together, once
+        let mut from_nodes = std::collections::HashSet::new();
+        self.reachable_edges(file_idx)
+            .into_iter()
+            .filter(|(_, edge)| edge.tag != TOMBSTONE_TAG)
+            .any(|((from_idx, _), _)| !from_nodes.insert(*from_idx))
+    }
+
+    /// Finds divergent branches of a file that fork off the latest path and never rejoin it.
+    ///
+    /// Each branch is returned as a path starting at the latest-path node it forks from
+    /// and ending at a node with no outgoing structural edges. Branches that lead back
+    /// onto the latest path are not dead and are not returned. Nodes shared by several
+    /// branches are only walked once.
+    pub fn dead_branches(&self, filename: &str) -> Vec<Vec<usize>> {
+        use std::collections::HashSet;
+
+        let Some(file_idx) = self.file_index(filename) else {
+            return Vec::new();
+        };
+
+        let latest = self.traverse_latest_path(file_idx);
+        let on_latest: HashSet<usize> = latest.iter().copied().collect();
+        let edge_table = &self.edge_table;
+        let successors = move |idx: usize| {
+            edge_table
+                .range((idx, usize::MIN)..(idx + 1, usize::MIN))
+                .map(|((_, to_idx), _)| *to_idx)
+        };
+
+        let mut visited = HashSet::new();
+        let mut dead = Vec::new();
+
+        for &fork_idx in &latest {
+            for branch_idx in successors(fork_idx) {
+                if on_latest.contains(&branch_idx) {
+                    continue;
+                }
+
+                // Depth-first walk of the branch, carrying the path taken so far
+                let mut stack = vec![vec![fork_idx, branch_idx]];
+                while let Some(path) = stack.pop() {
+                    let tail = *path.last().unwrap();
+                    if !visited.insert(tail) {
+                        continue;
+                    }
+
+                    let next: Vec<usize> = successors(tail).collect();
+                    if next.is_empty() {
+                        dead.push(path);
+                        continue;
+                    }
+
+                    // Successors on the latest path rejoin it, so they end this walk
+                    for next_idx in next {
+                        if !on_latest.contains(&next_idx) && !visited.contains(&next_idx) {
+                            let mut extended = path.clone();
+                            extended.push(next_idx);
+                            stack.push(extended);
+                        }
+                    }
+                }
+            }
+        }
+
+        dead
+    }
+
+    /// Builds the full provenance tree of a node, combining reverse structural traversal
+    /// (how it fits in its document) with reference ancestry (what influenced it).
+    ///
+    /// # Returns
+    /// The provenance tree, or `None` if the index is out of range
+    pub fn provenance(&self, idx: usize) -> Option<ProvenanceTree> {
+        use std::collections::HashSet;
+
+        if idx >= self.node_table.len() {
+            return None;
+        }
+
+        // Latest incoming structural edge per node, for walking back up a document
+        let mut latest_incoming: HashMap<usize, (usize, i32)> = HashMap::new();
+        for ((from_idx, to_idx), edge) in &self.edge_table {
+            let entry = latest_incoming
+                .entry(*to_idx)
+                .or_insert((*from_idx, edge.version));
+            if edge.version > entry.1 {
+                *entry = (*from_idx, edge.version);
+            }
+        }
+
+        let mut visited = HashSet::from([idx]);
+        Some(self.provenance_tree(idx, &latest_incoming, &mut visited))
+    }
+
+    fn provenance_tree(
+        &self,
+        idx: usize,
+        latest_incoming: &std::collections::HashMap<usize, (usize, i32)>,
+        visited: &mut std::collections::HashSet<usize>,
+    ) -> ProvenanceTree {
+        // Walk structural predecessors, stopping at the root or on a cycle
+        let mut structural = Vec::new();
+        let mut seen = std::collections::HashSet::from([idx]);
+        let mut current_idx = idx;
+        while let Some(&(from_idx, _)) = latest_incoming.get(&current_idx) {
+            if !seen.insert(from_idx) {
+                break;
+            }
+            structural.push(from_idx);
+            current_idx = from_idx;
+        }
+
+        // Recurse into direct reference ancestors not already in the tree
+        let direct_references: Vec<usize> = self.incoming_references(idx).collect();
+
+        let mut references = Vec::new();
+        for from_idx in direct_references {
+            if visited.insert(from_idx) {
+                references.push(self.provenance_tree(from_idx, latest_incoming, visited));
+            }
+        }
+
+        ProvenanceTree {
+            node_idx: idx,
+            structural,
+            references,
+        }
+    }
+
+    /// Merges another knowledge base into this one.
+    ///
+    /// Nodes are unioned by identity and edges are combined with LWW semantics:
+    /// the edge with the higher version wins. When both sides hold the same edge
+    /// with equal versions but different tags, the local edge is kept and the
+    /// collision is reported as a conflict. Both knowledge bases should use the
+    /// same `NodeIdentity`, otherwise identical lines are kept as separate nodes.
+    /// Embeddings are not merged.
+    ///
+    /// # Arguments
+    /// * `other` - The knowledge base to merge into this one
+    ///
+    /// # Returns
+    /// The conflicts encountered during the merge. Use `merge_plan` to preview them.
+    pub fn merge(&mut self, other: &KnowledgeBase) -> Vec<MergeConflict> {
+        // Map the other knowledge base's node indices onto this one
+        let index_map: Vec<usize> = other
+            .node_table
+            .iter()
+            .map(|node| self.push_node(node.clone()).0)
+            .collect();
+
+        let mut conflicts = Vec::new();
+        Self::merge_edges(
+            &mut self.edge_table,
+            &other.edge_table,
+            &index_map,
+            EdgeTable::Structural,
+            &mut conflicts,
+        );
+        Self::merge_edges(
+            &mut self.ref_table,
+            &other.ref_table,
+            &index_map,
+            EdgeTable::Reference,
+            &mut conflicts,
+        );
+        for ((file_idx, from_idx, to_idx), edge) in &other.file_edges {
+            let key = (index_map[*file_idx], index_map[*from_idx], index_map[*to_idx]);
+            match self.file_edges.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(edge.clone());
+                }
+                Entry::Occupied(mut entry) => {
+                    if edge.version > entry.get().version {
+                        entry.insert(edge.clone());
+                    }
+                }
+            }
+        }
+        for edge in other.edge_table.values().chain(other.ref_table.values()) {
+            self.note_version(edge.version);
+        }
+        self.rebuild_reverse_index();
+        // Edges are merged in bulk rather than through `record`
+        self.change_count += 1;
+
+        // Journal the merged result of every incoming key, whether or not it changed
+        if self.journal.is_some() {
+            for (table, source) in [
+                (EdgeTable::Structural, &other.edge_table),
+                (EdgeTable::Reference, &other.ref_table),
+            ] {
+                for (from_idx, to_idx) in source.keys() {
+                    let edge_key = (index_map[*from_idx], index_map[*to_idx]);
+                    let edge = EdgeRecord::new(edge_key, &self.table(table)[&edge_key]);
+                    self.record(|| WalRecord::PutEdge { table, edge });
+                }
+            }
+            for (file_idx, from_idx, to_idx) in other.file_edges.keys() {
+                let file_idx = index_map[*file_idx];
+                let edge_key = (index_map[*from_idx], index_map[*to_idx]);
+                let edge = &self.file_edges[&(file_idx, edge_key.0, edge_key.1)];
+                let edge = EdgeRecord::new(edge_key, edge);
+                self.record(|| WalRecord::PutFileEdge { file_idx, edge });
+            }
+        }
+
+        conflicts
+    }
+
+    /// Previews merging another knowledge base into this one without mutating it.
+    ///
+    /// Reports the nodes and edges that would be added, the conflicts `merge` would
+    /// return, and every file whose latest path would differ afterwards. The merge is
+    /// carried out on a copy, so this costs as much as cloning the knowledge base.
+    pub fn merge_plan(&self, other: &KnowledgeBase) -> MergePlan {
+        let mut merged = self.clone();
+        let conflicts = merged.merge(other);
+
+        // Node indices are stable under merge, so paths compare directly
+        let latest_path = |kb: &KnowledgeBase, filename: &str| {
+            kb.file_index(filename)
+                .map(|file_idx| kb.traverse_latest_path_with_meta(file_idx).0)
+        };
+        let mut filenames = self.list_files();
+        filenames.extend(merged.list_files());
+        filenames.sort();
+        filenames.dedup();
+        let changed_files = filenames
+            .into_iter()
+            .filter(|filename| latest_path(self, filename) != latest_path(&merged, filename))
+            .collect();
+
+        MergePlan {
+            nodes_added: merged.node_table.len() - self.node_table.len(),
+            edges_added: merged.edge_table.len() - self.edge_table.len(),
+            references_added: merged.ref_table.len() - self.ref_table.len(),
+            conflicts,
+            changed_files,
+        }
+    }
+
+    /// Merges one edge table into another using LWW, recording equal-version conflicts.
+    fn merge_edges(
+        target: &mut BTreeMap<(usize, usize), Edge>,
+        source: &BTreeMap<(usize, usize), Edge>,
+        index_map: &[usize],
+        table: EdgeTable,
+        conflicts: &mut Vec<MergeConflict>,
+    ) {
+        for ((from_idx, to_idx), edge) in source {
+            let edge_key = (index_map[*from_idx], index_map[*to_idx]);
+
+            match target.entry(edge_key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(edge.clone());
+                }
+                Entry::Occupied(mut entry) => {
+                    let local = entry.get_mut();
+                    if edge.version > local.version {
+                        *local = edge.clone();
+                    } else if edge.version == local.version && *edge != *local {
+                        conflicts.push(MergeConflict {
+                            table,
+                            edge_key,
+                            local: local.clone(),
+                            incoming: edge.clone(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Saves the whole knowledge base to a JSON snapshot at `path`.
+    pub fn save_to_path(&self, path: impl AsRef<std::path::Path>) -> Result<(), SnapshotError> {
+        let json = self.to_snapshot_json()?;
+        std::fs::write(path, json).map_err(SnapshotError::Io)
+    }
+
+    /// Encodes a snapshot like `save_to_path` does, without writing it anywhere.
+    pub fn to_snapshot_json(&self) -> Result<Vec<u8>, SnapshotError> {
+        let records = |table: &BTreeMap<(usize, usize), Edge>| -> Vec<EdgeRecord> {
+            table
+                .iter()
+                .map(|(edge_key, edge)| EdgeRecord::new(*edge_key, edge))
+                .collect()
+        };
+        let snapshot = Snapshot {
+            schema_version: SCHEMA_VERSION,
+            node_identity: self.node_identity,
+            nodes: self.node_table.iter().cloned().collect(),
+            edges: records(&self.edge_table),
+            references: records(&self.ref_table),
+            embeddings: self
+                .embeddings
+                .iter()
+                .map(|(idx, embedding)| (*idx, embedding.clone()))
+                .collect(),
+            read_counts: self
+                .read_counts
+                .iter()
+                .map(|(idx, count)| (*idx, count.count.load(Ordering::Relaxed)))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            checkpoints: {
+                let mut checkpoints: Vec<(String, String, i32)> = self
+                    .checkpoints
+                    .iter()
+                    .map(|((filename, name), version)| (filename.clone(), name.clone(), *version))
+                    .collect();
+                checkpoints.sort();
+                checkpoints
+            },
+            version_floor: self.version_floor,
+            file_edges: self
+                .file_edges
+                .iter()
+                .map(|((file_idx, from_idx, to_idx), edge)| {
+                    (*file_idx, EdgeRecord::new((*from_idx, *to_idx), edge))
+                })
+                .collect(),
+        };
+
+        serde_json::to_vec(&snapshot).map_err(SnapshotError::Json)
+    }
+
+    /// Loads a knowledge base from a snapshot written by `save_to_path`.
+    ///
+    /// Snapshots written with a different `SCHEMA_VERSION` are rejected rather than
+    /// guessed at. The rest is validated as a whole before loading, so a malformed
+    /// snapshot fails with the location of its first problem.
+    pub fn load_from_path(path: impl AsRef<std::path::Path>) -> Result<Self, SnapshotError> {
+        let bytes = std::fs::read(path).map_err(SnapshotError::Io)?;
+        Self::load_from_json(&bytes)
+    }
+
+    /// Loads a knowledge base from snapshot JSON, e.g. an uploaded dump, like
+    /// `load_from_path`. Nothing is built unless the whole snapshot is valid.
+    pub fn load_from_json(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        // Check the version before parsing the rest, whose shape may have changed
+        #[derive(Deserialize)]
+        struct VersionOnly {
+            schema_version: u32,
+        }
+        let VersionOnly { schema_version } =
+            serde_json::from_slice(&bytes).map_err(SnapshotError::Json)?;
+        if schema_version != SCHEMA_VERSION {
+            return Err(SnapshotError::IncompatibleSchema {
+                found: schema_version,
+                expected: SCHEMA_VERSION,
+            });
+        }
+
+        let snapshot: Snapshot = serde_json::from_slice(&bytes).map_err(SnapshotError::Json)?;
+        snapshot.validate()?;
+
+        let mut kb = Self::with_node_identity(snapshot.node_identity);
+        kb.node_table.extend(snapshot.nodes);
+        for (records, table) in [
+            (snapshot.edges, &mut kb.edge_table),
+            (snapshot.references, &mut kb.ref_table),
+        ] {
+            for record in records {
+                table.insert((record.from, record.to), record.into_edge());
+            }
+        }
+        let highest_version = kb
+            .edge_table
+            .values()
+            .chain(kb.ref_table.values())
+            .map(|e| e.version)
+            .max();
+        kb.version_floor = snapshot.version_floor;
+        if let Some(highest_version) = highest_version {
+            kb.note_version(highest_version);
+        }
+        kb.rebuild_reverse_index();
+        for (file_idx, record) in snapshot.file_edges {
+            kb.file_edges
+                .insert((file_idx, record.from, record.to), record.into_edge());
+        }
+
+        for (idx, embedding) in snapshot.embeddings {
+            kb.set_embedding(idx, embedding)
+                .map_err(|err| SnapshotError::Invalid {
+                    location: format!("embedding of node {}", idx),
+                    reason: err.to_string(),
+                })?;
+        }
+
+        for (idx, node) in kb.node_table.iter().enumerate() {
+            if node.is_file() {
+                kb.read_counts.insert(idx, ReadCount::default());
+            }
+        }
+        for (idx, count) in snapshot.read_counts {
+            // Counts for anything but a file node are dropped rather than rejected
+            if let Some(read_count) = kb.read_counts.get_mut(&idx) {
+                *read_count = ReadCount::persisted(count);
+            }
+        }
+        kb.checkpoints = snapshot
+            .checkpoints
+            .into_iter()
+            .map(|(filename, name, version)| ((filename, name), version))
+            .collect();
+
+        Ok(kb)
+    }
+}
+
+impl Default for KnowledgeBase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(kb: &KnowledgeBase, filename: &str) -> Option<String> {
+        kb.read_file(filename).map(|(content, _)| content)
+    }
+
+    #[test]
+    fn write_at_version_above_max_is_accepted() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions::default();
+        kb.write_file("a.md", "one\ntwo", vec![], &options).unwrap();
+        let max_version = kb.max_version("a.md").unwrap();
+
+        kb.write_file_at_version("a.md", "one\nthree", vec![], &options, max_version + 5, false)
+            .unwrap();
+
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("one\nthree"));
+        assert_eq!(kb.max_version("a.md"), Some(max_version + 5));
+    }
+
+    #[test]
+    fn write_at_stale_version_is_rejected_unchanged() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions::default();
+        kb.write_file("a.md", "one", vec![], &options).unwrap();
+        kb.write_file("a.md", "two", vec![], &options).unwrap();
+        let max_version = kb.max_version("a.md").unwrap();
+        let edges_before = kb.edge_count();
+
+        let err = kb
+            .write_file_at_version("a.md", "three", vec![], &options, max_version, false)
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            IngestError::StaleVersion {
+                version: max_version,
+                max_version
+            }
+        );
+        assert_eq!(kb.edge_count(), edges_before);
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("two"));
+    }
+
+    #[test]
+    fn forced_write_at_stale_version_is_stored() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions::default();
+        kb.write_file("a.md", "one", vec![], &options).unwrap();
+        kb.write_file("a.md", "two", vec![], &options).unwrap();
+        let max_version = kb.max_version("a.md");
+
+        let stats = kb
+            .write_file_at_version("a.md", "three", vec![], &options, 0, true)
+            .unwrap();
+
+        assert!(stats.edges_added > 0);
+        // The newer version still wins LWW
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("two"));
+        assert_eq!(kb.max_version("a.md"), max_version);
+    }
+
+    #[test]
+    fn default_write_after_forced_high_version_wins() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions::default();
+        kb.write_file_at_version("a.md", "one", vec![], &options, 1000, true)
+            .unwrap();
+        assert!(kb.next_version() > 1000);
+
+        kb.write_file("a.md", "two", vec![], &options).unwrap();
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("two"));
+
+        let outcome = kb.delete_file("a.md");
+        assert!(matches!(outcome, DeleteOutcome::Deleted { version } if version > 1000));
+        assert_eq!(read(&kb, "a.md"), None);
+    }
+
+    #[test]
+    fn default_transaction_after_forced_high_version_wins() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions::default();
+        kb.write_file_at_version("a.md", "one", vec![], &options, 1000, true)
+            .unwrap();
+
+        let ops = vec![TransactionOp::Write {
+            path: "a.md".to_string(),
+            content: "two".to_string(),
+            reference_nodes: vec![],
+        }];
+        let (version, _) = kb.apply_transaction(ops, &options).unwrap();
+
+        assert!(version > 1000);
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("two"));
+    }
+
+    #[test]
+    fn failed_transaction_changes_nothing() {
+        let mut kb = KnowledgeBase::with_node_identity(NodeIdentity::ContentOnly);
+        let options = IngestOptions {
+            max_fork_width: Some(1),
+            ..IngestOptions::default()
+        };
+        kb.write_file("a.md", "shared\none", vec![], &options).unwrap();
+        kb.write_file("b.md", "shared\ntwo", vec![], &options).unwrap();
+        assert!(matches!(kb.delete_file("b.md"), DeleteOutcome::Deleted { .. }));
+        kb.start_journal();
+        let before = kb.to_snapshot_json().unwrap();
+        let next_version = kb.next_version();
+
+        // Evicts a.md's fork, resurrects b.md, adds nodes and a reference, then fails
+        let write = |path: &str, content: &str| TransactionOp::Write {
+            path: path.to_string(),
+            content: content.to_string(),
+            reference_nodes: vec![Node::new("source".to_string(), String::new())],
+        };
+        let ops = vec![
+            write("a.md", "shared\nthree"),
+            write("b.md", "shared\nfour"),
+            TransactionOp::Reference { from: 0, to: 1 },
+            TransactionOp::Delete { path: "missing.md".to_string() },
+        ];
+        let error = kb.apply_transaction(ops, &options).unwrap_err();
+
+        assert_eq!(error.op_index, 3);
+        assert_eq!(kb.to_snapshot_json().unwrap(), before);
+        assert_eq!(kb.next_version(), next_version);
+        assert!(kb.take_journal().is_empty());
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("shared\none"));
+        assert_eq!(read(&kb, "b.md"), None);
+    }
+
+    fn out_degree(kb: &KnowledgeBase, content: &str) -> usize {
+        let idx = kb.nodes().iter().position(|node| node.content == content).unwrap();
+        kb.edges().range((idx, usize::MIN)..(idx + 1, usize::MIN)).count()
+    }
+
+    #[test]
+    fn fork_width_cap_holds_and_latest_path_survives() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions {
+            max_fork_width: Some(2),
+            ..IngestOptions::default()
+        };
+        let mut evicted = 0;
+        for tail in ["a", "b", "c", "d", "e"] {
+            let content = format!("head\n{}", tail);
+            evicted += kb.write_file("f.md", &content, vec![], &options).unwrap().edges_evicted;
+
+            assert!(out_degree(&kb, "head") <= 2);
+            assert_eq!(read(&kb, "f.md"), Some(content));
+        }
+        assert_eq!(evicted, 3);
+    }
+
+    #[test]
+    fn edit_after_resurrect_wins() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions::default();
+        kb.write_file("a.md", "one\ntwo", vec![], &options).unwrap();
+        assert!(matches!(kb.delete_file("a.md"), DeleteOutcome::Deleted { .. }));
+        assert_eq!(read(&kb, "a.md"), None);
+
+        // Resurrecting removes the tombstone edge
+        kb.write_file("a.md", "one\nthree", vec![], &options).unwrap();
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("one\nthree"));
+
+        kb.write_file("a.md", "one\nfour", vec![], &options).unwrap();
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("one\nfour"));
+
+        assert!(matches!(kb.delete_file("a.md"), DeleteOutcome::Deleted { .. }));
+        assert_eq!(read(&kb, "a.md"), None);
+    }
+
+    fn contents(kb: &KnowledgeBase, path: &[usize]) -> Vec<String> {
+        path.iter().map(|idx| kb.nodes()[*idx].content.clone()).collect()
+    }
+
+    #[test]
+    fn preferring_tag_follows_approved_over_newer_draft() {
+        let mut kb = KnowledgeBase::new();
+        let dir_idx = kb.insert_directory(".");
+        let file_idx = kb.insert_markdown("a\nb\nc", "f.md", dir_idx, vec![], 0, "approved");
+        kb.insert_markdown("a\nx\ny", "f.md", dir_idx, vec![], 1, "draft");
+
+        let approved = kb.traverse_preferring_tag(file_idx, "approved");
+        assert_eq!(contents(&kb, &approved[1..]), ["a", "b", "c"]);
+
+        let drafts = kb.traverse_preferring_tag(file_idx, "draft");
+        assert_eq!(contents(&kb, &drafts[1..]), ["a", "x", "y"]);
+    }
+
+    #[test]
+    fn preferring_tag_stops_on_cycle() {
+        let mut kb = KnowledgeBase::new();
+        let dir_idx = kb.insert_directory(".");
+        let file_idx = kb.insert_markdown("a\nb\na", "f.md", dir_idx, vec![], 0, "approved");
+        kb.insert_markdown("a\nx", "f.md", dir_idx, vec![], 1, "draft");
+
+        let path = kb.traverse_preferring_tag(file_idx, "approved");
+        assert_eq!(contents(&kb, &path[1..]), ["a", "b"]);
+    }
+
+    #[test]
+    fn rename_keeps_chunking_and_links_provenance() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions {
+            chunker: Some(Arc::new(ParagraphChunker)),
+            ..IngestOptions::default()
+        };
+        kb.write_file("old.md", "one\ntwo\n\nthree", vec![], &options).unwrap();
+        let (old_content, old_indices) = kb.read_file("old.md").unwrap();
+
+        kb.rename_file("old.md", "new.md").unwrap();
+
+        let (new_content, new_indices) = kb.read_file("new.md").unwrap();
+        assert_eq!(new_content, old_content);
+        assert_eq!(contents(&kb, &new_indices), ["one\ntwo", "three"]);
+        assert!(new_indices.iter().all(|idx| kb.nodes()[*idx].filename == "new.md"));
+        for (old_idx, new_idx) in old_indices.into_iter().zip(new_indices) {
+            assert!(kb.references().contains_key(&(old_idx, new_idx)));
+        }
+        assert_eq!(kb.read_file("old.md"), None);
+    }
+
+    fn snapshot_json() -> serde_json::Value {
+        let mut kb = KnowledgeBase::new();
+        let source = Node::new("source".to_string(), String::new());
+        kb.write_file("a.md", "one\ntwo", vec![source], &IngestOptions::default())
+            .unwrap();
+        serde_json::from_slice(&kb.to_snapshot_json().unwrap()).unwrap()
+    }
+
+    fn invalid_location(snapshot: serde_json::Value) -> String {
+        let bytes = serde_json::to_vec(&snapshot).unwrap();
+        match KnowledgeBase::load_from_json(&bytes) {
+            Err(SnapshotError::Invalid { location, .. }) => location,
+            Err(err) => panic!("expected a validation error, got {}", err),
+            Ok(_) => panic!("malformed snapshot was loaded"),
+        }
+    }
+
+    #[test]
+    fn valid_snapshot_loads() {
+        let bytes = serde_json::to_vec(&snapshot_json()).unwrap();
+        let kb = KnowledgeBase::load_from_json(&bytes).unwrap();
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("one\ntwo"));
+    }
+
+    #[test]
+    fn snapshot_with_dangling_edge_is_rejected() {
+        let mut snapshot = snapshot_json();
+        snapshot["edges"][1]["to"] = serde_json::json!(999);
+        assert_eq!(invalid_location(snapshot), "edges[1].to");
+    }
+
+    #[test]
+    fn snapshot_with_out_of_range_index_is_rejected() {
+        let mut snapshot = snapshot_json();
+        snapshot["references"][0]["from"] = serde_json::json!(999);
+        assert_eq!(invalid_location(snapshot), "references[0].from");
+
+        let mut snapshot = snapshot_json();
+        snapshot["embeddings"] = serde_json::json!([[999, [1.0, 0.0]]]);
+        assert_eq!(invalid_location(snapshot), "embeddings[0]");
+    }
+
+    #[test]
+    fn snapshot_with_duplicate_node_is_rejected() {
+        let mut snapshot = snapshot_json();
+        let nodes = snapshot["nodes"].as_array_mut().unwrap();
+        let duplicate = nodes[1].clone();
+        nodes.push(duplicate);
+        let location = format!("nodes[{}]", nodes.len() - 1);
+        assert_eq!(invalid_location(snapshot), location);
+    }
+
+    #[test]
+    fn snapshot_with_negative_version_is_rejected() {
+        let mut snapshot = snapshot_json();
+        snapshot["edges"][0]["version"] = serde_json::json!(-1);
+        assert_eq!(invalid_location(snapshot), "edges[0].version");
+    }
+
+    #[test]
+    fn wal_edge_to_missing_node_is_rejected() {
+        let mut kb = KnowledgeBase::new();
+        let record = WalRecord::PutEdge {
+            table: EdgeTable::Structural,
+            edge: EdgeRecord::new((0, 1), &Edge::new(0, "version-0".to_string())),
+        };
+        assert!(matches!(kb.apply_wal_record(record), Err(SnapshotError::Invalid { .. })));
+        assert_eq!(kb.edge_count(), 0);
+    }
+
+    #[test]
+    fn mixed_mode_file_converges_to_paragraphs() {
+        let mut kb = KnowledgeBase::new();
+        let paragraphs = IngestOptions {
+            chunker: Some(Arc::new(ParagraphChunker)),
+            ..IngestOptions::default()
+        };
+        kb.write_file("f.md", "x\ny\n\nz", vec![], &IngestOptions::default())
+            .unwrap();
+        // The paragraph write reuses the line node "x", whose old successors follow it
+        kb.write_file("f.md", "p\nq\n\nx", vec![], &paragraphs).unwrap();
+        let (_, mixed) = kb.read_file("f.md").unwrap();
+        assert_eq!(contents(&kb, &mixed), ["p\nq", "x", "y", "z"]);
+
+        kb.normalize_granularity("f.md", &ParagraphChunker).unwrap();
+
+        let (content, normalized) = kb.read_file("f.md").unwrap();
+        assert_eq!(contents(&kb, &normalized), ["p\nq", "x\ny\nz"]);
+        assert_eq!(content, "p\nq\nx\ny\nz");
+        for old_idx in &mixed[1..] {
+            assert!(kb.references().contains_key(&(*old_idx, normalized[1])));
+        }
+
+        // Converged: normalizing again changes nothing
+        kb.normalize_granularity("f.md", &ParagraphChunker).unwrap();
+        assert_eq!(kb.read_file("f.md").unwrap().1, normalized);
+    }
+
+    #[test]
+    fn sentence_chunked_file_has_one_node_per_sentence() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions {
+            chunker: Some(Arc::new(SentenceChunker)),
+            ..IngestOptions::default()
+        };
+        kb.write_file("s.md", "A. B. C.  ", vec![], &options).unwrap();
+
+        let (content, indices) = kb.read_file("s.md").unwrap();
+        assert_eq!(contents(&kb, &indices), ["A.", "B.", "C."]);
+        // Every sentence reads back on its own line
+        assert_eq!(content, "A.\nB.\nC.");
+        assert_eq!(content.replace('\n', " "), "A. B. C.");
+    }
+
+    #[test]
+    fn position_identity_round_trips_repeated_lines() {
+        let options = IngestOptions::default();
+        for content in ["a\n---\nb\n---\nc", "---\na\n---\nb\n---", "---\n---\n---"] {
+            let mut kb = KnowledgeBase::with_node_identity(NodeIdentity::ContentFileAndPosition);
+            kb.write_file("f.md", content, vec![], &options).unwrap();
+
+            let (read, indices) = kb.read_file("f.md").unwrap();
+            assert_eq!(read, content);
+            // Every separator is its own node, so the chain has no ambiguous fork
+            let separators = indices
+                .iter()
+                .filter(|idx| kb.nodes()[**idx].content == "---")
+                .count();
+            assert_eq!(separators, content.matches("---").count());
+        }
+    }
+
+    #[test]
+    fn content_and_file_identity_collapses_repeated_lines() {
+        let mut kb = KnowledgeBase::new();
+        kb.write_file("f.md", "a\n---\nb\n---\nc", vec![], &IngestOptions::default())
+            .unwrap();
+        // Without positions the separators share one node with two successors
+        assert_ne!(kb.read_file_text("f.md").as_deref(), Some("a\n---\nb\n---\nc"));
+    }
+
+    #[test]
+    fn rebuilding_reverse_index_restores_provenance_search() {
+        let mut kb = KnowledgeBase::new();
+        let source = Node::new("source".to_string(), String::new());
+        kb.write_file("a.md", "one\ntwo", vec![source], &IngestOptions::default())
+            .unwrap();
+        let (_, indices) = kb.read_file("a.md").unwrap();
+        let expected = kb.find_referenced_nodes(indices[0]);
+        assert_eq!(expected.len(), 2);
+        assert!(!kb.rebuild_reverse_index());
+
+        // Corrupt the index: drop one entry and add one no reference edge backs
+        let first = *kb.ref_index.iter().next().unwrap();
+        kb.ref_index.remove(&first);
+        kb.ref_index.insert((indices[1], indices[0]));
+        assert_ne!(kb.find_referenced_nodes(indices[0]), expected);
+
+        assert!(kb.rebuild_reverse_index());
+        assert_eq!(kb.find_referenced_nodes(indices[0]), expected);
+        assert_eq!(kb.find_referenced_nodes(indices[1]).len(), 2);
+        assert!(!kb.rebuild_reverse_index());
+    }
+
+    #[tokio::test]
+    async fn file_id_matches_datafusion_doc_id() {
+        let datafusion = DataFusionKnowledgeBase::new().await.unwrap();
+        let mut kb = KnowledgeBase::new();
+        kb.write_file("docs/a.md", "one", vec![], &IngestOptions::default())
+            .unwrap();
+
+        for filename in ["docs/a.md", "never-written.md"] {
+            assert_eq!(kb.file_id(filename), datafusion.doc_id(filename));
+            assert_eq!(kb.file_id(filename), document_id(filename));
+        }
+    }
+
+    #[tokio::test]
+    async fn seeded_file_id_matches_seeded_doc_id() {
+        let datafusion = DataFusionKnowledgeBase::new().await.unwrap().with_hash_seed(42);
+        let mut kb = KnowledgeBase::new();
+        kb.set_hash_seed(Some(42));
+
+        assert_eq!(kb.file_id("docs/a.md"), datafusion.doc_id("docs/a.md"));
+        assert_ne!(kb.file_id("docs/a.md"), document_id("docs/a.md"));
+
+        // The seed is configuration, kept when the contents are replaced
+        kb.replace_with(KnowledgeBase::new());
+        assert_eq!(kb.file_id("docs/a.md"), datafusion.doc_id("docs/a.md"));
+    }
+
+    #[test]
+    fn next_version_survives_snapshot_round_trip() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions::default();
+        kb.write_file_at_version("a.md", "one", vec![], &options, 1000, true)
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("kb-version-{}.json", std::process::id()));
+        kb.save_to_path(&path).unwrap();
+
+        let loaded = KnowledgeBase::load_from_path(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.next_version(), kb.next_version());
+    }
+
+    #[test]
+    fn oversized_line_is_rejected_without_ingesting() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions {
+            max_node_len: Some(1024),
+            ..IngestOptions::default()
+        };
+        let blob = "x".repeat(5 * 1024 * 1024);
+        let content = format!("header\n{blob}");
+
+        let err = kb.write_file("min.js", &content, vec![], &options).unwrap_err();
+
+        assert_eq!(
+            err,
+            IngestError::NodeTooLarge {
+                line: 2,
+                len: blob.len(),
+                max_len: 1024
+            }
+        );
+        assert_eq!(kb.node_count(), 0);
+        assert_eq!(kb.read_file("min.js"), None);
+    }
+
+    #[test]
+    fn oversized_line_is_split_into_chained_chunks() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions {
+            max_node_len: Some(1024),
+            oversize_policy: OversizePolicy::Split,
+            ..IngestOptions::default()
+        };
+        // Distinct chunks, so none of them collapse into one node
+        let blob: String = (0..5 * 1024).map(|i| format!("{:04}", i % 10_000)).collect();
+
+        kb.write_file("min.js", &blob, vec![], &options).unwrap();
+
+        let (content, indices) = kb.read_file("min.js").unwrap();
+        assert_eq!(indices.len(), blob.len().div_ceil(1024));
+        assert!(indices.iter().all(|idx| kb.nodes()[*idx].content.len() <= 1024));
+        assert_eq!(content, split_oversized(&blob, 1024).join("\n"));
+    }
+
+    #[test]
+    fn repeated_split_chunks_stay_separate_nodes() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions {
+            max_node_len: Some(1024),
+            oversize_policy: OversizePolicy::Split,
+            ..IngestOptions::default()
+        };
+        let blob = "x".repeat(5 * 1024 + 10);
+
+        kb.write_file("min.js", &format!("head\n{}\ntail", blob), vec![], &options)
+            .unwrap();
+
+        let (content, indices) = kb.read_file("min.js").unwrap();
+        assert_eq!(indices.len(), 8);
+        let chunks = split_oversized(&blob, 1024).join("\n");
+        assert_eq!(content, format!("head\n{}\ntail", chunks));
+
+        // Renaming keeps the chunks apart too
+        kb.rename_file("min.js", "app.js").unwrap();
+        assert_eq!(kb.read_file_text("app.js"), Some(format!("head\n{}\ntail", chunks)));
+    }
+
+    #[test]
+    fn split_keeps_multi_byte_chars_whole() {
+        let line = "é".repeat(10);
+
+        let chunks = split_oversized(&line, 3);
+
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 3));
+        assert_eq!(chunks.concat(), line);
+        // A limit below one char still makes progress, one char per chunk
+        assert_eq!(split_oversized("éé", 1), ["é", "é"]);
+    }
+
+    /// Writes two files ending in the same line and returns the indices of its nodes.
+    fn shared_line_nodes(kb: &mut KnowledgeBase) -> Vec<usize> {
+        let options = IngestOptions::default();
+        kb.write_file("a.md", "alpha\nlicense", vec![], &options).unwrap();
+        kb.write_file("b.md", "beta\nlicense", vec![], &options).unwrap();
+        assert_eq!(read(kb, "a.md").as_deref(), Some("alpha\nlicense"));
+        assert_eq!(read(kb, "b.md").as_deref(), Some("beta\nlicense"));
+        (0..kb.node_count())
+            .filter(|idx| kb.nodes()[*idx].content == "license")
+            .collect()
+    }
+
+    #[test]
+    fn content_and_file_identity_keeps_shared_lines_per_file() {
+        let mut kb = KnowledgeBase::new();
+
+        let shared = shared_line_nodes(&mut kb);
+
+        assert_eq!(shared.len(), 2);
+        assert_eq!(kb.node_positions(shared[0]), [("a.md".to_string(), 2)]);
+        assert_eq!(kb.node_positions(shared[1]), [("b.md".to_string(), 2)]);
+    }
+
+    #[test]
+    fn content_only_identity_shares_lines_across_files() {
+        let mut kb = KnowledgeBase::with_node_identity(NodeIdentity::ContentOnly);
+
+        let shared = shared_line_nodes(&mut kb);
+
+        assert_eq!(shared.len(), 1);
+        assert_eq!(kb.nodes()[shared[0]].filename, "");
+        assert_eq!(
+            kb.node_positions(shared[0]),
+            [("a.md".to_string(), 2), ("b.md".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn node_positions_lists_every_file_sharing_the_node() {
+        let mut kb = KnowledgeBase::with_node_identity(NodeIdentity::ContentOnly);
+        let options = IngestOptions::default();
+        kb.write_file("c.md", "gamma\ndelta\nshared", vec![], &options).unwrap();
+        kb.write_file("a.md", "alpha\nshared", vec![], &options).unwrap();
+        kb.write_file("b.md", "shared\nbeta", vec![], &options).unwrap();
+        let shared = kb.node_index(&Node::new("shared".to_string(), String::new())).unwrap();
+
+        assert_eq!(
+            kb.node_positions(shared),
+            [
+                ("a.md".to_string(), 2),
+                ("b.md".to_string(), 1),
+                ("c.md".to_string(), 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn node_positions_is_empty_off_every_latest_path() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions::default();
+        let source = Node::new("source".to_string(), String::new());
+        kb.write_file("a.md", "old", vec![source.clone()], &options).unwrap();
+        kb.write_file("a.md", "new", vec![], &options).unwrap();
+        let node =
+            |content: &str| kb.node_index(&Node::new(content.to_string(), "a.md".to_string()));
+        let (old, new) = (node("old").unwrap(), node("new").unwrap());
+        let source = kb.node_index(&source).unwrap();
+
+        assert_eq!(kb.node_positions(new), [("a.md".to_string(), 1)]);
+        // An overwritten line, a reference node, and an index past the end
+        assert!(kb.node_positions(old).is_empty());
+        assert!(kb.node_positions(source).is_empty());
+        assert!(kb.node_positions(kb.node_count()).is_empty());
+
+        assert!(matches!(kb.delete_file("a.md"), DeleteOutcome::Deleted { .. }));
+        assert!(kb.node_positions(new).is_empty());
+    }
+
+    #[test]
+    fn content_only_files_sharing_a_middle_line_keep_their_own_successors() {
+        let mut kb = KnowledgeBase::with_node_identity(NodeIdentity::ContentOnly);
+        let options = IngestOptions::default();
+        kb.write_file("a.md", "alpha\nshared\none", vec![], &options).unwrap();
+        kb.write_file("b.md", "beta\nshared\ntwo", vec![], &options).unwrap();
+
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("alpha\nshared\none"));
+        assert_eq!(kb.read_file_text("b.md").as_deref(), Some("beta\nshared\ntwo"));
+
+        // b.md moving onto a.md's old successor bumps the shared edge past a.md's newer one
+        kb.write_file("a.md", "alpha\nshared\nthree", vec![], &options).unwrap();
+        kb.write_file("b.md", "beta\nshared\none", vec![], &options).unwrap();
+
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("alpha\nshared\nthree"));
+        assert_eq!(read(&kb, "b.md").as_deref(), Some("beta\nshared\none"));
+        let kb = KnowledgeBase::load_from_json(&kb.to_snapshot_json().unwrap()).unwrap();
+        assert_eq!(kb.read_file_text("a.md").as_deref(), Some("alpha\nshared\nthree"));
+    }
+
+    #[test]
+    fn content_only_fork_width_is_counted_per_file() {
+        let mut kb = KnowledgeBase::with_node_identity(NodeIdentity::ContentOnly);
+        let options = IngestOptions {
+            max_fork_width: Some(1),
+            ..IngestOptions::default()
+        };
+        kb.write_file("a.md", "shared\nA", vec![], &options).unwrap();
+        let stats = kb.write_file("b.md", "shared\nB", vec![], &options).unwrap();
+
+        // Each file has one successor of the shared line, so nothing is over the cap
+        assert_eq!(stats.edges_evicted, 0);
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("shared\nA"));
+        assert_eq!(read(&kb, "b.md").as_deref(), Some("shared\nB"));
+
+        // a.md's own older fork goes, along with its edge, which no other file has
+        let stats = kb.write_file("a.md", "shared\nC", vec![], &options).unwrap();
+        assert_eq!(stats.edges_evicted, 1);
+        assert_eq!(out_degree(&kb, "shared"), 2);
+        assert_eq!(read(&kb, "a.md").as_deref(), Some("shared\nC"));
+        assert_eq!(read(&kb, "b.md").as_deref(), Some("shared\nB"));
+    }
+
+    /// Ingests the transition `a -> b` at v0 and again at v5, returning the stored edge.
+    fn reingested_edge(policy: EdgeInsertPolicy) -> Edge {
+        let mut kb = KnowledgeBase::new();
+        let dir_idx = kb.insert_directory(".");
+        let options = IngestOptions {
+            edge_insert_policy: policy,
+            ..IngestOptions::default()
+        };
+        for (version, tag) in [(0, "version-0"), (5, "version-5")] {
+            kb.insert_markdown_with_options("a\nb", "f.md", dir_idx, vec![], version, tag, &options)
+                .unwrap();
+        }
+        let a = kb.node_index(&Node::new("a".to_string(), "f.md".to_string())).unwrap();
+        let b = kb.node_index(&Node::new("b".to_string(), "f.md".to_string())).unwrap();
+        kb.edges()[&(a, b)].clone()
+    }
+
+    #[test]
+    fn latest_wins_policy_stores_the_newest_version() {
+        let edge = reingested_edge(EdgeInsertPolicy::LatestWins);
+        assert_eq!((edge.version, edge.tag.as_str()), (5, "version-5"));
+        assert_eq!(IngestOptions::default().edge_insert_policy, EdgeInsertPolicy::LatestWins);
+    }
+
+    #[test]
+    fn preserve_first_policy_keeps_the_first_version() {
+        let edge = reingested_edge(EdgeInsertPolicy::PreserveFirst);
+        assert_eq!((edge.version, edge.tag.as_str()), (0, "version-0"));
+    }
+
+    fn canonical(line: &str) -> String {
+        line.trim_end_matches(['.', '!', '?']).to_lowercase()
+    }
+
+    /// Writes two variants of one document and returns the node indices of each.
+    fn variant_indices(transform: Option<fn(&str) -> String>) -> (Vec<usize>, Vec<usize>) {
+        let mut kb = KnowledgeBase::with_node_identity(NodeIdentity::ContentOnly);
+        let options = IngestOptions {
+            transform,
+            ..IngestOptions::default()
+        };
+        kb.write_file("a.md", "# Intro!\nHello World.", vec![], &options).unwrap();
+        kb.write_file("b.md", "# INTRO\nhello world", vec![], &options).unwrap();
+        let (_, a) = kb.read_file("a.md").unwrap();
+        let (b_content, b) = kb.read_file("b.md").unwrap();
+        if transform.is_some() {
+            assert_eq!(b_content, "# intro\nhello world");
+        }
+        (a, b)
+    }
+
+    #[test]
+    fn transform_makes_variant_documents_share_nodes() {
+        let (a, b) = variant_indices(Some(canonical));
+        assert_eq!(a, b);
+
+        let (a, b) = variant_indices(None);
+        assert!(a.iter().all(|idx| !b.contains(idx)));
+    }
+
+    /// A source referenced at v1 by `old` and at v5 by `new`, and `old` referenced at
+    /// v6 by `derived`. Returns the knowledge base and the source's index.
+    fn mixed_version_references() -> (KnowledgeBase, usize) {
+        let mut kb = KnowledgeBase::new();
+        let dir_idx = kb.insert_directory(".");
+        let source = Node::new("source".to_string(), String::new());
+        kb.insert_markdown("old", "a.md", dir_idx, vec![source.clone()], 1, "version-1");
+        kb.insert_markdown("new", "b.md", dir_idx, vec![source.clone()], 5, "version-5");
+        let old = Node::new("old".to_string(), "a.md".to_string());
+        kb.insert_markdown("derived", "c.md", dir_idx, vec![old], 6, "version-6");
+        let source_idx = kb.node_index(&source).unwrap();
+        (kb, source_idx)
+    }
+
+    fn contaminated_contents(kb: &KnowledgeBase, indices: Vec<usize>) -> Vec<String> {
+        let mut found = contents(kb, &indices);
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn contamination_since_skips_older_references() {
+        let (kb, source_idx) = mixed_version_references();
+
+        let all = kb.find_contaminated_nodes(source_idx);
+        let recent = kb.find_contaminated_since(source_idx, 5);
+
+        assert_eq!(all[0], source_idx);
+        assert_eq!(contaminated_contents(&kb, all), ["derived", "new", "old", "source"]);
+        // `derived` is only reachable through the v1 reference to `old`
+        assert_eq!(recent[0], source_idx);
+        assert_eq!(contaminated_contents(&kb, recent), ["new", "source"]);
+        assert_eq!(kb.find_contaminated_since(source_idx, 6), [source_idx]);
+    }
+
+    #[test]
+    fn straighten_quotes_replaces_curly_quotes_only() {
+        assert_eq!(straighten_quotes("\u{201C}it\u{2019}s\u{201D}"), "\"it's\"");
+        assert_eq!(straighten_quotes("plain \"text\" 's"), "plain \"text\" 's");
+    }
+
+    const CURLY: &str = "He said \u{201C}hi\u{201D}";
+    const STRAIGHT: &str = "He said \"hi\"";
+
+    #[test]
+    fn dedup_key_merges_quote_variants_keeping_first_text() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_dedup_key(Some(straighten_quotes));
+        let options = IngestOptions::default();
+        kb.write_file("a.md", &format!("{CURLY}\nend"), vec![], &options).unwrap();
+        let (_, before) = kb.read_file("a.md").unwrap();
+        let node_count = kb.node_count();
+
+        kb.write_file("a.md", &format!("{STRAIGHT}\nend"), vec![], &options).unwrap();
+
+        let (content, after) = kb.read_file("a.md").unwrap();
+        assert_eq!(kb.node_count(), node_count);
+        assert_eq!(after, before);
+        assert_eq!(content, format!("{CURLY}\nend"));
+        let straight = Node::new(STRAIGHT.to_string(), "a.md".to_string());
+        assert_eq!(kb.node_index(&straight), Some(before[0]));
+    }
+
+    #[test]
+    fn without_dedup_key_quote_variants_stay_distinct() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions::default();
+        kb.write_file("a.md", CURLY, vec![], &options).unwrap();
+        kb.write_file("a.md", STRAIGHT, vec![], &options).unwrap();
+
+        let (content, _) = kb.read_file("a.md").unwrap();
+        assert_eq!(content, STRAIGHT);
+        let curly = kb.node_index(&Node::new(CURLY.to_string(), "a.md".to_string()));
+        let straight = kb.node_index(&Node::new(STRAIGHT.to_string(), "a.md".to_string()));
+        assert_ne!(curly, straight);
+
+        // Setting a key later indexes the existing nodes, keeping the first
+        kb.set_dedup_key(Some(straighten_quotes));
+        let straight = kb.node_index(&Node::new(STRAIGHT.to_string(), "a.md".to_string()));
+        assert_eq!(straight, curly);
+    }
+
+    #[test]
+    fn dedup_key_leaves_markers_and_other_files_apart() {
+        let mut kb = KnowledgeBase::new();
+        kb.set_dedup_key(Some(straighten_quotes));
+        let options = IngestOptions::default();
+        kb.write_file("\u{201C}a\u{201D}.md", CURLY, vec![], &options).unwrap();
+        kb.write_file("\"a\".md", STRAIGHT, vec![], &options).unwrap();
+
+        assert_eq!(kb.list_files().len(), 2);
+        assert_eq!(read(&kb, "\u{201C}a\u{201D}.md").as_deref(), Some(CURLY));
+        assert_eq!(read(&kb, "\"a\".md").as_deref(), Some(STRAIGHT));
+    }
+
+    #[test]
+    fn recent_ledger_nodes_are_the_last_added() {
+        let mut ledger = Ledger::new();
+        ledger.add_nodes(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        // Reading `a` again keeps the position of its first read
+        ledger.add_nodes(vec!["a".to_string(), "d".to_string()]);
+
+        assert_eq!(ledger.recent_nodes(Some(2)), ["c", "d"]);
+        assert_eq!(ledger.recent_nodes(Some(10)), ["a", "b", "c", "d"]);
+        assert_eq!(ledger.recent_nodes(None), ["a", "b", "c", "d"]);
+        assert!(ledger.recent_nodes(Some(0)).is_empty());
+    }
+
+    #[test]
+    fn content_ids_resolve_after_indices_change() {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions::default();
+        kb.write_file("src.md", "fact\ngone", vec![], &options).unwrap();
+        let (_, indices) = kb.read_file("src.md").unwrap();
+        let ids = kb.content_ids(&indices);
+
+        // Rebuilt with another file first and without `gone`, so `fact` moves
+        let mut rebuilt = KnowledgeBase::new();
+        rebuilt.write_file("other.md", "noise\nmore", vec![], &options).unwrap();
+        rebuilt.write_file("src.md", "fact", vec![], &options).unwrap();
+        let (_, rebuilt_indices) = rebuilt.read_file("src.md").unwrap();
+        assert_ne!(rebuilt_indices[0], indices[0]);
+
+        let resolved = rebuilt.resolve_content_ids(&ids);
+        assert_eq!(resolved, [Some(&rebuilt.nodes()[rebuilt_indices[0]]), None]);
+    }
+
+    /// A knowledge base where node `i` has `degrees[i]` structural edges tagged `tag`.
+    fn fan_out_kb(degrees: &[usize], tag: &str) -> KnowledgeBase {
+        let mut kb = KnowledgeBase::new();
+        let node_count = degrees.len().max(degrees.iter().copied().max().unwrap_or(0));
+        for idx in 0..node_count {
+            kb.insert_node(&format!("node {idx}"), "f.md");
+        }
+        for (from, degree) in degrees.iter().enumerate() {
+            for to in 0..*degree {
+                kb.set_edge(EdgeTable::Structural, (from, to), Edge::new(0, tag.to_string()));
+            }
+        }
+        kb
+    }
+
+    fn sources(page: &EdgePage) -> Vec<usize> {
+        page.edges.iter().map(|((from, _), _)| *from).collect()
+    }
+
+    #[test]
+    fn edge_page_full_at_node_boundary_continues_at_next_node() {
+        let kb = fan_out_kb(&[2, 2, 2], "t");
+
+        let page = kb.edges_in_range(0, 3, "", 4);
+
+        assert_eq!(sources(&page), [0, 0, 1, 1]);
+        assert_eq!(page.next, Some((2, 0)));
+    }
+
+    #[test]
+    fn edge_page_splits_a_node_at_the_cap() {
+        let kb = fan_out_kb(&[2, 3, 1], "t");
+
+        let page = kb.edges_in_range(0, 3, "", 4);
+        assert_eq!(sources(&page), [0, 0, 1, 1]);
+        assert_eq!(page.next, Some((1, 2)));
+
+        let page = kb.edges_in_range_from((1, 2), 3, "", 4);
+        assert_eq!(sources(&page), [1, 2]);
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn edge_pages_stay_within_the_cap_inside_a_hub() {
+        let kb = fan_out_kb(&[5, 1], "t");
+
+        let page = kb.edges_in_range(0, 2, "", 2);
+        assert_eq!(sources(&page), [0, 0]);
+        assert_eq!(page.next, Some((0, 2)));
+
+        let page = kb.edges_in_range_from((0, 2), 2, "", 2);
+        assert_eq!(sources(&page), [0, 0]);
+        assert_eq!(page.next, Some((0, 4)));
+
+        let page = kb.edges_in_range_from((0, 4), 2, "", 2);
+        assert_eq!(sources(&page), [0, 1]);
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn edge_pages_join_back_to_the_whole_range() {
+        let kb = fan_out_kb(&[3, 1, 4, 0, 2, 2], "t");
+        let whole = kb.edges_in_range(0, 6, "", usize::MAX);
+        assert_eq!(whole.edges.len(), 12);
+        assert_eq!(whole.next, None);
+
+        let mut edges = Vec::new();
+        let mut start = (0, 0);
+        loop {
+            let page = kb.edges_in_range_from(start, 6, "", 4);
+            assert!(page.edges.len() <= 4);
+            edges.extend(page.edges);
+            match page.next {
+                Some(next) => start = next,
+                None => break,
+            }
+        }
+        assert_eq!(edges, whole.edges);
+    }
+
+    #[test]
+    fn edge_page_caps_only_matching_tags() {
+        let mut kb = fan_out_kb(&[2, 2], "keep");
+        kb.insert_node("node 2", "f.md");
+        kb.set_edge(EdgeTable::Structural, (0, 2), Edge::new(0, "skip".to_string()));
+
+        let page = kb.edges_in_range(0, 2, "ke", 4);
+
+        assert_eq!(page.edges.len(), 4);
+        assert!(page.edges.iter().all(|(_, edge)| edge.tag == "keep"));
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn empty_or_reversed_edge_range_is_empty() {
+        let kb = fan_out_kb(&[2, 2], "t");
+        for (from_lo, from_hi) in [(1, 1), (2, 0)] {
+            let page = kb.edges_in_range(from_lo, from_hi, "", 4);
+            assert!(page.edges.is_empty());
+            assert_eq!(page.next, None);
+        }
+    }
+
+    /// Two files sharing a line, both derived from a source, with "secret" in every
+    /// content node and in no path or tag.
+    fn secret_kb() -> KnowledgeBase {
+        let mut kb = KnowledgeBase::new();
+        let options = IngestOptions::default();
+        let source = Node::new("secret source".to_string(), String::new());
+        kb.write_file("a.md", "secret one\nshared secret", vec![source.clone()], &options)
+            .unwrap();
+        kb.write_file("b.md", "secret two\nshared secret", vec![source], &options)
+            .unwrap();
+        kb
+    }
+
+    #[test]
+    fn redacted_exports_hold_no_content() {
+        let kb = secret_kb();
+
+        let dot = kb.file_to_dot("a.md", true).unwrap();
+        let prov = kb.to_prov_json(true).to_string();
+
+        assert!(!dot.contains("secret"));
+        assert!(dot.contains("FILE: a.md") && dot.contains("redacted-1"));
+        assert!(!prov.contains("secret"));
+        assert!(prov.contains("redacted-1") && prov.contains("a.md"));
+        assert!(kb.file_to_dot("a.md", false).unwrap().contains("secret one"));
+    }
+
+    #[test]
+    fn redacted_files_share_placeholders_for_equal_content() {
+        let kb = secret_kb();
+        let mut redactor = Redactor::default();
+
+        let a = kb.read_file_redacted("a.md", &mut redactor).unwrap();
+        let b = kb.read_file_redacted("b.md", &mut redactor).unwrap();
+
+        assert_eq!(a, "redacted-1\nredacted-2");
+        assert_eq!(b, "redacted-3\nredacted-2");
+        assert_eq!(kb.read_file_redacted("missing.md", &mut redactor), None);
+    }
+}
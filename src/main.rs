@@ -1,400 +1,456 @@
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    routing::{delete, get},
+    body::{Body, Bytes},
+    extract::{rejection::JsonRejection, DefaultBodyLimit, Multipart, Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
     Json, Router,
 };
-use indexmap::IndexSet;
+use liasiondb::{
+    BulkReference, BulkReferenceStats, Clock, DeleteOutcome, DiffLine, EdgeInsertPolicy,
+    EmbeddingError, ImpactReport, IngestError, IngestOptions, IngestStats, KnowledgeBase, Ledger,
+    Node, NodeIdentity, OversizePolicy, ProvenanceTree, Redactor, RenameError, SCHEMA_VERSION,
+    SnapshotError, SystemClock,
+    TransactionOp, TransactionOpError, TraversalMeta, WriteAheadLog, chunker_by_name, diff_text,
+    straighten_quotes,
+};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
-use std::sync::{Arc, RwLock};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::fs;
+use tower_http::compression::CompressionLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-/// Represents a content node in the knowledge graph.
-/// Nodes are uniquely identified by their content and source filename.
-#[derive(Debug, Hash, Eq, Clone, PartialEq, Ord, PartialOrd)]
-pub struct Node {
-    content: String,
-    filename: String,
-}
+mod openapi;
 
-impl Node {
-    pub fn new(content: String, filename: String) -> Self {
-        Self { content, filename }
-    }
-}
+/// Maximum number of idempotency keys remembered at once
+const IDEMPOTENCY_CAPACITY: usize = 1024;
 
-/// Represents a Structural directed edge between two nodes in the knowledge graph.
-/// Edges track the version/timestamp when they were created and can be tagged.
-#[derive(Debug, Clone, PartialEq)]
-pub struct Edge {
-    /// Version number or timestamp for CRDT conflict resolution
-    pub version: i32,
-    /// Optional tag for categorizing edges
-    pub tag: String,
+/// Bounded cache of recently seen `Idempotency-Key`s and the write responses they produced.
+/// Keys expire after a TTL; when full, the oldest key is evicted.
+///
+/// Entries are keyed on the idempotency key and the path written, so a client reusing
+/// a key for another file gets a fresh write instead of the other file's response.
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    entries: HashMap<(String, String), (SystemTime, StatusCode, serde_json::Value)>,
+    /// `(key, path)` entries in insertion order, for expiry and eviction
+    order: VecDeque<(String, String)>,
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
 }
 
-impl Edge {
-    pub fn new(version: i32, tag: String) -> Self {
-        Self { version, tag }
+impl IdempotencyCache {
+    /// Creates a cache that expires keys after `ttl`, as measured by `clock`.
+    pub fn new(ttl: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            ttl,
+            clock,
+        }
     }
-}
 
-/// Ledger file that tracks which nodes have been read.
-/// This is a single .ledger file that accumulates node IDs as files are read.
-/// When writing, these nodes are used as references.
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct Ledger {
-    /// Node indices that have been read
-    pub node_indices: Vec<usize>,
-}
+    /// Returns the cached response for a key's write to `path`, if it hasn't expired.
+    pub fn get(&mut self, key: &str, path: &str) -> Option<(StatusCode, serde_json::Value)> {
+        self.evict_expired();
+        self.entries
+            .get(&(key.to_string(), path.to_string()))
+            .map(|(_, status, body)| (*status, body.clone()))
+    }
 
-impl Ledger {
-    pub fn new() -> Self {
-        Self {
-            node_indices: Vec::new(),
+    /// Remembers the response produced for a key's write to `path`.
+    pub fn insert(
+        &mut self,
+        key: String,
+        path: String,
+        status: StatusCode,
+        body: serde_json::Value,
+    ) {
+        let key = (key, path);
+        self.evict_expired();
+        while self.order.len() >= IDEMPOTENCY_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
         }
+        self.order.push_back(key.clone());
+        self.entries.insert(key, (self.clock.now(), status, body));
     }
 
-    pub fn add_nodes(&mut self, nodes: Vec<usize>) {
-        self.node_indices.extend(nodes);
-        // Remove duplicates while preserving order
-        let mut seen = std::collections::HashSet::new();
-        self.node_indices.retain(|&x| seen.insert(x));
+    /// Forgets every remembered key.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
     }
-}
 
-/// Shared application state
-#[derive(Clone)]
-pub struct AppState {
-    kb: Arc<RwLock<KnowledgeBase>>,
-    /// Directory where files are saved/loaded
-    file_dir: String,
+    fn evict_expired(&mut self) {
+        let now = self.clock.now();
+        // Keys are stored in insertion order, so expired keys are at the front
+        while let Some(oldest) = self.order.front() {
+            match self.entries.get(oldest) {
+                Some((seen_at, _, _))
+                    if now.duration_since(*seen_at).unwrap_or_default() < self.ttl =>
+                {
+                    break;
+                }
+                _ => {
+                    let oldest = self.order.pop_front().unwrap();
+                    self.entries.remove(&oldest);
+                }
+            }
+        }
+    }
 }
 
-/// A graph-based CRDT for tracking provenance and relationships in a knowledge base.
-///
-/// This structure maintains a directed graph where:
-/// - Nodes represent content chunks (e.g., paragraphs from markdown files)
-/// - Edges represent sequential relationships with version tracking
+/// Append-only JSONL log of every mutation, kept as `ops.log` in the file directory.
 ///
-/// The CRDT uses Last-Write-Wins (LWW) semantics based on version numbers.
+/// Each line records the operation, path, and ingest stats with a timestamp, giving a
+/// replayable history independent of the graph's internal versioning.
 #[derive(Debug)]
-pub struct KnowledgeBase {
-    /// Maps node index pairs (from, to) to edges
-    edge_table: BTreeMap<(usize, usize), Edge>,
-    /// Maps from reference nodes to nodes
-    ref_table: BTreeMap<(usize, usize), Edge>,
-    /// Ordered set of unique nodes
-    node_table: IndexSet<Node>,
-}
-
-impl KnowledgeBase {
-    /// Creates a new empty knowledge base.
-    pub fn new() -> Self {
-        Self {
-            edge_table: BTreeMap::new(),
-            ref_table: BTreeMap::new(),
-            node_table: IndexSet::new(),
-        }
-    }
+pub struct OpsLog {
+    file: Mutex<std::fs::File>,
+    /// Canonical path of the log file
+    path: std::path::PathBuf,
+    clock: Arc<dyn Clock>,
+}
 
-    /// Inserts a directory node into the knowledge base.
-    ///
-    /// # Arguments
-    /// * `directory_path` - Path of the directory (e.g., "src/components")
-    ///
-    /// # Returns
-    /// The index of the created directory node
-    pub fn insert_directory(&mut self, directory_path: &str) -> usize {
-        let dir_node = Node::new(format!("DIR: {}", directory_path), "".to_string());
-        self.node_table.insert(dir_node.clone());
-        self.node_table.get_index_of(&dir_node).unwrap()
+impl OpsLog {
+    /// Opens the log for appending, timestamping entries by `clock`.
+    pub fn open(path: &str, clock: Arc<dyn Clock>) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+            path: std::fs::canonicalize(path)?,
+            clock,
+        })
     }
 
-    /// Inserts a generic node into the knowledge base.
-    ///
-    /// # Arguments
-    /// * `content` - Content of the node
-    /// * `filename` - Source filename for provenance tracking
-    ///
-    /// # Returns
-    /// The index of the created node
-    pub fn insert_node(&mut self, content: &str, filename: &str) -> usize {
-        let node = Node::new(content.to_string(), filename.to_string());
-        self.node_table.insert(node.clone());
-        self.node_table.get_index_of(&node).unwrap()
+    /// Checks whether `path` names the log file, resolving `.`, `..`, and symlinks,
+    /// so reads can refuse to save a file over it.
+    pub fn is_at(&self, path: &std::path::Path) -> bool {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return false;
+        };
+        std::fs::canonicalize(parent).is_ok_and(|parent| parent.join(name) == self.path)
     }
 
-    /// Inserts markdown content into the knowledge base.
-    ///
-    /// The markdown is split by newlines. Each line becomes a node, and sequential 
-    /// nodes are connected by edges with the given version.
-    /// A file node is created and linked to the parent node, then all content nodes
-    /// are linked sequentially starting from the file node.
-    ///
-    /// # Arguments
-    /// * `markdown_content` - Raw markdown text to process
-    /// * `filename` - Source filename for provenance tracking
-    /// * `parent_idx` - Index of the parent node (e.g., directory node)
-    /// * `version` - Version number for CRDT conflict resolution (higher = newer)
-    /// * `tag` - Tag to apply to all edges created from this content
-    ///
-    /// # Returns
-    /// The index of the file node created
-    pub fn insert_markdown(
-        &mut self,
-        markdown_content: &str,
-        filename: &str,
-        parent_idx: usize,
-        reference_nodes: Vec<Node>,
-        version: i32,
-        tag: &str,
-    ) -> usize {
-        // Create file node and link it to parent
-        let file_node = Node::new(format!("FILE: {}", filename), filename.to_string());
-        self.node_table.insert(file_node.clone());
-        let file_idx = self.node_table.get_index_of(&file_node).unwrap();
-        
-        // Create structural edge from parent to file
-        self.edge_table
-            .entry((parent_idx, file_idx))
-            .or_insert_with(|| Edge::new(version, tag.to_string()));
-
-        // Split markdown by lines and create nodes
-        let content_nodes: Vec<Node> = markdown_content
-            .split('\n')
-            .filter(|line| !line.is_empty())
-            .map(|line| Node::new(line.to_string(), filename.to_string()))
-            .collect();
+    /// Appends one entry and flushes it to disk. Failures are logged rather than
+    /// failing the mutation, which has already been applied.
+    pub fn append(&self, op: &str, path: &str, details: serde_json::Value) {
+        let timestamp_ms = self.clock.unix_millis();
+        let mut entry = serde_json::json!({
+            "timestamp_ms": timestamp_ms,
+            "op": op,
+            "path": path,
+        });
+        if let (Some(fields), serde_json::Value::Object(details)) =
+            (entry.as_object_mut(), details)
+        {
+            fields.extend(details);
+        }
 
-        if content_nodes.is_empty() {
-            return file_idx;
+        let mut file = self.file.lock().unwrap();
+        let result = writeln!(file, "{}", entry).and_then(|_| file.sync_data());
+        if let Err(err) = result {
+            tracing::warn!("Failed to append to ops log: {}", err);
         }
+    }
+}
+
+/// Locks on individual file paths, so writes to one file are serialized without
+/// blocking writes to other files.
+#[derive(Debug, Default)]
+pub struct FileLocks {
+    /// Locks by path. Entries are weak so a lock is freed once nobody holds or
+    /// waits for it.
+    locks: Mutex<HashMap<String, Weak<tokio::sync::Mutex<()>>>>,
+}
 
-        let mut new_node_indices = Vec::new();
+impl FileLocks {
+    /// Locks a file path, waiting until no other request holds it.
+    pub async fn lock(&self, filepath: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            // Only paths being written have live entries, so this stays small
+            locks.retain(|_, lock| lock.strong_count() > 0);
+            match locks.get(filepath).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    let lock = Arc::new(tokio::sync::Mutex::new(()));
+                    locks.insert(filepath.to_string(), Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+        lock.lock_owned().await
+    }
+}
 
-        // Insert first content node and link it from file node
-        self.node_table.insert(content_nodes[0].clone());
-        let first_content_idx = self.node_table.get_index_of(&content_nodes[0]).unwrap();
-        new_node_indices.push(first_content_idx);
+/// Requests served since startup, for the shutdown report
+#[derive(Debug, Default)]
+pub struct ServeCounters {
+    /// Ingests logged by `AppState::log_ingest`
+    ingests: AtomicU64,
+    /// Successful file reads
+    reads: AtomicU64,
+}
 
-        // Link file node to first content node
-        self.edge_table
-            .entry((file_idx, first_content_idx))
-            .or_insert_with(|| Edge::new(version, tag.to_string()));
+/// Media type of MessagePack request and response bodies
+const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
 
-        // Insert remaining nodes and create edges
-        for window in content_nodes.windows(2) {
-            let from_node = &window[0];
-            let to_node = &window[1];
+/// Response body encodings a client can ask for with the `Accept` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Json,
+    MessagePack,
+}
 
-            let is_new = self.node_table.insert(to_node.clone());
+impl Encoding {
+    /// Picks MessagePack if the `Accept` header lists it, JSON otherwise
+    fn negotiate(headers: &HeaderMap) -> Self {
+        let accepts_msgpack = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|media_type| media_type.split(';').next())
+            .any(|media_type| {
+                matches!(media_type.trim(), MSGPACK_CONTENT_TYPE | "application/x-msgpack")
+            });
+        if accepts_msgpack {
+            Encoding::MessagePack
+        } else {
+            Encoding::Json
+        }
+    }
 
-            let from_idx = self.node_table.get_index_of(from_node).unwrap();
-            let to_idx = self.node_table.get_index_of(to_node).unwrap();
-            if is_new {
-                new_node_indices.push(to_idx)
-            };
+    /// Short name of the encoding, used to keep ETags of the encodings apart
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::MessagePack => "msgpack",
+        }
+    }
+}
 
-            let edge_key = (from_idx, to_idx);
+/// A response body serialized as JSON or MessagePack, as negotiated by
+/// `Encoding::negotiate`. Used for error bodies as well, so clients decoding
+/// MessagePack never get JSON back.
+struct Encoded<T>(Encoding, T);
 
-            // Only insert if edge doesn't exist - this preserves divergent paths
-            self.edge_table
-                .entry(edge_key)
-                .or_insert_with(|| Edge::new(version, tag.to_string()));
+impl<T: Serialize> IntoResponse for Encoded<T> {
+    fn into_response(self) -> Response {
+        // The body depends on Accept, so caches must keep the encodings apart
+        let vary = [(header::VARY, HeaderValue::from_static("accept"))];
+        match self.0 {
+            Encoding::Json => (vary, Json(self.1)).into_response(),
+            Encoding::MessagePack => match rmp_serde::to_vec_named(&self.1) {
+                Ok(bytes) => (
+                    vary,
+                    [(header::CONTENT_TYPE, HeaderValue::from_static(MSGPACK_CONTENT_TYPE))],
+                    bytes,
+                )
+                    .into_response(),
+                Err(err) => {
+                    tracing::warn!("Failed to encode MessagePack response: {}", err);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            },
         }
+    }
+}
 
-        // Insert references
-        for reference_node in reference_nodes {
-            self.node_table.insert(reference_node.clone());
+/// Folds a filename to lowercase if `case_insensitive` is set, so `Docs/A.md` and
+/// `docs/a.md` key the same file chain and materialize to the same path on disk.
+/// The graph only stores the folded name; the original casing is not kept.
+fn fold_path_case(filepath: &str, case_insensitive: bool) -> String {
+    if case_insensitive {
+        filepath.to_lowercase()
+    } else {
+        filepath.to_string()
+    }
+}
 
-            let from_idx = self.node_table.get_index_of(&reference_node).unwrap();
-            for to_idx in new_node_indices.clone().into_iter() {
-                let edge_key = (from_idx, to_idx);
+/// Shared application state
+#[derive(Clone)]
+pub struct AppState {
+    kb: Arc<RwLock<KnowledgeBase>>,
+    /// Directory where files are saved/loaded
+    file_dir: String,
+    /// Optional upstream liasiondb server used as a read-through fallback
+    upstream_url: Option<String>,
+    /// Client for upstream reads, with connect and request timeouts
+    upstream_client: reqwest::Client,
+    /// Options applied to every ingest
+    ingest_options: IngestOptions,
+    /// Hosts that `POST /fetch` may download from (empty disables fetching)
+    fetch_allowed_hosts: Vec<String>,
+    /// Client for `POST /fetch`, with connect and request timeouts
+    fetch_client: reqwest::Client,
+    /// Largest body `POST /fetch` downloads
+    fetch_max_bytes: usize,
+    /// Recently seen idempotency keys for `write_file`
+    idempotency: Arc<Mutex<IdempotencyCache>>,
+    /// Per-file locks held by `write_file`
+    file_locks: Arc<FileLocks>,
+    /// Audit log of mutations, if enabled with `OPS_LOG`
+    ops_log: Option<Arc<OpsLog>>,
+    /// Bearer token required by admin endpoints (unset disables them)
+    admin_token: Option<String>,
+    /// Write-ahead log the knowledge base is persisted to, if enabled with `WAL_DIR`
+    wal: Option<Arc<Mutex<WriteAheadLog>>>,
+    /// Most ledger entries used as reference nodes per write (unset = all)
+    ledger_max_refs: Option<usize>,
+    /// How long `run_query` waits for an expensive query
+    query_timeout: Duration,
+    /// Graph revision, bumped after every mutation; the ETag of listings
+    revision: Arc<AtomicU64>,
+    /// Whether `POST /selftest` is served, set with `ENABLE_SELFTEST`
+    selftest_enabled: bool,
+    /// When the server started, for the uptime in the shutdown report
+    started_at: Instant,
+    /// Ingests and reads served, for the shutdown report
+    counters: Arc<ServeCounters>,
+    /// Whether filenames are folded to lowercase, set with `CASE_INSENSITIVE_PATHS`
+    case_insensitive_paths: bool,
+}
 
-                // Only insert if edge doesn't exist - this preserves divergent paths
-                self.ref_table
-                    .entry(edge_key)
-                    .or_insert_with(|| Edge::new(version, tag.to_string()));
-            }
-        }
+impl AppState {
+    /// Checks whether a URL may be fetched: http(s) only, and only from allowed hosts.
+    fn is_fetch_allowed(&self, url: &reqwest::Url) -> bool {
+        matches!(url.scheme(), "http" | "https")
+            && url.host_str().is_some_and(|host| {
+                self.fetch_allowed_hosts
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(host))
+            })
+    }
 
-        file_idx
+    /// Returns the filename a request path refers to, see `fold_path_case`.
+    fn canonical_path(&self, filepath: &str) -> String {
+        fold_path_case(filepath, self.case_insensitive_paths)
     }
 
-    /// Returns an immutable reference to the node table.
-    pub fn nodes(&self) -> &IndexSet<Node> {
-        &self.node_table
+    /// Returns the current graph revision. Read it before reading the knowledge base,
+    /// so a response is never tagged with a revision newer than its content.
+    fn revision(&self) -> u64 {
+        self.revision.load(Ordering::Acquire)
     }
 
-    /// Returns an immutable reference to the edge table.
-    pub fn edges(&self) -> &BTreeMap<(usize, usize), Edge> {
-        &self.edge_table
+    /// Marks the graph as changed, invalidating the ETags of listings.
+    fn bump_revision(&self) {
+        self.revision.fetch_add(1, Ordering::AcqRel);
     }
 
-    /// Traverses the graph starting from a given node index, following the
-    /// edges with the highest version numbers (most recent path).
+    /// Records an ingest in the ops log, if enabled, bumps the graph revision, and
+    /// warns if the ingest left nodes with more divergent successors than `MAX_FAN_OUT`.
     ///
-    /// Returns a vector of node indices representing the traversal path.
-    pub fn traverse_latest_path(&self, start_idx: usize) -> Vec<usize> {
-        let mut path = Vec::new();
-        let mut current_idx = start_idx;
-
-        loop {
-            path.push(current_idx);
-
-            // Find all outgoing edges from current node
-            let next_edge = self
-                .edge_table
-                .range((current_idx, usize::MIN)..(current_idx + 1, usize::MIN))
-                .max_by_key(|(_, edge)| edge.version);
-
-            match next_edge {
-                Some(((_, to_idx), _)) => {
-                    current_idx = *to_idx;
-                }
-                None => break,
-            }
+    /// Call this while still holding the knowledge base write lock, so log order
+    /// matches the order mutations were applied.
+    fn log_ingest(&self, op: &str, path: &str, stats: &IngestStats) {
+        self.bump_revision();
+        self.counters.ingests.fetch_add(1, Ordering::Relaxed);
+        let exceeded = stats.high_fan_out_nodes > 0;
+        if let Some(max_fan_out) = self.ingest_options.max_fan_out.filter(|_| exceeded) {
+            tracing::warn!(
+                "{} of {} left {} nodes with more than {} successors, see GET /stats",
+                op,
+                path,
+                stats.high_fan_out_nodes,
+                max_fan_out,
+            );
+        }
+        if let Some(ops_log) = &self.ops_log {
+            ops_log.append(op, path, serde_json::json!({ "stats": stats }));
         }
-
-        path
     }
 
-    /// Pretty prints the traversal path starting from a given node.
-    pub fn print_latest_path(&self, start_idx: usize) {
-        let path = self.traverse_latest_path(start_idx);
-
-        for idx in path {
-            println!("{:?}", self.node_table.get_index(idx));
+    /// Checks the `Authorization: Bearer` header against the admin token.
+    /// Returns 403 if admin endpoints are disabled and 401 if the token is wrong.
+    fn authorize_admin(&self, headers: &HeaderMap) -> Result<(), StatusCode> {
+        let Some(admin_token) = &self.admin_token else {
+            return Err(StatusCode::FORBIDDEN);
+        };
+        let presented = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if presented == Some(admin_token.as_str()) {
+            Ok(())
+        } else {
+            Err(StatusCode::UNAUTHORIZED)
         }
     }
 
-    /// Performs breadth-first search to find all nodes "contaminated" by a given node.
-    /// This follows the reference edges forward (from the given node to all nodes it influenced).
-    ///
-    /// # Arguments
-    /// * `start_idx` - The index of the node to start the search from
-    ///
-    /// # Returns
-    /// A vector of node indices that are contaminated (influenced) by the starting node
-    pub fn find_contaminated_nodes(&self, start_idx: usize) -> Vec<usize> {
-        use std::collections::{HashSet, VecDeque};
-
-        let mut visited = HashSet::new();
-        let mut queue = VecDeque::new();
-        let mut contaminated = Vec::new();
-
-        queue.push_back(start_idx);
-        visited.insert(start_idx);
-
-        while let Some(current_idx) = queue.pop_front() {
-            contaminated.push(current_idx);
-
-            // Find all outgoing reference edges from current node
-            for ((from_idx, to_idx), _) in self
-                .ref_table
-                .range((current_idx, usize::MIN)..(current_idx + 1, usize::MIN))
-            {
-                if *from_idx == current_idx && !visited.contains(to_idx) {
-                    visited.insert(*to_idx);
-                    queue.push_back(*to_idx);
-                }
-            }
+    /// Returns the directory of a workspace (the file directory itself if unnamed).
+    fn workspace_dir(&self, workspace: &str) -> String {
+        if workspace.is_empty() {
+            self.file_dir.clone()
+        } else {
+            format!("{}/{}", self.file_dir, workspace)
         }
-
-        contaminated
     }
+}
 
-    /// Performs breadth-first search to find all nodes referenced by a given node.
-    /// This follows the reference edges backward (from the given node to all nodes that influenced it).
-    ///
-    /// # Arguments
-    /// * `start_idx` - The index of the node to start the search from
-    ///
-    /// # Returns
-    /// A vector of node indices that are referenced (influenced) the starting node
-    pub fn find_referenced_nodes(&self, start_idx: usize) -> Vec<usize> {
-        use std::collections::{HashSet, VecDeque};
-
-        let mut visited = HashSet::new();
-        let mut queue = VecDeque::new();
-        let mut referenced = Vec::new();
-
-        queue.push_back(start_idx);
-        visited.insert(start_idx);
-
-        while let Some(current_idx) = queue.pop_front() {
-            referenced.push(current_idx);
-
-            // Find all incoming reference edges to current node
-            for ((from_idx, to_idx), _) in self.ref_table.iter() {
-                if *to_idx == current_idx && !visited.contains(from_idx) {
-                    visited.insert(*from_idx);
-                    queue.push_back(*from_idx);
-                }
-            }
-        }
 
-        referenced
-    }
+/// Middleware that appends the knowledge base changes made while handling a
+/// request to the write-ahead log before the response is sent.
+///
+/// The log is appended under the knowledge base write lock, so batches are logged
+/// in the order the changes were made. Whichever request finds pending changes
+/// logs them; a request checking afterwards waits on the lock until they're synced.
+async fn persist_changes(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
 
-    /// Returns the number of nodes in the knowledge base.
-    pub fn node_count(&self) -> usize {
-        self.node_table.len()
+    let Some(wal) = &state.wal else {
+        return response;
+    };
+    if !state.kb.read().unwrap().has_journal_records() {
+        return response;
     }
 
-    /// Returns the number of edges in the knowledge base.
-    pub fn edge_count(&self) -> usize {
-        self.edge_table.len()
+    let mut kb = state.kb.write().unwrap();
+    if let Err(err) = wal.lock().unwrap().append(&mut kb) {
+        tracing::error!("Failed to append to write-ahead log: {}", err);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
     }
+    response
+}
 
-    /// Lists all unique filenames in the knowledge base.
-    pub fn list_files(&self) -> Vec<String> {
-        let mut files: Vec<String> = self
-            .node_table
-            .iter()
-            .filter(|node| node.content.starts_with("FILE: "))
-            .map(|node| node.content.strip_prefix("FILE: ").unwrap().to_string())
-            .collect();
-        files.sort();
-        files.dedup();
-        files
-    }
-
-    /// Reconstructs a markdown file from the knowledge base by traversing from a file node.
-    /// Returns both the markdown content and the node indices that composed it.
-    pub fn read_file(&self, filename: &str) -> Option<(String, Vec<usize>)> {
-        // Find the file node
-        let file_node = Node::new(format!("FILE: {}", filename), filename.to_string());
-        let file_idx = self.node_table.get_index_of(&file_node)?;
-
-        // Traverse from the file node to get all content
-        let path = self.traverse_latest_path(file_idx);
-        
-        // Skip the first node (FILE node itself) and collect content
-        let mut node_indices = Vec::new();
-        let mut markdown_parts = Vec::new();
-        
-        for idx in path.iter().skip(1) {
-            if let Some(node) = self.node_table.get_index(*idx) {
-                let content = &node.content;
-                markdown_parts.push(content.clone());
-                node_indices.push(*idx);
-            }
-        }
-
-        // Join markdown lines back together
-        let markdown = markdown_parts.join("\n");
-        Some((markdown, node_indices))
+/// Bumps the graph revision after every request that changed the knowledge base,
+/// whatever its method, so read-only POSTs such as batch node lookups keep cached
+/// listings valid. A change made by a concurrent request may bump it once more,
+/// which only costs a cache miss.
+async fn track_revision(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let changes_before = state.kb.read().unwrap().change_count();
+    let response = next.run(request).await;
+    if state.kb.read().unwrap().change_count() != changes_before {
+        state.bump_revision();
     }
+    response
 }
 
-impl Default for KnowledgeBase {
-    fn default() -> Self {
-        Self::new()
+/// Answers `304 Not Modified` if `If-None-Match` names `etag`, and otherwise tags the
+/// response built by `respond` with it. For listings that only change with the graph.
+fn with_etag(headers: &HeaderMap, etag: String, respond: impl FnOnce() -> Response) -> Response {
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| etag_matches(value, &etag));
+    let etag_header = [(header::ETAG, format!("\"{}\"", etag))];
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, etag_header).into_response();
     }
+    (etag_header, respond()).into_response()
 }
 
 // ============================================================================
@@ -406,17 +462,90 @@ async fn health() -> &'static str {
     "OK"
 }
 
+#[derive(Deserialize)]
+struct StatsQuery {
+    /// Out-degree above which nodes are listed (default `MAX_FAN_OUT`)
+    max_fan_out: Option<usize>,
+}
+
+/// Reports graph size and the nodes whose structural out-degree exceeds the fan-out
+/// threshold, as a health signal for a graph that is diverging too much. Tagged
+/// with the graph revision and encoding like `list_files`.
+async fn stats(
+    State(state): State<AppState>,
+    Query(params): Query<StatsQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let encoding = Encoding::negotiate(&headers);
+    let etag = format!("r{}-{}", state.revision(), encoding.name());
+    with_etag(&headers, etag, || stats_response(&state, &params, encoding))
+}
+
+/// Builds the statistics returned by `stats`
+fn stats_response(state: &AppState, params: &StatsQuery, encoding: Encoding) -> Response {
+    let max_fan_out = params.max_fan_out.or(state.ingest_options.max_fan_out);
+    let kb = state.kb.read().unwrap();
+    let high_fan_out: Vec<serde_json::Value> = max_fan_out
+        .map(|max_fan_out| kb.high_fan_out_nodes(max_fan_out))
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(idx, out_degree)| {
+            let mut result = node_json(&kb, idx)?;
+            result["out_degree"] = serde_json::json!(out_degree);
+            Some(result)
+        })
+        .collect();
+    let body = serde_json::json!({
+        "nodes": kb.node_count(),
+        "edges": kb.edge_count(),
+        "references": kb.references().len(),
+        "max_fan_out": max_fan_out,
+        "high_fan_out": high_fan_out,
+    });
+    Encoded(encoding, body).into_response()
+}
+
+/// Query parameters for exports
+#[derive(Deserialize)]
+struct ExportQuery {
+    /// Replace the content of content nodes with `redacted-N` placeholders
+    #[serde(default)]
+    redact: bool,
+}
+
+/// Exports the reference graph as W3C PROV-JSON, for provenance tooling
+async fn export_prov(
+    State(state): State<AppState>,
+    Query(params): Query<ExportQuery>,
+) -> Response {
+    let prov = state.kb.read().unwrap().to_prov_json(params.redact);
+    (
+        [(header::CONTENT_TYPE, "application/prov+json")],
+        Json(prov),
+    )
+        .into_response()
+}
+
+/// Reports the crate version and the snapshot schema version
+async fn version() -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "schema_version": SCHEMA_VERSION,
+    }))
+}
+
+/// Serves the OpenAPI description of this API
+async fn openapi_json() -> Json<serde_json::Value> {
+    Json(openapi::spec())
+}
+
 /// Clear the ledger file
 async fn clear_ledger(
     State(state): State<AppState>,
     Query(params): Query<WorkspaceQuery>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
     // Determine workspace directory
-    let workspace_dir = if params.workspace.is_empty() {
-        state.file_dir.clone()
-    } else {
-        format!("{}/{}", state.file_dir, params.workspace)
-    };
+    let workspace_dir = state.workspace_dir(&params.workspace);
     
     let ledger_path = format!("{}/.ledger", workspace_dir);
     
@@ -434,158 +563,2690 @@ async fn clear_ledger(
     })))
 }
 
-/// Query parameters for workspace selection
-#[derive(Deserialize)]
-struct WorkspaceQuery {
-    #[serde(default)]
-    workspace: String,
+/// Rebuilds the knowledge base's derived reverse index of reference edges, in case
+/// it drifted from the edges themselves
+async fn reindex(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    state.authorize_admin(&headers)?;
+
+    let mut kb = state.kb.write().unwrap();
+    let drifted = kb.rebuild_reverse_index();
+    if drifted {
+        tracing::warn!("Reverse reference index had drifted and was rebuilt");
+    }
+    Ok(Json(serde_json::json!({
+        "status": "reindexed",
+        "drifted": drifted,
+        "references": kb.references().len(),
+    })))
 }
 
-/// Lists all files in the knowledge base
-async fn list_files(State(state): State<AppState>) -> Json<Vec<String>> {
-    let kb = state.kb.read().unwrap();
-    Json(kb.list_files())
+/// Path of the document written by `POST /selftest`
+const SELFTEST_PATH: &str = "selftest/selftest.md";
+
+/// Document written by `POST /selftest`, without blank lines so that no chunker
+/// drops any of it
+const SELFTEST_DOCUMENT: &str =
+    "# Self-test\nThis line is read back\n## Second section\nSo is this one";
+
+/// Content of the reference node the self-test document is written against
+const SELFTEST_REFERENCE: &str = "liasiondb self-test reference";
+
+/// Outcome of one step of `POST /selftest`
+#[derive(Serialize)]
+struct SelftestStep {
+    step: &'static str,
+    passed: bool,
+    detail: String,
 }
 
-/// Reads a file from the knowledge base and saves it with a .ledger file
-async fn read_file(
-    State(state): State<AppState>,
-    Path(filepath): Path<String>,
-    Query(params): Query<WorkspaceQuery>,
-) -> Result<String, StatusCode> {
-    let content: String;
-    let node_indices: Vec<usize>;
-    
-    {
-        let kb = state.kb.read().unwrap();
-        match kb.read_file(&filepath) {
-            Some(result) => {
-                content = result.0;
-                node_indices = result.1;
-            },
-            None => return Err(StatusCode::NOT_FOUND),
+/// Runs the self-test steps in order against `kb`, stopping at the first failure.
+fn run_selftest(kb: &mut KnowledgeBase, options: &IngestOptions) -> Vec<SelftestStep> {
+    let mut steps = Vec::new();
+    let mut report = |step, passed, detail: String| {
+        steps.push(SelftestStep { step, passed, detail });
+        passed
+    };
+
+    let reference = Node::new(SELFTEST_REFERENCE.to_string(), String::new());
+    let written = KnowledgeBase::prepare_content(SELFTEST_DOCUMENT, options).and_then(|prepared| {
+        let expected = prepared.lines().join("\n");
+        kb.write_prepared(SELFTEST_PATH, prepared, vec![reference.clone()], options, None, false)
+            .map(|stats| (stats, expected))
+    });
+    let (stats, expected) = match written {
+        Ok(written) => written,
+        Err(err) => {
+            report("ingest", false, err.to_string());
+            return steps;
         }
-    }
+    };
+    let detail = format!("{} nodes and {} edges added", stats.nodes_added, stats.edges_added);
+    report("ingest", true, detail);
 
-    // Determine workspace directory
-    let workspace_dir = if params.workspace.is_empty() {
-        state.file_dir.clone()
+    let Some((content, node_indices)) = kb.read_file(SELFTEST_PATH) else {
+        report("read", false, "file not found after ingest".to_string());
+        return steps;
+    };
+    let detail = if content == expected {
+        format!("{} lines read back", node_indices.len())
     } else {
-        format!("{}/{}", state.file_dir, params.workspace)
+        format!("read back {:?}, expected {:?}", content, expected)
     };
-    
-    // Save file to disk
-    let file_path = format!("{}/{}", workspace_dir, filepath);
-    
-    // Ensure parent directory exists
-    if let Some(parent) = std::path::Path::new(&file_path).parent() {
-        fs::create_dir_all(parent).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !report("read", content == expected, detail) {
+        return steps;
     }
-    
-    fs::write(&file_path, &content).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    // Update the workspace-specific .ledger file
-    let ledger_path = format!("{}/.ledger", workspace_dir);
-    let mut ledger = if let Ok(ledger_content) = fs::read_to_string(&ledger_path).await {
-        serde_json::from_str::<Ledger>(&ledger_content).unwrap_or_else(|_| Ledger::new())
-    } else {
-        Ledger::new()
+
+    let Some(reference_idx) = kb.nodes().get_index_of(&reference) else {
+        report("contamination", false, "reference node not found".to_string());
+        return steps;
     };
-    
-    ledger.add_nodes(node_indices);
-    
-    let ledger_json = serde_json::to_string_pretty(&ledger).unwrap();
-    fs::write(&ledger_path, ledger_json).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let contaminated = kb.find_contaminated_nodes(reference_idx);
+    let missing = node_indices
+        .iter()
+        .filter(|idx| !contaminated.contains(*idx))
+        .count();
+    let detail = format!(
+        "{} of {} lines traced to the reference",
+        node_indices.len() - missing,
+        node_indices.len()
+    );
+    report("contamination", missing == 0, detail);
+    steps
+}
+
+/// Ingests a known document, reads it back, and checks that a contamination query
+/// from its reference reaches every line, reporting whether each step passed.
+///
+/// The steps run against a scratch knowledge base with the server's node identity
+/// and ingest options, so the served graph is left untouched.
+async fn selftest(State(state): State<AppState>) -> (StatusCode, Json<serde_json::Value>) {
+    if !state.selftest_enabled {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "status": "self-test disabled" })),
+        );
+    }
 
-    Ok(content)
+    let node_identity = state.kb.read().unwrap().node_identity();
+    let mut kb = KnowledgeBase::with_node_identity(node_identity);
+    let steps = run_selftest(&mut kb, &state.ingest_options);
+    let failed = steps.iter().find(|step| !step.passed);
+    if let Some(step) = failed {
+        tracing::warn!("Self-test failed at {}: {}", step.step, step.detail);
+    }
+    let passed = failed.is_none();
+    let status_code = if passed {
+        StatusCode::OK
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    (
+        status_code,
+        Json(serde_json::json!({
+            "status": if passed { "passed" } else { "failed" },
+            "steps": steps,
+        })),
+    )
 }
 
-/// Request body for writing a file
+/// Query parameters for resetting the knowledge base
 #[derive(Deserialize)]
-struct WriteFileRequest {
-    content: String,
+struct ResetQuery {
+    /// Re-insert the example document after clearing
+    #[serde(default)]
+    reseed: bool,
 }
 
-/// Writes a file to the knowledge base, using .ledger file for reference nodes
-async fn write_file(
+/// Clears the whole knowledge base, for starting fresh in test/dev environments.
+///
+/// Ledgers are removed too, since their node IDs no longer refer to anything.
+async fn reset_kb(
     State(state): State<AppState>,
-    Path(filepath): Path<String>,
-    Query(params): Query<WorkspaceQuery>,
-    Json(payload): Json<WriteFileRequest>,
+    Query(params): Query<ResetQuery>,
+    headers: HeaderMap,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
-    // Determine workspace directory
-    let workspace_dir = if params.workspace.is_empty() {
-        state.file_dir.clone()
-    } else {
-        format!("{}/{}", state.file_dir, params.workspace)
+    state.authorize_admin(&headers)?;
+
+    let (nodes_cleared, edges_cleared, references_cleared) = {
+        let mut kb = state.kb.write().unwrap();
+        let counts = (kb.node_count(), kb.edge_count(), kb.references().len());
+        kb.clear();
+        if params.reseed {
+            seed_example(&mut kb);
+        }
+        if let Some(ops_log) = &state.ops_log {
+            ops_log.append("reset", "", serde_json::json!({ "reseed": params.reseed }));
+        }
+        counts
     };
-    
-    // Read the workspace-specific .ledger file to get reference nodes
-    let ledger_path = format!("{}/.ledger", workspace_dir);
-    let reference_nodes = if let Ok(ledger_content) = fs::read_to_string(&ledger_path).await {
-        match serde_json::from_str::<Ledger>(&ledger_content) {
-            Ok(ledger) => {
-                // Convert node indices to actual nodes
-                let kb = state.kb.read().unwrap();
-                ledger
-                    .node_indices
-                    .iter()
-                    .filter_map(|idx| kb.nodes().get_index(*idx).cloned())
-                    .collect()
+    // Cached write responses name file indices that no longer exist
+    state.idempotency.lock().unwrap().clear();
+
+    // Remove the default ledger and every workspace ledger
+    let mut ledger_paths = vec![format!("{}/.ledger", state.file_dir)];
+    if let Ok(mut entries) = fs::read_dir(&state.file_dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_type().await.is_ok_and(|file_type| file_type.is_dir()) {
+                ledger_paths.push(format!("{}/.ledger", entry.path().display()));
             }
-            Err(_) => {
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    for ledger_path in ledger_paths {
+        match fs::remove_file(&ledger_path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                tracing::warn!("Failed to remove ledger {}: {}", ledger_path, err);
             }
+            _ => {}
         }
-    } else {
-        Vec::new()
-    };
+    }
 
-    // Get or create directory parent node
-    let file_idx: usize;
-    {
-        let mut kb = state.kb.write().unwrap();
-        
-        // Extract directory path from filepath
-        let dir_path = std::path::Path::new(&filepath)
-            .parent()
-            .and_then(|p| p.to_str())
-            .unwrap_or("");
-        
-        let parent_idx = if dir_path.is_empty() {
-            kb.insert_directory(".")
-        } else {
-            kb.insert_directory(dir_path)
-        };
+    Ok(Json(serde_json::json!({
+        "status": "reset",
+        "nodes_cleared": nodes_cleared,
+        "edges_cleared": edges_cleared,
+        "references_cleared": references_cleared,
+        "reseeded": params.reseed,
+    })))
+}
+
+/// Request body for merging ledgers
+#[derive(Deserialize)]
+struct MergeLedgerRequest {
+    /// Workspace whose ledger receives the merged node indices
+    into: String,
+    /// Workspaces whose ledgers are merged in, in order
+    from: Vec<String>,
+}
+
+/// Reads a workspace's ledger, treating a missing ledger as empty
+async fn load_ledger(state: &AppState, workspace: &str) -> Result<Ledger, StatusCode> {
+    let ledger_path = format!("{}/.ledger", state.workspace_dir(workspace));
+    match fs::read_to_string(&ledger_path).await {
+        Ok(ledger_content) => serde_json::from_str(&ledger_content)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR),
+        Err(_) => Ok(Ledger::new()),
+    }
+}
 
-        // Get current highest version
-        let version = kb.edge_count() as i32;
-        
-        // Insert the markdown
-        file_idx = kb.insert_markdown(
-            &payload.content,
-            &filepath,
-            parent_idx,
-            reference_nodes,
-            version,
-            &format!("version-{}", version),
+/// Resolves the most recent `max` node IDs of a ledger to reference nodes. Nodes
+/// that are no longer in the knowledge base are skipped with a warning.
+fn ledger_reference_nodes(kb: &KnowledgeBase, ledger: &Ledger, max: Option<usize>) -> Vec<Node> {
+    let ids = ledger.recent_nodes(max);
+    let resolved = kb.resolve_content_ids(ids);
+    let missing = resolved.iter().filter(|node| node.is_none()).count();
+    if missing > 0 {
+        tracing::warn!(
+            "{} of {} ledger nodes are no longer in the knowledge base",
+            missing,
+            ids.len()
         );
     }
+    resolved.into_iter().flatten().cloned().collect()
+}
+
+/// Merges the ledgers of several workspaces into one
+async fn merge_ledgers(
+    State(state): State<AppState>,
+    Json(payload): Json<MergeLedgerRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let mut ledger = load_ledger(&state, &payload.into).await?;
+    for workspace in &payload.from {
+        ledger.merge(&load_ledger(&state, workspace).await?);
+    }
+
+    let workspace_dir = state.workspace_dir(&payload.into);
+    fs::create_dir_all(&workspace_dir).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let ledger_json = serde_json::to_string_pretty(&ledger).unwrap();
+    fs::write(format!("{}/.ledger", workspace_dir), ledger_json)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     Ok(Json(serde_json::json!({
-        "status": "success",
-        "file_idx": file_idx,
+        "status": "ledgers merged",
+        "workspace": if payload.into.is_empty() { "default" } else { &payload.into },
+        "node_count": ledger.node_ids.len(),
     })))
 }
 
-// ============================================================================
-// Main Application
-// ============================================================================
+/// Query parameters for workspace selection
+#[derive(Deserialize)]
+struct WorkspaceQuery {
+    #[serde(default)]
+    workspace: String,
+}
 
-#[tokio::main]
-async fn main() {
-    // Initialize tracing
-    tracing_subscriber::registry()
+/// Query parameters for reading a file
+#[derive(Deserialize)]
+struct ReadFileQuery {
+    #[serde(default)]
+    workspace: String,
+    /// Whether to save the file to disk and record the read in the ledger
+    #[serde(default = "default_true")]
+    persist: bool,
+    /// Return JSON lines annotated with the version and tag of their edge
+    #[serde(default)]
+    annotate: bool,
+    /// Include traversal metadata headers
+    #[serde(default)]
+    meta: bool,
+    /// Read the file as it was at this named checkpoint
+    #[serde(default)]
+    checkpoint: Option<String>,
+    /// Append a section listing the nodes that influenced the file
+    #[serde(default)]
+    with_references: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Lists all files in the knowledge base. The listing is tagged with the graph
+/// revision, so clients can revalidate it with `If-None-Match`.
+async fn list_files(
+    State(state): State<AppState>,
+    Query(params): Query<ListFilesQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let encoding = Encoding::negotiate(&headers);
+    let etag = format!("r{}-{}", state.revision(), encoding.name());
+    with_etag(&headers, etag, || list_files_response(&state, &params, encoding))
+}
+
+/// Builds the file listing returned by `list_files`
+fn list_files_response(state: &AppState, params: &ListFilesQuery, encoding: Encoding) -> Response {
+    let kb = state.kb.read().unwrap();
+    let files = kb.list_files().into_iter().map(|path| {
+        if !params.sizes {
+            return serde_json::Value::String(path);
+        }
+        let (lines, bytes) = kb.file_size(&path).unwrap_or_default();
+        let forked = kb.is_forked(&path);
+        serde_json::json!({
+            "id": format!("{:016x}", kb.file_id(&path)),
+            "path": path,
+            "lines": lines,
+            "bytes": bytes,
+            "forked": forked,
+        })
+    });
+    Encoded(encoding, files.collect::<Vec<_>>()).into_response()
+}
+
+/// Query parameters for listing files
+#[derive(Deserialize)]
+struct ListFilesQuery {
+    /// List objects with line and byte counts and fork status instead of bare filenames
+    #[serde(default)]
+    sizes: bool,
+}
+
+/// Query parameters for content search
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    /// Rank results by relevance instead of insertion order
+    #[serde(default)]
+    rank: bool,
+    #[serde(default = "default_search_limit")]
+    limit: usize,
+}
+
+fn default_search_limit() -> usize {
+    50
+}
+
+/// Searches content nodes case-insensitively, optionally ranked by relevance
+async fn search(
+    State(state): State<AppState>,
+    Query(params): Query<SearchQuery>,
+) -> Json<Vec<serde_json::Value>> {
+    let kb = state.kb.read().unwrap();
+
+    let hits: Vec<(usize, Option<f32>)> = if params.rank {
+        kb.search_ranked(&params.q, params.limit)
+            .into_iter()
+            .map(|(idx, score)| (idx, Some(score)))
+            .collect()
+    } else {
+        kb.search(&params.q)
+            .into_iter()
+            .take(params.limit)
+            .map(|idx| (idx, None))
+            .collect()
+    };
+
+    let results = hits
+        .into_iter()
+        .filter_map(|(idx, score)| {
+            let mut result = node_json(&kb, idx)?;
+            if let Some(score) = score {
+                result["score"] = serde_json::json!(score);
+            }
+            Some(result)
+        })
+        .collect();
+
+    Json(results)
+}
+
+/// Most memory a compiled search pattern may use, in bytes. Large counted
+/// repetitions like `a{1000}{1000}` blow past this and are rejected.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+#[derive(Deserialize)]
+struct RegexSearchQuery {
+    pattern: String,
+    #[serde(default = "default_search_limit")]
+    limit: usize,
+}
+
+/// Searches content nodes with a regular expression, in insertion order.
+/// Returns 400 with the compile error if the pattern is invalid or too large.
+async fn search_regex(
+    State(state): State<AppState>,
+    Query(params): Query<RegexSearchQuery>,
+) -> Result<Json<Vec<serde_json::Value>>, Response> {
+    let regex = regex::RegexBuilder::new(&params.pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|err| {
+            tracing::warn!("Rejected search pattern {:?}: {}", params.pattern, err);
+            let body = serde_json::json!({
+                "status": "invalid pattern",
+                "error": err.to_string(),
+            });
+            (StatusCode::BAD_REQUEST, Json(body)).into_response()
+        })?;
+
+    let limit = params.limit;
+    let results = run_query(&state, move |kb| {
+        kb.search_regex(&regex)
+            .into_iter()
+            .take(limit)
+            .filter_map(|idx| node_json(kb, idx))
+            .collect()
+    })
+    .await?;
+    Ok(Json(results))
+}
+
+/// Request body for vector similarity search
+#[derive(Deserialize)]
+struct VectorSearchRequest {
+    embedding: Vec<f32>,
+    #[serde(default = "default_top_n")]
+    k: usize,
+}
+
+/// Finds the nodes whose embeddings are most similar to a query embedding.
+/// Returns 422 if the query is invalid or has the wrong dimension.
+async fn search_vector(
+    State(state): State<AppState>,
+    Json(payload): Json<VectorSearchRequest>,
+) -> Result<Json<Vec<serde_json::Value>>, (StatusCode, Json<serde_json::Value>)> {
+    let kb = state.kb.read().unwrap();
+    let nearest = kb.nearest(&payload.embedding, payload.k).map_err(|err| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "status": err.to_string() })),
+        )
+    })?;
+
+    let results = nearest
+        .into_iter()
+        .filter_map(|(idx, score)| {
+            let mut result = node_json(&kb, idx)?;
+            result["score"] = serde_json::json!(score);
+            Some(result)
+        })
+        .collect();
+    Ok(Json(results))
+}
+
+/// Query parameters for top-N listings
+#[derive(Deserialize)]
+struct TopQuery {
+    #[serde(default = "default_top_n")]
+    n: usize,
+}
+
+fn default_top_n() -> usize {
+    10
+}
+
+/// Lists the most referenced nodes with their reference counts
+async fn top_referenced(
+    State(state): State<AppState>,
+    Query(params): Query<TopQuery>,
+) -> Json<Vec<serde_json::Value>> {
+    let kb = state.kb.read().unwrap();
+    let top = kb
+        .top_referenced(params.n)
+        .into_iter()
+        .filter_map(|(idx, count)| {
+            let mut result = node_json(&kb, idx)?;
+            result["count"] = serde_json::json!(count);
+            Some(result)
+        })
+        .collect();
+    Json(top)
+}
+
+/// Most edges returned by one page of `GET /edges?from_lo=&from_hi=`
+const MAX_EDGES_PER_PAGE: usize = 10_000;
+
+/// Query parameters for listing edges
+#[derive(Deserialize)]
+struct EdgesQuery {
+    #[serde(default)]
+    tag_prefix: String,
+    /// First source node index of a page, used together with `from_hi`
+    from_lo: Option<usize>,
+    /// Source node index a page ends before
+    from_hi: Option<usize>,
+    /// First target node index within `from_lo`, to continue a page that stopped
+    /// inside a node (default 0)
+    to_lo: Option<usize>,
+}
+
+/// Lists structural edges whose tag starts with a prefix, sorted by edge key.
+///
+/// With `from_lo` and `from_hi`, only edges whose source is in that range are
+/// listed, at most `MAX_EDGES_PER_PAGE` of them. If a page stops short of `from_hi`,
+/// `X-Next-From-Lo` and `X-Next-To-Lo` give the key of the edge to continue from, so
+/// a node with more edges than the cap is spread over several pages.
+async fn edges_by_tag(
+    State(state): State<AppState>,
+    Query(params): Query<EdgesQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let encoding = Encoding::negotiate(&headers);
+    let kb = state.kb.read().unwrap();
+    let (edges, next) = match (params.from_lo, params.from_hi) {
+        (None, None) if params.to_lo.is_none() => {
+            let edges = kb
+                .edges_by_tag_prefix(&params.tag_prefix)
+                .into_iter()
+                .map(|(edge_key, edge)| (edge_key, edge.clone()))
+                .collect();
+            (edges, None)
+        }
+        (Some(from_lo), Some(from_hi)) if from_lo <= from_hi => {
+            let start = (from_lo, params.to_lo.unwrap_or(0));
+            let page =
+                kb.edges_in_range_from(start, from_hi, &params.tag_prefix, MAX_EDGES_PER_PAGE);
+            (page.edges, page.next)
+        }
+        (from_lo, from_hi) => {
+            let body = serde_json::json!({
+                "status": "invalid range",
+                "error": "from_lo and from_hi must be given together, with from_lo <= from_hi, \
+                          and to_lo only with them",
+                "from_lo": from_lo,
+                "from_hi": from_hi,
+            });
+            return (StatusCode::BAD_REQUEST, Encoded(encoding, body)).into_response();
+        }
+    };
+
+    let edges: Vec<serde_json::Value> = edges
+        .into_iter()
+        .map(|((from_idx, to_idx), edge)| {
+            serde_json::json!({
+                "from": from_idx,
+                "to": to_idx,
+                "version": edge.version,
+                "tag": edge.tag,
+            })
+        })
+        .collect();
+    let mut response = Encoded(encoding, edges).into_response();
+    if let Some((next_from_lo, next_to_lo)) = next {
+        let response_headers = response.headers_mut();
+        response_headers.insert("x-next-from-lo", HeaderValue::from(next_from_lo));
+        response_headers.insert("x-next-to-lo", HeaderValue::from(next_to_lo));
+    }
+    response
+}
+
+/// Lists every edge tag with its number of structural and reference edges, most
+/// used first
+async fn tags(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Encoded<Vec<serde_json::Value>> {
+    let mut counts: Vec<(String, usize)> =
+        state.kb.read().unwrap().tag_stats().into_iter().collect();
+    // Highest count first; the sort is stable, so ties stay in tag order
+    counts.sort_by(|(_, a_count), (_, b_count)| b_count.cmp(a_count));
+    let tags = counts
+        .into_iter()
+        .map(|(tag, count)| serde_json::json!({ "tag": tag, "count": count }))
+        .collect();
+    Encoded(Encoding::negotiate(&headers), tags)
+}
+
+#[derive(Deserialize)]
+struct PopularQuery {
+    /// Number of files to return
+    #[serde(default = "default_popular_n")]
+    n: usize,
+}
+
+fn default_popular_n() -> usize {
+    10
+}
+
+/// Lists the most read files with their read counts, most read first.
+/// Only local reads are counted; annotated reads and upstream fetches are not.
+async fn popular_files(
+    State(state): State<AppState>,
+    Query(params): Query<PopularQuery>,
+    headers: HeaderMap,
+) -> Encoded<Vec<serde_json::Value>> {
+    let files = state
+        .kb
+        .read()
+        .unwrap()
+        .most_read_files(params.n)
+        .into_iter()
+        .map(|(path, reads)| serde_json::json!({ "path": path, "reads": reads }))
+        .collect();
+    Encoded(Encoding::negotiate(&headers), files)
+}
+
+#[derive(Deserialize)]
+struct ChangedQuery {
+    /// Only list files changed after this version
+    since: i32,
+}
+
+/// Lists files changed after a version with their latest version, sorted by path,
+/// so a replica can poll for what to pull. Deleted files are flagged.
+async fn changed_files(
+    State(state): State<AppState>,
+    Query(params): Query<ChangedQuery>,
+    headers: HeaderMap,
+) -> Encoded<Vec<serde_json::Value>> {
+    let kb = state.kb.read().unwrap();
+    let files = kb
+        .files_changed_since(params.since)
+        .into_iter()
+        .map(|(path, version)| {
+            let deleted = kb.file_index(&path).is_none();
+            serde_json::json!({ "path": path, "version": version, "deleted": deleted })
+        })
+        .collect();
+    Encoded(Encoding::negotiate(&headers), files)
+}
+
+/// Dispatches `GET /files/*path`.
+///
+/// axum only allows wildcards at the end of a route, so file sub-resources such as
+/// `{filepath}/history`, `popular`, and `changed` are split off here. A path naming an existing
+/// file always reads that file.
+async fn get_file(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let filepath = state.canonical_path(&filepath);
+    let is_file = state.kb.read().unwrap().file_index(&filepath).is_some();
+    if !is_file && filepath == "popular" {
+        let params: Query<PopularQuery> =
+            Query::try_from_uri(&uri).map_err(|_| StatusCode::BAD_REQUEST)?;
+        return Ok(popular_files(State(state), params, headers).await.into_response());
+    }
+    if !is_file && filepath == "changed" {
+        let params: Query<ChangedQuery> =
+            Query::try_from_uri(&uri).map_err(|_| StatusCode::BAD_REQUEST)?;
+        return Ok(changed_files(State(state), params, headers).await.into_response());
+    }
+    if !is_file {
+        if let Some((filename, resource)) = filepath.rsplit_once('/') {
+            let filename = filename.to_string();
+            match resource {
+                "history" => {
+                    return file_history(State(state), Path(filename))
+                        .await
+                        .map(IntoResponse::into_response);
+                }
+                "dead-branches" => {
+                    return dead_branches(State(state), Path(filename))
+                        .await
+                        .map(IntoResponse::into_response);
+                }
+                "references" => {
+                    return Ok(file_references(State(state), Path(filename))
+                        .await
+                        .into_response());
+                }
+                "checkpoints" => {
+                    return list_checkpoints(State(state), Path(filename))
+                        .await
+                        .map(IntoResponse::into_response);
+                }
+                "nodes" => {
+                    return file_nodes(State(state), Path(filename))
+                        .await
+                        .map(IntoResponse::into_response);
+                }
+                "graph.dot" => {
+                    let params: Query<ExportQuery> =
+                        Query::try_from_uri(&uri).map_err(|_| StatusCode::BAD_REQUEST)?;
+                    return file_graph_dot(State(state), Path(filename), params)
+                        .await
+                        .map(IntoResponse::into_response);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let params: Query<ReadFileQuery> =
+        Query::try_from_uri(&uri).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let response = read_file(State(state.clone()), Path(filepath), params, headers).await?;
+    if response.status().is_success() {
+        state.counters.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    Ok(response)
+}
+
+/// Adds `X-Traversal-*` headers describing how a traversal went
+fn insert_traversal_headers(response_headers: &mut HeaderMap, meta: &TraversalMeta) {
+    response_headers.insert("x-traversal-hops", HeaderValue::from(meta.hops));
+    response_headers.insert(
+        "x-traversal-truncated",
+        HeaderValue::from_static(if meta.truncated { "true" } else { "false" }),
+    );
+    response_headers.insert(
+        "x-traversal-max-branch-factor",
+        HeaderValue::from(meta.max_branch_factor),
+    );
+    response_headers.insert(
+        "x-traversal-termination-reason",
+        HeaderValue::from_static(meta.termination_reason.as_str()),
+    );
+}
+
+/// Reconstructs content downstream of any node, e.g. from a section heading
+/// to the end of its file. Always includes `X-Traversal-*` headers.
+async fn reconstruct(
+    State(state): State<AppState>,
+    Path(idx): Path<usize>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let (content, meta) = state
+        .kb
+        .read()
+        .unwrap()
+        .reconstruct_from(idx)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut response = content_response(content, &headers);
+    insert_traversal_headers(response.headers_mut(), &meta);
+    Ok(response)
+}
+
+/// Returns how many edges of a file's chain were introduced at each version,
+/// as an array sorted by version
+async fn file_history(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    let kb = state.kb.read().unwrap();
+    kb.file_index(&filepath).ok_or(StatusCode::NOT_FOUND)?;
+
+    let history = kb
+        .version_histogram(&filepath)
+        .into_iter()
+        .map(|(version, edges)| serde_json::json!({ "version": version, "edges": edges }))
+        .collect();
+    Ok(Json(history))
+}
+
+/// Lists a file's dead branches with the content of each node along them
+async fn dead_branches(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+) -> Result<Json<Vec<Vec<serde_json::Value>>>, StatusCode> {
+    let kb = state.kb.read().unwrap();
+    kb.file_index(&filepath).ok_or(StatusCode::NOT_FOUND)?;
+
+    let branches = kb
+        .dead_branches(&filepath)
+        .into_iter()
+        .map(|path| {
+            path.into_iter()
+                .filter_map(|idx| {
+                    let node = kb.nodes().get_index(idx)?;
+                    Some(serde_json::json!({
+                        "node_idx": idx,
+                        "kind": kb.node_kind(idx),
+                        "content": node.display_content(),
+                    }))
+                })
+                .collect()
+        })
+        .collect();
+
+    Ok(Json(branches))
+}
+
+/// Lists the nodes that influenced any line of a file, with the lines they influenced
+async fn file_references(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+) -> Result<Json<Vec<serde_json::Value>>, Response> {
+    let references = run_query(&state, move |kb| {
+        let references = kb
+            .file_references(&filepath)?
+            .into_iter()
+            .filter_map(|reference| {
+                let mut json = node_json(kb, reference.node_idx)?;
+                json["lines"] = serde_json::json!(reference.lines);
+                Some(json)
+            })
+            .collect();
+        Some(references)
+    })
+    .await?;
+    references
+        .map(Json)
+        .ok_or_else(|| StatusCode::NOT_FOUND.into_response())
+}
+
+/// Lists every content node of a file across all its versions
+async fn file_nodes(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    let kb = state.kb.read().unwrap();
+    // Deleted files keep their history, so only never-written files are missing
+    kb.max_version(&filepath).ok_or(StatusCode::NOT_FOUND)?;
+
+    let nodes = kb
+        .nodes_for_file(&filepath)
+        .into_iter()
+        .filter_map(|idx| node_json(&kb, idx))
+        .collect();
+    Ok(Json(nodes))
+}
+
+/// Renders a file's version graph and its references as Graphviz DOT
+async fn file_graph_dot(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+    Query(params): Query<ExportQuery>,
+) -> Result<([(header::HeaderName, &'static str); 1], String), StatusCode> {
+    let dot = state
+        .kb
+        .read()
+        .unwrap()
+        .file_to_dot(&filepath, params.redact)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(([(header::CONTENT_TYPE, "text/vnd.graphviz")], dot))
+}
+
+/// Renders a node as JSON, or `None` if the index is out of range
+fn node_json(kb: &KnowledgeBase, idx: usize) -> Option<serde_json::Value> {
+    let node = kb.nodes().get_index(idx)?;
+    Some(serde_json::json!({
+        "node_idx": idx,
+        "kind": kb.node_kind(idx),
+        "content": node.display_content(),
+        "filename": node.filename,
+    }))
+}
+
+/// Query parameters for fetching a single node
+#[derive(Deserialize)]
+struct NodeQuery {
+    /// Also list where the node sits in each file's latest content
+    #[serde(default)]
+    positions: bool,
+}
+
+/// Returns a single node's content and filename, and with `?positions=true` its
+/// positions in the files that contain it
+async fn get_node(
+    State(state): State<AppState>,
+    Path(idx): Path<usize>,
+    Query(params): Query<NodeQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let encoding = Encoding::negotiate(&headers);
+    let node = if params.positions {
+        // Finding positions reconstructs every file
+        let result = run_query(&state, move |kb| {
+            let mut node = node_json(kb, idx)?;
+            let positions: Vec<serde_json::Value> = kb
+                .node_positions(idx)
+                .into_iter()
+                .map(|(path, position)| serde_json::json!({ "path": path, "position": position }))
+                .collect();
+            node["positions"] = positions.into();
+            Some(node)
+        })
+        .await;
+        match result {
+            Ok(node) => node,
+            Err(response) => return response,
+        }
+    } else {
+        node_json(&state.kb.read().unwrap(), idx)
+    };
+
+    match node {
+        Some(node) => Encoded(encoding, node).into_response(),
+        None => {
+            let body = serde_json::json!({ "status": "not found", "node_idx": idx });
+            (StatusCode::NOT_FOUND, Encoded(encoding, body)).into_response()
+        }
+    }
+}
+
+/// Request body for fetching several nodes at once
+#[derive(Deserialize)]
+struct NodeBatchRequest {
+    indices: Vec<usize>,
+}
+
+/// Returns the nodes at the given indices, in request order.
+/// Out-of-range indices yield `null` so results stay aligned with the request.
+async fn get_nodes_batch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    payload: Result<Json<NodeBatchRequest>, JsonRejection>,
+) -> Result<Encoded<Vec<Option<serde_json::Value>>>, (StatusCode, Encoded<serde_json::Value>)> {
+    let encoding = Encoding::negotiate(&headers);
+    let Json(payload) = payload.map_err(|rejection| {
+        let body = serde_json::json!({
+            "status": "invalid request",
+            "error": rejection.body_text(),
+        });
+        (rejection.status(), Encoded(encoding, body))
+    })?;
+
+    let kb = state.kb.read().unwrap();
+    let nodes = payload
+        .indices
+        .into_iter()
+        .map(|idx| node_json(&kb, idx))
+        .collect();
+    Ok(Encoded(encoding, nodes))
+}
+
+/// Request body for storing a node embedding
+#[derive(Deserialize)]
+struct EmbeddingRequest {
+    embedding: Vec<f32>,
+}
+
+/// Stores an externally computed embedding for a node.
+/// Returns 404 for an unknown node and 422 for an empty, non-finite, or
+/// wrongly sized embedding.
+async fn set_node_embedding(
+    State(state): State<AppState>,
+    Path(idx): Path<usize>,
+    Json(payload): Json<EmbeddingRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let dimension = payload.embedding.len();
+    let result = state.kb.write().unwrap().set_embedding(idx, payload.embedding);
+
+    match result {
+        Ok(()) => Ok(Json(serde_json::json!({
+            "status": "stored",
+            "node_idx": idx,
+            "dimension": dimension,
+        }))),
+        Err(err) => {
+            let status_code = match err {
+                EmbeddingError::NodeNotFound => StatusCode::NOT_FOUND,
+                _ => StatusCode::UNPROCESSABLE_ENTITY,
+            };
+            Err((
+                status_code,
+                Json(serde_json::json!({
+                    "status": err.to_string(),
+                    "node_idx": idx,
+                })),
+            ))
+        }
+    }
+}
+
+/// Query parameters for contamination queries
+#[derive(Deserialize)]
+struct ContaminationQuery {
+    /// Only follow reference edges with at least this version
+    since: Option<i32>,
+}
+
+/// Returns the structural edge that latest-path traversal follows from a node, from
+/// `KnowledgeBase::next_structural_edge`, or `null` at the end of a chain
+async fn node_next_edge(
+    State(state): State<AppState>,
+    Path(idx): Path<usize>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let kb = state.kb.read().unwrap();
+    let node = node_json(&kb, idx).ok_or(StatusCode::NOT_FOUND)?;
+    let next_edge = kb.next_structural_edge(idx).map(|((from, to), edge)| {
+        serde_json::json!({
+            "from": from,
+            "to": to,
+            "version": edge.version,
+            "tag": edge.tag,
+            "weight": edge.weight,
+        })
+    });
+    Ok(Json(serde_json::json!({
+        "node": node,
+        "out_degree": kb.out_degree(idx),
+        "next_edge": next_edge,
+    })))
+}
+
+/// Lists the nodes influenced by a node, along with the node itself as the source
+async fn node_contaminated(
+    State(state): State<AppState>,
+    Path(idx): Path<usize>,
+    Query(params): Query<ContaminationQuery>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let result = run_query(&state, move |kb| {
+        let source = node_json(kb, idx)?;
+        let contaminated = match params.since {
+            Some(min_version) => kb.find_contaminated_since(idx, min_version),
+            None => kb.find_contaminated_nodes(idx),
+        };
+        // The search starts with the source itself, which is reported separately
+        let contaminated: Vec<serde_json::Value> = contaminated
+            .into_iter()
+            .skip(1)
+            .filter_map(|idx| node_json(kb, idx))
+            .collect();
+        Some(serde_json::json!({
+            "source": source,
+            "contaminated": contaminated,
+        }))
+    })
+    .await?;
+    result
+        .map(Json)
+        .ok_or_else(|| StatusCode::NOT_FOUND.into_response())
+}
+
+/// Query parameters for diffing two files
+#[derive(Deserialize)]
+struct DiffQuery {
+    /// The old side, e.g. a template
+    a: String,
+    /// The new side, e.g. a draft
+    b: String,
+}
+
+/// Diffs the latest content of two files line by line, from `a` to `b`
+async fn diff_files(
+    State(state): State<AppState>,
+    Query(mut params): Query<DiffQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    params.a = state.canonical_path(&params.a);
+    params.b = state.canonical_path(&params.b);
+    let kb = state.kb.read().unwrap();
+    let Some(lines) = kb.diff_files(&params.a, &params.b) else {
+        let missing = if kb.file_index(&params.a).is_none() { &params.a } else { &params.b };
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "status": "not found",
+                "path": missing,
+            })),
+        ));
+    };
+
+    let count = |matches: fn(&DiffLine) -> bool| lines.iter().filter(|line| matches(line)).count();
+    Ok(Json(serde_json::json!({
+        "a": params.a,
+        "b": params.b,
+        "added": count(|line| matches!(line, DiffLine::Added { .. })),
+        "removed": count(|line| matches!(line, DiffLine::Removed { .. })),
+        "common": count(|line| matches!(line, DiffLine::Common { .. })),
+        "lines": lines,
+    })))
+}
+
+/// Compares a file's latest content in the graph with its materialized copy on disk.
+///
+/// The copy is only written by persisting reads, so it goes stale when the file
+/// changes without being read again. The diff turns the disk copy into the graph's
+/// content, i.e. shows what the next read would change.
+async fn consistency(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+    Query(params): Query<WorkspaceQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let filepath = state.canonical_path(&filepath);
+    let Some(graph_content) = state.kb.read().unwrap().read_file_text(&filepath) else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "status": "not found",
+                "path": filepath,
+            })),
+        ));
+    };
+
+    let file_path = format!(
+        "{}/{}",
+        state.workspace_dir(&params.workspace),
+        disk_path(&filepath)
+    );
+    let disk_content = match fs::read(&file_path).await {
+        Ok(bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => {
+            tracing::warn!("Failed to read {} for a consistency check: {}", file_path, err);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "status": "error",
+                    "path": filepath,
+                })),
+            ));
+        }
+    };
+
+    let status = match &disk_content {
+        None => "missing",
+        Some(disk_content) if *disk_content == graph_content => "match",
+        Some(_) => "mismatch",
+    };
+    let lines = diff_text(disk_content.as_deref().unwrap_or(""), &graph_content);
+    let count = |matches: fn(&DiffLine) -> bool| lines.iter().filter(|line| matches(line)).count();
+    Ok(Json(serde_json::json!({
+        "path": filepath,
+        "disk_path": file_path,
+        "status": status,
+        "added": count(|line| matches!(line, DiffLine::Added { .. })),
+        "removed": count(|line| matches!(line, DiffLine::Removed { .. })),
+        "lines": lines,
+    })))
+}
+
+/// Query parameters for impact reports
+#[derive(Deserialize)]
+struct ImpactQuery {
+    /// Exact content of the source node
+    content: String,
+}
+
+/// Reports what a source influenced: affected node count, affected files, and depth.
+/// Every node whose content matches exactly is treated as a source.
+async fn impact(
+    State(state): State<AppState>,
+    Query(params): Query<ImpactQuery>,
+) -> Result<Json<ImpactReport>, Response> {
+    let report = run_query(&state, move |kb| {
+        let sources: Vec<usize> = kb
+            .nodes()
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.content == params.content)
+            .map(|(idx, _)| idx)
+            .collect();
+        (!sources.is_empty()).then(|| kb.impact_report(&sources))
+    })
+    .await?;
+    report
+        .map(Json)
+        .ok_or_else(|| StatusCode::NOT_FOUND.into_response())
+}
+
+/// Returns the provenance tree of a node, with content and filename for every node
+async fn node_provenance(
+    State(state): State<AppState>,
+    Path(idx): Path<usize>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let tree = run_query(&state, move |kb| {
+        kb.provenance(idx).map(|tree| provenance_json(kb, &tree))
+    })
+    .await?;
+    tree.map(Json)
+        .ok_or_else(|| StatusCode::NOT_FOUND.into_response())
+}
+
+/// Default for `QUERY_TIMEOUT_MS`
+const DEFAULT_QUERY_TIMEOUT_MS: u64 = 10_000;
+
+/// Default for `UPSTREAM_CONNECT_TIMEOUT_MS`
+const DEFAULT_UPSTREAM_CONNECT_TIMEOUT_MS: u64 = 2_000;
+
+/// Default for `UPSTREAM_TIMEOUT_MS`
+const DEFAULT_UPSTREAM_TIMEOUT_MS: u64 = 10_000;
+
+/// Builds the client for upstream reads. An unreachable or stalled upstream fails
+/// the read-through after these timeouts instead of holding the read open.
+fn upstream_client(connect_timeout: Duration, timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(connect_timeout)
+        .timeout(timeout)
+        .build()
+        .expect("Failed to build the upstream HTTP client")
+}
+
+/// Default for `FETCH_CONNECT_TIMEOUT_MS`
+const DEFAULT_FETCH_CONNECT_TIMEOUT_MS: u64 = 2_000;
+
+/// Default for `FETCH_TIMEOUT_MS`
+const DEFAULT_FETCH_TIMEOUT_MS: u64 = 10_000;
+
+/// Default for `FETCH_MAX_BYTES`
+const DEFAULT_FETCH_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+/// Builds the client for `POST /fetch`. Redirects could lead off the allowlist, so
+/// they are not followed; the timeouts keep a slow host from holding the request open.
+fn fetch_client(connect_timeout: Duration, timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .connect_timeout(connect_timeout)
+        .timeout(timeout)
+        .build()
+        .expect("Failed to build the fetch HTTP client")
+}
+
+/// Runs an expensive read-only query on the blocking thread pool, so it doesn't
+/// tie up an async worker, and gives up on it after `QUERY_TIMEOUT_MS`.
+///
+/// Traversals can't be interrupted, so a query that times out still runs to
+/// completion in the background, holding a read lock; its result is dropped
+/// unseen. Returns `503 Service Unavailable` on timeout.
+async fn run_query<T, F>(state: &AppState, query: F) -> Result<T, Response>
+where
+    T: Send + 'static,
+    F: FnOnce(&KnowledgeBase) -> T + Send + 'static,
+{
+    let kb = state.kb.clone();
+    let task = tokio::task::spawn_blocking(move || query(&kb.read().unwrap()));
+    match tokio::time::timeout(state.query_timeout, task).await {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(err)) => {
+            tracing::warn!("Query task failed: {}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+        Err(_) => {
+            tracing::warn!("Query exceeded the {:?} timeout", state.query_timeout);
+            let body = serde_json::json!({
+                "status": "query too expensive",
+                "timeout_ms": state.query_timeout.as_millis() as u64,
+            });
+            Err((StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response())
+        }
+    }
+}
+
+/// Renders a provenance tree as JSON, resolving node indices to their content
+fn provenance_json(kb: &KnowledgeBase, tree: &ProvenanceTree) -> serde_json::Value {
+    let node_json = |idx: usize| {
+        let node = kb.nodes().get_index(idx);
+        serde_json::json!({
+            "node_idx": idx,
+            "kind": kb.node_kind(idx),
+            "content": node.map(Node::display_content),
+            "filename": node.map(|node| node.filename.as_str()),
+        })
+    };
+
+    let mut json = node_json(tree.node_idx);
+    json["structural"] = tree.structural.iter().map(|idx| node_json(*idx)).collect();
+    json["references"] = tree
+        .references
+        .iter()
+        .map(|reference| provenance_json(kb, reference))
+        .collect();
+    json
+}
+
+/// Reads a file from the knowledge base and saves it with a .ledger file
+async fn read_file(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+    Query(params): Query<ReadFileQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    // Annotated reads are an audit view and have no side effects
+    if params.annotate {
+        let (lines, meta) = state
+            .kb
+            .read()
+            .unwrap()
+            .read_file_annotated(&filepath)
+            .ok_or(StatusCode::NOT_FOUND)?;
+        let lines: Vec<serde_json::Value> = lines
+            .into_iter()
+            .map(|(content, version, tag)| {
+                serde_json::json!({
+                    "content": content,
+                    "version": version,
+                    "tag": tag,
+                })
+            })
+            .collect();
+        // The traversal metadata says whether the lines are the whole document
+        let mut body = serde_json::to_value(meta).unwrap();
+        body["lines"] = lines.into();
+        return Ok(Json(body).into_response());
+    }
+
+    // Checkpoint reads are historical, so they neither count as reads nor overwrite
+    // the materialized copy
+    if let Some(name) = &params.checkpoint {
+        let content = state
+            .kb
+            .read()
+            .unwrap()
+            .read_file_at_checkpoint(&filepath, name)
+            .ok_or(StatusCode::NOT_FOUND)?;
+        return Ok(content_response(content, &headers));
+    }
+
+    // Like annotated reads, reads with references are a derived view of the file, so
+    // they aren't saved or recorded either
+    if params.with_references {
+        let document = run_query(&state, move |kb| kb.read_file_with_references(&filepath)).await;
+        return match document {
+            Ok(Some(document)) => Ok(content_response(document, &headers)),
+            Ok(None) => Err(StatusCode::NOT_FOUND),
+            Err(response) => Ok(response),
+        };
+    }
+
+    // Without persistence there is no ledger to update, so skip collecting node IDs.
+    // Traversal metadata comes from the traversal that builds the content, so plain
+    // reads don't pay for it
+    if !params.persist {
+        let local = {
+            let kb = state.kb.read().unwrap();
+            kb.record_read(&filepath);
+            if params.meta {
+                kb.read_file_with_meta(&filepath)
+                    .map(|(content, _, meta)| (content, Some(meta)))
+            } else {
+                kb.read_file_text(&filepath).map(|content| (content, None))
+            }
+        };
+        let (content, meta) = match local {
+            Some(result) => result,
+            None => fetch_from_upstream(&state, &filepath)
+                .await
+                .map(|(content, _, meta)| (content, Some(meta)))
+                .ok_or(StatusCode::NOT_FOUND)?,
+        };
+        return Ok(traversal_response(content, meta, &headers));
+    }
+
+    let local = {
+        let kb = state.kb.read().unwrap();
+        kb.record_read(&filepath);
+        if params.meta {
+            kb.read_file_with_meta(&filepath).map(|(content, node_indices, meta)| {
+                (content, kb.content_ids(&node_indices), Some(meta))
+            })
+        } else {
+            kb.read_file(&filepath).map(|(content, node_indices)| {
+                (content, kb.content_ids(&node_indices), None)
+            })
+        }
+    };
+    let (content, node_ids, meta) = match local {
+        Some(result) => result,
+        // Fall back to the upstream server when configured
+        None => fetch_from_upstream(&state, &filepath)
+            .await
+            .map(|(content, node_ids, meta)| (content, node_ids, Some(meta)))
+            .ok_or(StatusCode::NOT_FOUND)?,
+    };
+
+    // A revalidation that ends in 304 returns no content, so the file is neither
+    // saved nor recorded in the ledger
+    if is_not_modified(&headers, &content) {
+        return Ok(traversal_response(content, meta, &headers));
+    }
+
+    // Determine workspace directory
+    let workspace_dir = state.workspace_dir(&params.workspace);
+    
+    // Save file to disk under a sanitized name; the graph keeps the original path
+    let relative_path = disk_path(&filepath);
+    let file_path = format!("{}/{}", workspace_dir, relative_path);
+
+    // The ops log lives in the file directory, so a file named like it, e.g.
+    // `ops.log` read without a workspace, would overwrite the audit trail
+    if state
+        .ops_log
+        .as_ref()
+        .is_some_and(|ops_log| ops_log.is_at(std::path::Path::new(&file_path)))
+    {
+        let body = serde_json::json!({
+            "status": "path conflict",
+            "error": format!("cannot save {} over the ops log", filepath),
+        });
+        return Ok((StatusCode::CONFLICT, Json(body)).into_response());
+    }
+
+    // Ensure parent directory exists. create_dir_all succeeds if the directories
+    // already exist, so concurrent reads under one directory don't race.
+    let materialized = match std::path::Path::new(&file_path).parent() {
+        Some(parent) => fs::create_dir_all(parent).await,
+        None => Ok(()),
+    };
+    let materialized = match materialized {
+        Ok(()) => fs::write(&file_path, &content).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = materialized {
+        tracing::warn!("Failed to save {} as {}: {}", filepath, file_path, err);
+        // A file and a directory competing for one path, e.g. reading `docs` and
+        // then `docs/a.md`, is a conflict between the two files
+        if let Some(conflict) = materialize_conflict(&workspace_dir, &relative_path).await {
+            let body = serde_json::json!({
+                "status": "path conflict",
+                "error": format!(
+                    "cannot save {} because {} is already saved as a {}",
+                    filepath, conflict.path, conflict.existing,
+                ),
+            });
+            return Ok((StatusCode::CONFLICT, Json(body)).into_response());
+        }
+        // Otherwise the filepath is unusable on this filesystem, which is the
+        // client's problem rather than a server error
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    
+    // Update the workspace-specific .ledger file
+    let ledger_path = format!("{}/.ledger", workspace_dir);
+    let mut ledger = if let Ok(ledger_content) = fs::read_to_string(&ledger_path).await {
+        serde_json::from_str::<Ledger>(&ledger_content).unwrap_or_else(|_| Ledger::new())
+    } else {
+        Ledger::new()
+    };
+    
+    ledger.add_nodes(node_ids);
+    
+    let ledger_json = serde_json::to_string_pretty(&ledger).unwrap();
+    fs::write(&ledger_path, ledger_json).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(traversal_response(content, meta, &headers))
+}
+
+/// Builds a `content_response`, adding `X-Traversal-*` headers if `meta` is given
+fn traversal_response(content: String, meta: Option<TraversalMeta>, headers: &HeaderMap) -> Response {
+    let mut response = content_response(content, headers);
+    if let Some(meta) = meta {
+        insert_traversal_headers(response.headers_mut(), &meta);
+    }
+    response
+}
+
+/// A path in a workspace that is already taken by the wrong kind of entry.
+struct MaterializeConflict {
+    /// The conflicting path, relative to the workspace
+    path: String,
+    /// What is stored there: "file" or "directory"
+    existing: &'static str,
+}
+
+/// Finds why `relative_path` can't be saved in a workspace: one of its parent
+/// components is a file, or the path itself is a directory.
+async fn materialize_conflict(
+    workspace_dir: &str,
+    relative_path: &str,
+) -> Option<MaterializeConflict> {
+    let components: Vec<&str> = relative_path.split('/').collect();
+    for end in 1..=components.len() {
+        let path = components[..end].join("/");
+        let Ok(metadata) = fs::metadata(format!("{}/{}", workspace_dir, path)).await else {
+            return None;
+        };
+        let is_last = end == components.len();
+        if !is_last && !metadata.is_dir() {
+            return Some(MaterializeConflict { path, existing: "file" });
+        }
+        if is_last && metadata.is_dir() {
+            return Some(MaterializeConflict { path, existing: "directory" });
+        }
+    }
+    None
+}
+
+/// Longest path component written to disk, in bytes; most filesystems allow 255
+const MAX_DISK_COMPONENT_LEN: usize = 200;
+
+/// Characters that are invalid in filenames on common filesystems, plus `%`, which
+/// introduces an escape
+const DISK_UNSAFE_CHARS: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*', '%'];
+
+/// Maps a logical filepath to a relative path that is safe to create on disk.
+///
+/// Each component is percent-encoded where it contains characters that are invalid
+/// on common filesystems (`<>:"\|?*`, control characters, `%` itself), `.` and `..`
+/// are encoded so paths can't escape the workspace, and overlong components are
+/// truncated with a hash suffix to keep them distinct.
+fn disk_path(filepath: &str) -> String {
+    filepath
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .map(|component| {
+            let mut encoded = String::new();
+            for c in component.chars() {
+                if c.is_control() || DISK_UNSAFE_CHARS.contains(&c) {
+                    let mut buf = [0; 4];
+                    for byte in c.encode_utf8(&mut buf).bytes() {
+                        encoded.push_str(&format!("%{:02X}", byte));
+                    }
+                } else {
+                    encoded.push(c);
+                }
+            }
+
+            // Dot components would navigate, and Windows drops trailing dots and spaces
+            if encoded == "." || encoded == ".." || encoded.ends_with(['.', ' ']) {
+                let last = encoded.pop().unwrap();
+                encoded.push_str(&format!("%{:02X}", last as u32));
+            }
+
+            if encoded.len() > MAX_DISK_COMPONENT_LEN {
+                let hash = content_checksum(&encoded);
+                let mut end = MAX_DISK_COMPONENT_LEN - 17;
+                while !encoded.is_char_boundary(end) {
+                    end -= 1;
+                }
+                encoded = format!("{}~{}", &encoded[..end], &hash[..16]);
+            }
+            encoded
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Builds a plain-text file response with checksum headers, honoring `If-None-Match`
+fn content_response(content: String, headers: &HeaderMap) -> Response {
+    // Checksum over the exact bytes returned, doubling as the ETag
+    let checksum = content_checksum(&content);
+    // Counted the way `KnowledgeBase::file_size` counts, one line per content node
+    let line_count = if content.is_empty() { 0 } else { content.split('\n').count() };
+    let response_headers = [
+        (HeaderName::from_static("x-content-sha256"), checksum.clone()),
+        (header::ETAG, format!("\"{}\"", checksum)),
+        (HeaderName::from_static("x-line-count"), line_count.to_string()),
+    ];
+
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| etag_matches(value, &checksum));
+
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+
+    (response_headers, content).into_response()
+}
+
+/// Reports a file's metadata without its content: the headers of a plain read, with
+/// `Content-Length` giving the size of the body a read would return, plus the
+/// file's latest version in `X-File-Version`.
+///
+/// Unlike a read, this has no side effects: nothing is saved, recorded in the
+/// ledger, counted, or fetched from the upstream server.
+async fn head_file(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let filepath = state.canonical_path(&filepath);
+    let (content, version) = {
+        let kb = state.kb.read().unwrap();
+        (kb.read_file_text(&filepath), kb.max_version(&filepath))
+    };
+    let Some(content) = content else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let content_length = content.len();
+    let (mut parts, _) = content_response(content, &headers).into_parts();
+    if parts.status.is_success() {
+        parts.headers.insert(header::CONTENT_LENGTH, HeaderValue::from(content_length));
+    }
+    if let Some(version) = version {
+        parts.headers.insert("x-file-version", HeaderValue::from(version));
+    }
+    Response::from_parts(parts, Body::empty())
+}
+
+/// Checks whether a request's `If-None-Match` matches the checksum of `content`, so
+/// `content_response` answers it with `304 Not Modified`. The checksum is only
+/// computed if the header is present.
+fn is_not_modified(headers: &HeaderMap, content: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| etag_matches(value, &content_checksum(content)))
+}
+
+/// Computes the hex-encoded SHA-256 checksum of file content
+fn content_checksum(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// Checks whether an `If-None-Match` header value matches the given checksum
+fn etag_matches(if_none_match: &str, checksum: &str) -> bool {
+    if_none_match.split(',').any(|tag| {
+        let tag = tag.trim();
+        tag == "*" || tag.trim_start_matches("W/").trim_matches('"') == checksum
+    })
+}
+
+/// Fetches a file that is missing locally from the upstream server and ingests it.
+/// Returns the local read of the ingested file, like `KnowledgeBase::read_file_with_meta`
+/// but with content IDs, or `None` if no upstream is configured or the upstream also misses.
+///
+/// A file deleted locally is not fetched, since ingesting it again would undo the delete.
+async fn fetch_from_upstream(
+    state: &AppState,
+    filepath: &str,
+) -> Option<(String, Vec<String>, TraversalMeta)> {
+    let upstream_url = state.upstream_url.as_ref()?;
+    if state.kb.read().unwrap().is_deleted(filepath) {
+        return None;
+    }
+    // A non-persisting read, so the upstream neither materializes the file nor
+    // records the read in its ledger on this server's behalf
+    let url = format!(
+        "{}/files/{}?persist=false",
+        upstream_url.trim_end_matches('/'),
+        filepath
+    );
+
+    let response = match state.upstream_client.get(&url).send().await {
+        Ok(response) => response,
+        Err(err) => {
+            tracing::warn!("Upstream request to {} failed: {}", url, err);
+            return None;
+        }
+    };
+
+    if !response.status().is_success() {
+        return None;
+    }
+
+    let content = response.text().await.ok()?;
+
+    // Ingest locally so subsequent reads are served from this KB, unless the file
+    // was deleted while it was being fetched
+    let mut kb = state.kb.write().unwrap();
+    if kb.is_deleted(filepath) {
+        return None;
+    }
+    let stats = kb
+        .write_file(filepath, &content, Vec::new(), &state.ingest_options)
+        .ok()?;
+    state.log_ingest("upstream", filepath, &stats);
+    let (content, node_indices, meta) = kb.read_file_with_meta(filepath)?;
+    Some((content, kb.content_ids(&node_indices), meta))
+}
+
+/// Request body for writing a file
+#[derive(Deserialize)]
+struct WriteFileRequest {
+    content: String,
+    /// Explicit version for the new edges; must exceed the file's current version
+    #[serde(default)]
+    version: Option<i32>,
+    /// Accept an explicit version that doesn't exceed the file's current version
+    #[serde(default)]
+    force: bool,
+    /// Confidence weight for the new structural edges; defaults to 1.0
+    #[serde(default)]
+    weight: Option<f32>,
+}
+
+/// Query parameters for renaming a file
+#[derive(Deserialize)]
+struct RenameQuery {
+    /// New path of the file
+    to: String,
+}
+
+/// Handles POST requests under `/files/`, dispatching renames and writes.
+///
+/// Like `get_file`, this works around axum's trailing-wildcard rule:
+/// `{filepath}/rename?to=...` renames a file, anything else is a write.
+async fn post_file(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let filepath = state.canonical_path(&filepath);
+    if let Some(checkpoint_filepath) = filepath.strip_suffix("/checkpoints") {
+        if let Ok(payload) = Json::<CheckpointRequest>::from_bytes(&body) {
+            return create_checkpoint(State(state), Path(checkpoint_filepath.to_string()), payload)
+                .await
+                .into_response();
+        }
+    }
+    if let Some(old_filepath) = filepath.strip_suffix("/rename") {
+        if let Ok(params) = Query::<RenameQuery>::try_from_uri(&uri) {
+            return rename_file(State(state), Path(old_filepath.to_string()), params)
+                .await
+                .into_response();
+        }
+    }
+
+    let params = match Query::<WorkspaceQuery>::try_from_uri(&uri) {
+        Ok(params) => params,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let payload = match Json::<WriteFileRequest>::from_bytes(&body) {
+        Ok(payload) => payload,
+        Err(rejection) => return rejection.into_response(),
+    };
+    write_file(State(state), Path(filepath), params, headers, payload)
+        .await
+        .into_response()
+}
+
+/// Request body for naming a checkpoint
+#[derive(Deserialize)]
+struct CheckpointRequest {
+    name: String,
+}
+
+/// Names a file's current state so it can be read back with `?checkpoint=`
+async fn create_checkpoint(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+    Json(payload): Json<CheckpointRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
+    if payload.name.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let version = {
+        let mut kb = state.kb.write().unwrap();
+        let version = kb
+            .checkpoint(&filepath, &payload.name)
+            .ok_or(StatusCode::NOT_FOUND)?;
+        if let Some(ops_log) = &state.ops_log {
+            ops_log.append(
+                "checkpoint",
+                &filepath,
+                serde_json::json!({ "name": payload.name, "version": version }),
+            );
+        }
+        version
+    };
+    Ok((
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "path": filepath,
+            "name": payload.name,
+            "version": version,
+        })),
+    ))
+}
+
+/// Lists a file's named checkpoints, oldest first
+async fn list_checkpoints(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    let kb = state.kb.read().unwrap();
+    kb.max_version(&filepath).ok_or(StatusCode::NOT_FOUND)?;
+    let checkpoints = kb
+        .checkpoints(&filepath)
+        .into_iter()
+        .map(|(name, version)| serde_json::json!({ "name": name, "version": version }))
+        .collect();
+    Ok(Json(checkpoints))
+}
+
+/// Renames a file, linking the new chain back to the old one for provenance.
+/// Returns 404 if the old file doesn't exist and 409 if the new path is taken.
+async fn rename_file(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+    Query(params): Query<RenameQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let to = state.canonical_path(&params.to);
+    let result = {
+        let mut kb = state.kb.write().unwrap();
+        let result = kb.rename_file(&filepath, &to);
+        if let (Some(ops_log), Ok(stats)) = (&state.ops_log, &result) {
+            ops_log.append(
+                "rename",
+                &to,
+                serde_json::json!({ "from": filepath, "stats": stats }),
+            );
+        }
+        result
+    };
+
+    match result {
+        Ok(stats) => Ok(Json(serde_json::json!({
+            "status": "renamed",
+            "from": filepath,
+            "to": to,
+            "file_idx": stats.file_idx,
+            "version": stats.version,
+        }))),
+        Err(err) => {
+            let status_code = match err {
+                RenameError::NotFound => StatusCode::NOT_FOUND,
+                RenameError::AlreadyExists => StatusCode::CONFLICT,
+            };
+            Err((
+                status_code,
+                Json(serde_json::json!({
+                    "status": err.to_string(),
+                    "from": filepath,
+                    "to": to,
+                })),
+            ))
+        }
+    }
+}
+
+/// Writes a file to the knowledge base, using .ledger file for reference nodes
+async fn write_file(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+    Query(params): Query<WorkspaceQuery>,
+    headers: HeaderMap,
+    Json(payload): Json<WriteFileRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
+    // Writes to the same file run one at a time, so concurrent retries with the same
+    // idempotency key can't both write; writes to other files proceed meanwhile
+    let _file_guard = state.file_locks.lock(&filepath).await;
+
+    if let Some(weight) = payload.weight {
+        if !weight.is_finite() || weight < 0.0 {
+            tracing::warn!("Rejected write to {}: invalid edge weight {}", filepath, weight);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    let options = IngestOptions {
+        edge_weight: payload.weight,
+        ..state.ingest_options.clone()
+    };
+
+    // Determine workspace directory
+    let workspace_dir = state.workspace_dir(&params.workspace);
+    
+    // Read the workspace-specific .ledger file to get reference nodes
+    let ledger_path = format!("{}/.ledger", workspace_dir);
+    let reference_nodes = if let Ok(ledger_content) = fs::read_to_string(&ledger_path).await {
+        match serde_json::from_str::<Ledger>(&ledger_content) {
+            Ok(ledger) => {
+                let kb = state.kb.read().unwrap();
+                ledger_reference_nodes(&kb, &ledger, state.ledger_max_refs)
+            }
+            Err(_) => {
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    let idempotency_key = headers
+        .get("idempotency-key")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    // Replays of a key for the same file return the cached result
+    let cached = idempotency_key
+        .as_ref()
+        .and_then(|key| state.idempotency.lock().unwrap().get(key, &filepath));
+    if let Some((status_code, body)) = cached {
+        return Ok((status_code, Json(body)));
+    }
+
+    // Split and check the content before taking the knowledge base lock, which is
+    // then only held for the graph update itself
+    let result = KnowledgeBase::prepare_content(&payload.content, &options)
+        .and_then(|prepared| {
+            // Check for an existing file under the same lock as the write so
+            // concurrent writers agree on created vs updated
+            let mut kb = state.kb.write().unwrap();
+            let created = kb.file_index(&filepath).is_none();
+            let stats = kb.write_prepared(
+                &filepath,
+                prepared,
+                reference_nodes,
+                &options,
+                payload.version,
+                payload.force,
+            )?;
+            state.log_ingest("write", &filepath, &stats);
+            Ok((created, stats))
+        });
+    let (created, stats) = result.map_err(|err| {
+        tracing::warn!("Rejected write to {}: {}", filepath, err);
+        match err {
+            IngestError::StaleVersion { .. } => StatusCode::CONFLICT,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    })?;
+
+    let status_code = if created { StatusCode::CREATED } else { StatusCode::OK };
+    let body = serde_json::json!({
+        "status": if created { "created" } else { "updated" },
+        "file_idx": stats.file_idx,
+        "parent_idx": stats.parent_idx,
+        "directory": KnowledgeBase::directory_path(&filepath),
+    });
+
+    if let Some(key) = idempotency_key {
+        state
+            .idempotency
+            .lock()
+            .unwrap()
+            .insert(key, filepath, status_code, body.clone());
+    }
+
+    Ok((status_code, Json(body)))
+}
+
+/// Request body for fetching a remote URL into the knowledge base
+#[derive(Deserialize)]
+struct FetchRequest {
+    url: String,
+    /// Filepath to ingest the downloaded content at
+    path: String,
+}
+
+/// Downloads a URL and ingests it as markdown, recording the URL as a reference node
+async fn fetch_url(
+    State(state): State<AppState>,
+    Json(mut payload): Json<FetchRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    payload.path = state.canonical_path(&payload.path);
+    let url = reqwest::Url::parse(&payload.url).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if !state.is_fetch_allowed(&url) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let response = state
+        .fetch_client
+        .get(url.clone())
+        .send()
+        .await
+        .map_err(|err| {
+            tracing::warn!("Fetching {} failed: {}", url, err);
+            StatusCode::BAD_GATEWAY
+        })?;
+    if !response.status().is_success() {
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+    let content = read_capped_body(response, state.fetch_max_bytes).await?;
+
+    // The source URL becomes a reference node, like the nodes read from a ledger
+    let source_node = Node::new(url.to_string(), String::new());
+    let (stats, source_idx) = {
+        let mut kb = state.kb.write().unwrap();
+        let stats = kb
+            .write_file(
+                &payload.path,
+                &content,
+                vec![source_node.clone()],
+                &state.ingest_options,
+            )
+            .map_err(|err| {
+                tracing::warn!("Rejected fetched content for {}: {}", payload.path, err);
+                StatusCode::BAD_REQUEST
+            })?;
+        state.log_ingest("fetch", &payload.path, &stats);
+        (stats, kb.node_index(&source_node))
+    };
+
+    Ok(Json(serde_json::json!({
+        "status": "success",
+        "path": payload.path,
+        "stats": stats,
+        "source_idx": source_idx,
+    })))
+}
+
+/// Reads a response body as text, giving up with `413` once it exceeds `max_bytes`
+/// (checked up front against `Content-Length`, then while streaming) and with `502`
+/// if the download fails or times out.
+async fn read_capped_body(
+    mut response: reqwest::Response,
+    max_bytes: usize,
+) -> Result<String, StatusCode> {
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_bytes as u64)
+    {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|err| {
+        tracing::warn!("Downloading {} failed: {}", response.url(), err);
+        StatusCode::BAD_GATEWAY
+    })? {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Maximum size of an uploaded (compressed) archive
+const MAX_ARCHIVE_UPLOAD_BYTES: usize = 16 * 1024 * 1024;
+
+/// Maximum total uncompressed size of an archive, guarding against zip bombs
+const MAX_ARCHIVE_UNCOMPRESSED_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Errors that can occur while unpacking an uploaded archive
+#[derive(Debug)]
+enum ArchiveError {
+    /// The upload is not a readable zip or tar.gz archive
+    Invalid(String),
+    /// The total uncompressed size exceeds `MAX_ARCHIVE_UNCOMPRESSED_BYTES`
+    TooLarge,
+}
+
+/// A file unpacked from an archive: its relative path and content (`None` if not UTF-8)
+type ArchiveEntry = (String, Option<String>);
+
+/// Unpacks a zip or tar.gz archive in memory, detecting the format from its magic bytes
+fn unpack_archive(bytes: &[u8]) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+    if bytes.starts_with(b"PK\x03\x04") {
+        unpack_zip(bytes)
+    } else if bytes.starts_with(&[0x1f, 0x8b]) {
+        unpack_tar_gz(bytes)
+    } else {
+        Err(ArchiveError::Invalid(
+            "expected a zip or tar.gz archive".to_string(),
+        ))
+    }
+}
+
+fn unpack_zip(bytes: &[u8]) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+    let invalid = |err: zip::result::ZipError| ArchiveError::Invalid(err.to_string());
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).map_err(invalid)?;
+
+    let mut remaining = MAX_ARCHIVE_UNCOMPRESSED_BYTES;
+    let mut entries = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(invalid)?;
+        if file.is_dir() {
+            continue;
+        }
+        let Some(path) = file.enclosed_name().and_then(|p| normalize_archive_path(&p)) else {
+            continue;
+        };
+        let content = read_archive_entry(&mut file, &mut remaining)?;
+        entries.push((path, content));
+    }
+
+    Ok(entries)
+}
+
+fn unpack_tar_gz(bytes: &[u8]) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+    let invalid = |err: std::io::Error| ArchiveError::Invalid(err.to_string());
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(bytes));
+
+    let mut remaining = MAX_ARCHIVE_UNCOMPRESSED_BYTES;
+    let mut entries = Vec::new();
+
+    for entry in archive.entries().map_err(invalid)? {
+        let mut entry = entry.map_err(invalid)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let Some(path) = entry.path().ok().and_then(|p| normalize_archive_path(&p)) else {
+            continue;
+        };
+        let content = read_archive_entry(&mut entry, &mut remaining)?;
+        entries.push((path, content));
+    }
+
+    Ok(entries)
+}
+
+/// Reads one archive entry, charging its actual size against the remaining budget.
+/// Declared sizes in archive headers are not trusted.
+fn read_archive_entry(
+    reader: impl std::io::Read,
+    remaining: &mut u64,
+) -> Result<Option<String>, ArchiveError> {
+    use std::io::Read;
+
+    let mut buf = Vec::new();
+    reader
+        .take(*remaining + 1)
+        .read_to_end(&mut buf)
+        .map_err(|err| ArchiveError::Invalid(err.to_string()))?;
+
+    if buf.len() as u64 > *remaining {
+        return Err(ArchiveError::TooLarge);
+    }
+    *remaining -= buf.len() as u64;
+
+    Ok(String::from_utf8(buf).ok())
+}
+
+/// Converts an archive path into a relative filename, rejecting absolute and `..` paths
+fn normalize_archive_path(path: &std::path::Path) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::Normal(part) => parts.push(part.to_str()?.to_string()),
+            std::path::Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("/"))
+    }
+}
+
+/// Maximum size of an uploaded snapshot
+const MAX_SNAPSHOT_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+/// Replaces the whole knowledge base with an uploaded snapshot, as written by the
+/// write-ahead log. The snapshot is validated as a whole before anything changes, so
+/// a malformed dump is rejected with the location of its first problem.
+async fn import_snapshot(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if let Err(status) = state.authorize_admin(&headers) {
+        return status.into_response();
+    }
+
+    // Parsing and validation are CPU-bound, keep them off the async workers
+    let loaded = tokio::task::spawn_blocking(move || KnowledgeBase::load_from_json(&body)).await;
+    let loaded = match loaded {
+        Ok(Ok(loaded)) => loaded,
+        Ok(Err(err)) => {
+            let location = match &err {
+                SnapshotError::Invalid { location, .. } => Some(location.clone()),
+                _ => None,
+            };
+            let body = serde_json::json!({ "error": err.to_string(), "location": location });
+            return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let counts = serde_json::json!({
+        "nodes": loaded.node_count(),
+        "edges": loaded.edge_count(),
+        "references": loaded.references().len(),
+    });
+
+    {
+        let mut kb = state.kb.write().unwrap();
+        kb.replace_with(loaded);
+        // The journal doesn't hold the new contents, so persist them as a snapshot
+        if let Some(wal) = &state.wal {
+            if let Err(err) = wal.lock().unwrap().compact(&kb) {
+                tracing::error!("Failed to snapshot the imported knowledge base: {}", err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+        if let Some(ops_log) = &state.ops_log {
+            ops_log.append("import_snapshot", "", counts.clone());
+        }
+    }
+    // Cached write responses name file indices that may no longer exist
+    state.idempotency.lock().unwrap().clear();
+
+    Json(serde_json::json!({ "status": "imported", "counts": counts })).into_response()
+}
+
+/// Imports every file in an uploaded zip or tar.gz archive into the knowledge base.
+/// Relative paths become filenames and their directories become DIR nodes.
+async fn import_archive(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let field = multipart
+        .next_field()
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let bytes = field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    // Decompression is CPU-bound, keep it off the async workers
+    let entries = tokio::task::spawn_blocking(move || unpack_archive(&bytes))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|err| match err {
+            ArchiveError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ArchiveError::Invalid(message) => {
+                tracing::warn!("Rejected archive upload: {}", message);
+                StatusCode::BAD_REQUEST
+            }
+        })?;
+
+    let mut kb = state.kb.write().unwrap();
+    let files: Vec<serde_json::Value> = entries
+        .into_iter()
+        // Archives from `export_archive` carry a manifest that isn't part of the KB
+        .filter(|(path, _)| path != ARCHIVE_MANIFEST)
+        .map(|(path, content)| (state.canonical_path(&path), content))
+        .map(|(path, content)| match content {
+            None => serde_json::json!({
+                "path": path,
+                "status": "skipped",
+                "reason": "content is not valid UTF-8",
+            }),
+            Some(content) => {
+                match kb.write_file(&path, &content, Vec::new(), &state.ingest_options) {
+                    Ok(stats) => {
+                        state.log_ingest("import", &path, &stats);
+                        serde_json::json!({
+                            "path": path,
+                            "status": "ingested",
+                            "stats": stats,
+                        })
+                    }
+                    Err(err) => serde_json::json!({
+                        "path": path,
+                        "status": "skipped",
+                        "reason": err.to_string(),
+                    }),
+                }
+            }
+        })
+        .collect();
+    kb.rebuild_reverse_index();
+
+    let ingested = files
+        .iter()
+        .filter(|file| file["status"] == "ingested")
+        .count();
+
+    Ok(Json(serde_json::json!({
+        "ingested": ingested,
+        "skipped": files.len() - ingested,
+        "files": files,
+    })))
+}
+
+/// Name of the manifest written as the last entry of an exported archive
+const ARCHIVE_MANIFEST: &str = ".manifest.json";
+
+/// Chunks of an exported archive buffered between the zip writer and the response
+const ARCHIVE_CHANNEL_CAPACITY: usize = 16;
+
+/// Bytes collected before a chunk of an exported archive is sent
+const ARCHIVE_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Streams every file's latest content as a zip archive, keeping the directory structure
+/// of the filenames. A manifest listing each file and its version is written last.
+/// Files are read one at a time, so writes during the export may land in the archive.
+///
+/// With `redact`, every line is replaced by its `redacted-N` placeholder, numbered
+/// across the whole archive.
+async fn export_archive(
+    State(state): State<AppState>,
+    Query(params): Query<ExportQuery>,
+) -> Response {
+    let (sender, receiver) = tokio::sync::mpsc::channel(ARCHIVE_CHANNEL_CAPACITY);
+    let kb = state.kb.clone();
+
+    // Compression and KB reads are blocking, keep them off the async workers
+    tokio::task::spawn_blocking(move || {
+        let errors = sender.clone();
+        if let Err(err) = write_archive(&kb, ChunkSender::new(sender), params.redact) {
+            tracing::warn!("Archive export failed: {}", err);
+            // Abort the response so the client doesn't mistake it for a complete archive
+            let _ = errors.blocking_send(Err(std::io::Error::other(err.to_string())));
+        }
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/zip"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"liasiondb-export.zip\"",
+            ),
+        ],
+        Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(receiver)),
+    )
+        .into_response()
+}
+
+/// Writes the latest content of every file, then the manifest, as a streamed zip
+fn write_archive(
+    kb: &RwLock<KnowledgeBase>,
+    out: ChunkSender,
+    redact: bool,
+) -> Result<(), zip::result::ZipError> {
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    let mut zip = zip::ZipWriter::new_stream(out);
+    let mut manifest = Vec::new();
+    let mut redactor = redact.then(Redactor::default);
+
+    let files = kb.read().unwrap().list_files();
+    for path in files {
+        if path == ARCHIVE_MANIFEST {
+            tracing::warn!("Skipping {} in archive export, the name is reserved", path);
+            continue;
+        }
+        // Hold the lock for one file at a time, so long exports don't block writers
+        let entry = {
+            let kb = kb.read().unwrap();
+            let content = match redactor.as_mut() {
+                Some(redactor) => kb.read_file_redacted(&path, redactor),
+                None => kb.read_file_text(&path),
+            };
+            content.map(|content| (content, kb.max_version(&path)))
+        };
+        // Deleted since the listing
+        let Some((content, version)) = entry else {
+            continue;
+        };
+        zip.start_file(path.as_str(), options)?;
+        zip.write_all(content.as_bytes())?;
+        manifest.push(serde_json::json!({ "path": path, "version": version }));
+    }
+
+    let manifest = serde_json::json!({
+        "schema_version": SCHEMA_VERSION,
+        "redacted": redact,
+        "files": manifest,
+    });
+    zip.start_file(ARCHIVE_MANIFEST, options)?;
+    zip.write_all(&serde_json::to_vec_pretty(&manifest).map_err(std::io::Error::other)?)?;
+    zip.finish()?.flush()?;
+    Ok(())
+}
+
+/// `Write` adapter that forwards buffered chunks to a streaming response body.
+/// Fails with `BrokenPipe` once the client has gone away.
+struct ChunkSender {
+    sender: tokio::sync::mpsc::Sender<Result<Bytes, std::io::Error>>,
+    buffer: Vec<u8>,
+}
+
+impl ChunkSender {
+    fn new(sender: tokio::sync::mpsc::Sender<Result<Bytes, std::io::Error>>) -> Self {
+        Self {
+            sender,
+            buffer: Vec::with_capacity(ARCHIVE_CHUNK_BYTES),
+        }
+    }
+}
+
+impl Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= ARCHIVE_CHUNK_BYTES {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.buffer));
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+    }
+}
+
+/// Deletes a file from the knowledge base.
+/// Returns 404 if the file never existed and 409 if it was already deleted,
+/// e.g. by a concurrent client.
+async fn delete_file(
+    State(state): State<AppState>,
+    Path(filepath): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let filepath = state.canonical_path(&filepath);
+    // Existence is checked and the tombstone written under the same write lock
+    let outcome = {
+        let mut kb = state.kb.write().unwrap();
+        let outcome = kb.delete_file(&filepath);
+        if let (Some(ops_log), DeleteOutcome::Deleted { version }) = (&state.ops_log, outcome) {
+            ops_log.append("delete", &filepath, serde_json::json!({ "version": version }));
+        }
+        outcome
+    };
+
+    match outcome {
+        DeleteOutcome::Deleted { version } => Ok(Json(serde_json::json!({
+            "status": "deleted",
+            "path": filepath,
+            "version": version,
+        }))),
+        DeleteOutcome::AlreadyDeleted => Err((
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "status": "already deleted",
+                "path": filepath,
+            })),
+        )),
+        DeleteOutcome::NotFound => Err((
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "status": "not found",
+                "path": filepath,
+            })),
+        )),
+    }
+}
+
+/// One operation in a transaction request body
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum TransactionOpRequest {
+    Write { path: String, content: String },
+    Delete { path: String },
+    Reference { from: usize, to: usize },
+}
+
+/// Request body for an atomic transaction
+#[derive(Deserialize)]
+struct TransactionRequest {
+    /// Workspace whose ledger supplies the reference nodes for writes
+    #[serde(default)]
+    workspace: String,
+    operations: Vec<TransactionOpRequest>,
+}
+
+/// Applies a list of writes, deletes, and references atomically at one version
+async fn apply_transaction(
+    State(state): State<AppState>,
+    Json(payload): Json<TransactionRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    // Writes reference the workspace ledger, same as a single-file write
+    let ledger_path = format!("{}/.ledger", state.workspace_dir(&payload.workspace));
+    let ledger = match fs::read_to_string(&ledger_path).await {
+        Ok(ledger_content) => serde_json::from_str::<Ledger>(&ledger_content).map_err(|err| {
+            tracing::warn!("Failed to parse ledger {}: {}", ledger_path, err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "status": "invalid ledger" })),
+            )
+        })?,
+        Err(_) => Ledger::default(),
+    };
+
+    let outcome = {
+        let mut kb = state.kb.write().unwrap();
+        let reference_nodes = ledger_reference_nodes(&kb, &ledger, state.ledger_max_refs);
+        let ops = payload
+            .operations
+            .into_iter()
+            .map(|op| match op {
+                TransactionOpRequest::Write { path, content } => TransactionOp::Write {
+                    path: state.canonical_path(&path),
+                    content,
+                    reference_nodes: reference_nodes.clone(),
+                },
+                TransactionOpRequest::Delete { path } => TransactionOp::Delete {
+                    path: state.canonical_path(&path),
+                },
+                TransactionOpRequest::Reference { from, to } => {
+                    TransactionOp::Reference { from, to }
+                }
+            })
+            .collect();
+
+        let outcome = kb.apply_transaction(ops, &state.ingest_options);
+        if let (Some(ops_log), Ok((version, results))) = (&state.ops_log, &outcome) {
+            ops_log.append(
+                "transaction",
+                "",
+                serde_json::json!({ "version": version, "results": results }),
+            );
+        }
+        outcome
+    };
+
+    match outcome {
+        Ok((version, results)) => Ok(Json(serde_json::json!({
+            "status": "committed",
+            "version": version,
+            "results": results,
+        }))),
+        Err(err) => {
+            tracing::warn!("Rolled back transaction: {}", err);
+            let status_code = match err.error {
+                TransactionOpError::FileNotFound | TransactionOpError::NodeNotFound => {
+                    StatusCode::NOT_FOUND
+                }
+                TransactionOpError::AlreadyDeleted => StatusCode::CONFLICT,
+                TransactionOpError::Ingest(_) | TransactionOpError::DuplicatePath => {
+                    StatusCode::BAD_REQUEST
+                }
+            };
+            Err((
+                status_code,
+                Json(serde_json::json!({
+                    "status": "rolled back",
+                    "op_index": err.op_index,
+                    "error": err.to_string(),
+                })),
+            ))
+        }
+    }
+}
+
+/// Asserts many reference edges at once, e.g. provenance computed by an external
+/// pipeline. Returns 404 without applying anything if a `to` index is out of range.
+async fn bulk_references(
+    State(state): State<AppState>,
+    Json(references): Json<Vec<BulkReference>>,
+) -> Result<Json<BulkReferenceStats>, (StatusCode, Json<serde_json::Value>)> {
+    let mut kb = state.kb.write().unwrap();
+    let stats = kb.assert_references(&references).map_err(|err| {
+        tracing::warn!("Rejected bulk references: {}", err);
+        (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "status": "node not found",
+                "index": err.index,
+                "to": err.to,
+            })),
+        )
+    })?;
+    if let Some(ops_log) = &state.ops_log {
+        ops_log.append("references", "", serde_json::json!(stats));
+    }
+    Ok(Json(stats))
+}
+
+// ============================================================================
+// Main Application
+// ============================================================================
+
+/// Populates a knowledge base with the example document
+fn seed_example(kb: &mut KnowledgeBase) {
+    // Create a directory node
+    let docs_dir_idx = kb.insert_directory("docs");
+    
+    // Insert example content
+    let md1 = "# Example Document\n\nThis is some example content.";
+    kb.insert_markdown(
+        md1,
+        "example.md",
+        docs_dir_idx,
+        vec![],
+        0,
+        "version-0",
+    );
+}
+
+/// Populates an empty knowledge base on startup: with the markdown files under
+/// `seed_dir` if given, otherwise with the example document
+fn seed(
+    kb: &mut KnowledgeBase,
+    seed_dir: Option<&str>,
+    options: &IngestOptions,
+    case_insensitive_paths: bool,
+) {
+    match seed_dir {
+        Some(seed_dir) => seed_from_dir(kb, seed_dir, options, case_insensitive_paths),
+        None => seed_example(kb),
+    }
+}
+
+/// Ingests every `.md` file under `seed_dir`, keyed by its path relative to it, so
+/// subdirectories become directory nodes
+fn seed_from_dir(
+    kb: &mut KnowledgeBase,
+    seed_dir: &str,
+    options: &IngestOptions,
+    case_insensitive_paths: bool,
+) {
+    let root = std::path::Path::new(seed_dir);
+    let mut pending = vec![root.to_path_buf()];
+    let mut paths = Vec::new();
+    while let Some(dir) = pending.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                tracing::warn!("Failed to read seed directory {}: {}", dir.display(), err);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_markdown = path.extension().is_some_and(|ext| ext == "md");
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => pending.push(path),
+                Ok(file_type) if file_type.is_file() && is_markdown => paths.push(path),
+                _ => {}
+            }
+        }
+    }
+    // Directory order is arbitrary; sorting makes the seeded graph reproducible
+    paths.sort();
+
+    let mut seeded = 0;
+    for path in paths {
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        let filename = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let filename = fold_path_case(&filename, case_insensitive_paths);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) => {
+                tracing::warn!("Skipped seed file {}: {}", path.display(), err);
+                continue;
+            }
+        };
+        match kb.write_file(&filename, &content, Vec::new(), options) {
+            Ok(stats) => {
+                tracing::info!(
+                    "Seeded {}: {} nodes, {} edges",
+                    filename,
+                    stats.nodes_added,
+                    stats.edges_added
+                );
+                seeded += 1;
+            }
+            Err(err) => tracing::warn!("Skipped seed file {}: {}", filename, err),
+        }
+    }
+    tracing::info!("Seeded {} files from {}", seeded, seed_dir);
+}
+
+/// Builds the router serving the whole API.
+fn app(state: AppState) -> Router {
+    use axum::routing::MethodRouter;
+    Router::new()
+        .route("/health", get(health))
+        .route("/stats", get(stats))
+        .route("/export/prov", get(export_prov))
+        .route("/export/archive", get(export_archive))
+        .route("/version", get(version))
+        .route("/openapi.json", get(openapi_json))
+        .route("/kb", delete(reset_kb))
+        .route("/maintenance/reindex", post(reindex))
+        .route("/selftest", post(selftest))
+        .route("/ledger", delete(clear_ledger))
+        .route("/ledger/merge", post(merge_ledgers))
+        .route("/files", get(list_files))
+        .route("/fetch", post(fetch_url))
+        .route("/transaction", post(apply_transaction))
+        .route("/search", get(search))
+        .route("/search/regex", get(search_regex))
+        .route("/search/vector", post(search_vector))
+        .route("/references/top", get(top_referenced))
+        .route("/references/bulk", post(bulk_references))
+        .route("/edges", get(edges_by_tag))
+        .route("/tags", get(tags))
+        .route("/impact", get(impact))
+        .route("/diff", get(diff_files))
+        .route("/nodes/batch", post(get_nodes_batch))
+        .route("/nodes/:idx", get(get_node))
+        .route("/nodes/:idx/provenance", get(node_provenance))
+        .route("/nodes/:idx/contaminated", get(node_contaminated))
+        .route("/nodes/:idx/next-edge", get(node_next_edge))
+        .route("/nodes/:idx/embedding", post(set_node_embedding))
+        .route("/reconstruct/:idx", get(reconstruct))
+        .route("/consistency/*path", get(consistency))
+        .route(
+            "/import/archive",
+            post(import_archive).layer(DefaultBodyLimit::max(MAX_ARCHIVE_UPLOAD_BYTES)),
+        )
+        .route(
+            "/import/snapshot",
+            post(import_snapshot).layer(DefaultBodyLimit::max(MAX_SNAPSHOT_UPLOAD_BYTES)),
+        )
+        .route(
+            "/files/*path",
+            MethodRouter::new()
+                .get(get_file)
+                .head(head_file)
+                .post(post_file)
+                .delete(delete_file),
+        )
+        .layer(middleware::from_fn_with_state(state.clone(), persist_changes))
+        .layer(middleware::from_fn_with_state(state.clone(), track_revision))
+        // gzip/brotli when the client sends Accept-Encoding; the default predicate
+        // leaves tiny bodies, images, and event streams uncompressed
+        .layer(CompressionLayer::new())
+        .with_state(state)
+}
+
+#[tokio::main]
+async fn main() {
+    // Initialize tracing
+    tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "liasiondb=debug,tower_http=debug,axum=trace".into()),
@@ -594,41 +3255,203 @@ async fn main() {
         .init();
 
     // Create knowledge base and populate with example data
-    let mut kb = KnowledgeBase::new();
-    
-    // Create a directory node
-    let docs_dir_idx = kb.insert_directory("docs");
-    
-    // Insert example content
-    let md1 = "# Example Document\n\nThis is some example content.";
-    kb.insert_markdown(
-        md1,
-        "example.md",
-        docs_dir_idx,
-        vec![],
-        0,
-        "version-0",
+    let node_identity = match std::env::var("NODE_IDENTITY").as_deref() {
+        Ok("content") => NodeIdentity::ContentOnly,
+        Ok("position") => NodeIdentity::ContentFileAndPosition,
+        _ => NodeIdentity::ContentAndFile,
+    };
+    let dedup_key: Option<fn(&str) -> String> = match std::env::var("DEDUP_KEY").as_deref() {
+        Ok("quotes") => Some(straighten_quotes),
+        _ => None,
+    };
+    let hash_seed = std::env::var("HASH_SEED")
+        .ok()
+        .and_then(|value| value.parse().ok());
+    let mut empty_kb = KnowledgeBase::with_node_identity(node_identity);
+    empty_kb.set_hash_seed(hash_seed);
+
+    let ingest_options = IngestOptions {
+        max_node_len: std::env::var("MAX_NODE_LEN")
+            .ok()
+            .and_then(|value| value.parse().ok()),
+        oversize_policy: match std::env::var("OVERSIZE_POLICY").as_deref() {
+            Ok("split") => OversizePolicy::Split,
+            _ => OversizePolicy::Reject,
+        },
+        edge_insert_policy: match std::env::var("EDGE_INSERT_POLICY").as_deref() {
+            Ok("preserve-first") => EdgeInsertPolicy::PreserveFirst,
+            _ => EdgeInsertPolicy::LatestWins,
+        },
+        transform: None,
+        edge_weight: None,
+        chunker: std::env::var("CHUNKER")
+            .ok()
+            .and_then(|name| chunker_by_name(&name)),
+        max_fan_out: std::env::var("MAX_FAN_OUT")
+            .ok()
+            .and_then(|value| value.parse().ok()),
+        max_fork_width: std::env::var("MAX_FORK_WIDTH")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|width| *width > 0),
+    };
+    let seed_dir = std::env::var("SEED_DIR").ok().filter(|dir| !dir.is_empty());
+    let case_insensitive_paths = matches!(
+        std::env::var("CASE_INSENSITIVE_PATHS").as_deref(),
+        Ok("1" | "true")
     );
 
+    // With a write-ahead log, recover from it and only seed a fresh directory
+    let (kb, wal) = match std::env::var("WAL_DIR") {
+        Ok(wal_dir) if !wal_dir.is_empty() => {
+            let snapshot_every = std::env::var("SNAPSHOT_EVERY")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(1000);
+            let (mut wal, mut kb, recovery) =
+                WriteAheadLog::open(&wal_dir, snapshot_every, empty_kb)
+                    .expect("Failed to recover from write-ahead log");
+            // Snapshots don't store the dedup key or hash seed, so set them again
+            kb.set_dedup_key(dedup_key);
+            kb.set_hash_seed(hash_seed);
+            if recovery.torn_tail {
+                tracing::warn!("Discarded a torn batch at the end of the write-ahead log");
+            }
+            if recovery.is_empty() {
+                seed(&mut kb, seed_dir.as_deref(), &ingest_options, case_insensitive_paths);
+                wal.append(&mut kb).expect("Failed to append to write-ahead log");
+            }
+            tracing::info!(
+                "Recovered from {} (snapshot {:?}, {} batches replayed)",
+                wal_dir,
+                recovery.snapshot_seq,
+                recovery.batches_replayed
+            );
+            (kb, Some(Arc::new(Mutex::new(wal))))
+        }
+        _ => {
+            let mut kb = empty_kb;
+            kb.set_dedup_key(dedup_key);
+            seed(&mut kb, seed_dir.as_deref(), &ingest_options, case_insensitive_paths);
+            (kb, None)
+        }
+    };
+
     // Set up shared state
     let file_dir = std::env::var("FILE_DIR").unwrap_or_else(|_| "./files".to_string());
     fs::create_dir_all(&file_dir)
         .await
         .expect("Failed to create file directory");
 
+    let upstream_url = std::env::var("UPSTREAM_URL").ok();
+    let upstream_client = upstream_client(
+        Duration::from_millis(
+            std::env::var("UPSTREAM_CONNECT_TIMEOUT_MS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_UPSTREAM_CONNECT_TIMEOUT_MS),
+        ),
+        Duration::from_millis(
+            std::env::var("UPSTREAM_TIMEOUT_MS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_UPSTREAM_TIMEOUT_MS),
+        ),
+    );
+
+    let fetch_client = fetch_client(
+        Duration::from_millis(
+            std::env::var("FETCH_CONNECT_TIMEOUT_MS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_FETCH_CONNECT_TIMEOUT_MS),
+        ),
+        Duration::from_millis(
+            std::env::var("FETCH_TIMEOUT_MS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_FETCH_TIMEOUT_MS),
+        ),
+    );
+
+    let fetch_allowed_hosts = std::env::var("FETCH_ALLOWED_HOSTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .collect();
+
+    let idempotency_ttl = std::env::var("IDEMPOTENCY_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(300);
+
+
+    // Every timestamp the server assigns comes from this clock
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+
+    let ops_log = match std::env::var("OPS_LOG").as_deref() {
+        Ok("true" | "1") => {
+            let ops_log_path = format!("{}/ops.log", file_dir);
+            let ops_log = OpsLog::open(&ops_log_path, clock.clone())
+                .expect("Failed to open ops log");
+            tracing::info!("Appending mutations to {}", ops_log_path);
+            Some(Arc::new(ops_log))
+        }
+        _ => None,
+    };
+
     let state = AppState {
         kb: Arc::new(RwLock::new(kb)),
         file_dir,
+        upstream_url,
+        upstream_client,
+        ingest_options,
+        fetch_allowed_hosts,
+        fetch_client,
+        fetch_max_bytes: std::env::var("FETCH_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_FETCH_MAX_BYTES),
+        idempotency: Arc::new(Mutex::new(IdempotencyCache::new(
+            Duration::from_secs(idempotency_ttl),
+            clock,
+        ))),
+        file_locks: Arc::new(FileLocks::default()),
+        ops_log,
+        admin_token: std::env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty()),
+        wal,
+        ledger_max_refs: std::env::var("LEDGER_MAX_REFS")
+            .ok()
+            .and_then(|value| value.parse().ok()),
+        query_timeout: Duration::from_millis(
+            std::env::var("QUERY_TIMEOUT_MS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_QUERY_TIMEOUT_MS),
+        ),
+        revision: Arc::new(AtomicU64::new(0)),
+        selftest_enabled: matches!(
+            std::env::var("ENABLE_SELFTEST").as_deref(),
+            Ok("1" | "true")
+        ),
+        started_at: Instant::now(),
+        counters: Arc::new(ServeCounters::default()),
+        case_insensitive_paths,
     };
 
     // Build router
-    use axum::routing::MethodRouter;
-    let app = Router::new()
-        .route("/health", get(health))
-        .route("/ledger", delete(clear_ledger))
-        .route("/files", get(list_files))
-        .route("/files/*path", MethodRouter::new().get(read_file).post(write_file))
-        .with_state(state);
+    let app = app(state.clone());
+
+    // Optionally mount the whole API under a prefix for reverse-proxy deployments
+    let base_path = std::env::var("BASE_PATH").unwrap_or_default();
+    let base_path = base_path.trim_matches('/');
+    let app = if base_path.is_empty() {
+        app
+    } else {
+        tracing::info!("Serving API under /{}", base_path);
+        Router::new().nest(&format!("/{}", base_path), app)
+    };
 
     // Start server
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
@@ -637,7 +3460,65 @@ async fn main() {
     
     tracing::info!("Server listening on {}", listener.local_addr().unwrap());
     
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap();
+
+    log_shutdown_report(&state);
+}
+
+/// Resolves once the process is asked to stop, with Ctrl+C or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl+C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutting down, waiting for in-flight requests");
+}
+
+/// Takes a final snapshot, if the write-ahead log is enabled, and logs a summary
+/// of the server's life as one structured event.
+fn log_shutdown_report(state: &AppState) {
+    let mut kb = state.kb.write().unwrap();
+    let final_snapshot = match &state.wal {
+        None => "disabled",
+        Some(wal) => {
+            let mut wal = wal.lock().unwrap();
+            match wal.append(&mut kb).and_then(|_| wal.compact(&kb)) {
+                Ok(()) => "ok",
+                Err(err) => {
+                    tracing::error!("Failed to write the final snapshot: {}", err);
+                    "failed"
+                }
+            }
+        }
+    };
+    tracing::info!(
+        uptime_secs = state.started_at.elapsed().as_secs(),
+        ingests = state.counters.ingests.load(Ordering::Relaxed),
+        reads = state.counters.reads.load(Ordering::Relaxed),
+        nodes = kb.node_count(),
+        edges = kb.edge_count(),
+        references = kb.references().len(),
+        final_snapshot,
+        "Shutdown report"
+    );
 }
 
 #[cfg(test)]
@@ -694,7 +3575,7 @@ fn old_main() {
     kb.print_latest_path(docs_dir_idx);
 
     println!("\n=== Reference Table ===");
-    println!("{:?}", kb.ref_table);
+    println!("{:?}", kb.references());
 
     // Demonstrate BFS: Find all nodes contaminated by "it came to me in a dream"
     println!("\n=== Nodes Contaminated by 'it came to me in a dream' ===");
@@ -724,3 +3605,848 @@ fn old_main() {
         println!("  [{}] {:?}", idx, kb.nodes().get_index(*idx));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    /// Returns a fresh, empty directory under the system temp directory.
+    fn temp_dir(name: &str) -> String {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "liasiondb-{}-{}-{}",
+            name,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().into_owned()
+    }
+
+    /// State like `main` builds it with no environment set, saving under `file_dir`.
+    fn test_state(kb: KnowledgeBase, file_dir: &str) -> AppState {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        AppState {
+            kb: Arc::new(RwLock::new(kb)),
+            file_dir: file_dir.to_string(),
+            upstream_url: None,
+            upstream_client: upstream_client(
+                Duration::from_millis(DEFAULT_UPSTREAM_CONNECT_TIMEOUT_MS),
+                Duration::from_millis(DEFAULT_UPSTREAM_TIMEOUT_MS),
+            ),
+            ingest_options: IngestOptions::default(),
+            fetch_allowed_hosts: Vec::new(),
+            fetch_client: fetch_client(
+                Duration::from_millis(DEFAULT_FETCH_CONNECT_TIMEOUT_MS),
+                Duration::from_millis(DEFAULT_FETCH_TIMEOUT_MS),
+            ),
+            fetch_max_bytes: DEFAULT_FETCH_MAX_BYTES,
+            idempotency: Arc::new(Mutex::new(IdempotencyCache::new(
+                Duration::from_secs(300),
+                clock,
+            ))),
+            file_locks: Arc::new(FileLocks::default()),
+            ops_log: None,
+            admin_token: None,
+            wal: None,
+            ledger_max_refs: None,
+            query_timeout: Duration::from_millis(DEFAULT_QUERY_TIMEOUT_MS),
+            revision: Arc::new(AtomicU64::new(0)),
+            selftest_enabled: false,
+            started_at: Instant::now(),
+            counters: Arc::new(ServeCounters::default()),
+            case_insensitive_paths: false,
+        }
+    }
+
+    fn request(method: Method, uri: &str, body: impl Into<Body>) -> Request {
+        axum::http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(body.into())
+            .unwrap()
+    }
+
+    /// Sends a request through the router and returns its status, headers, and body.
+    async fn send(app: &Router, request: Request) -> (StatusCode, HeaderMap, String) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// Serves `router` on an ephemeral local port and returns its base URL.
+    async fn serve_stub(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    /// A stub upstream serving `remote.md` and recording the query of every request.
+    async fn stub_upstream(queries: Arc<Mutex<Vec<String>>>) -> String {
+        serve_stub(Router::new().route(
+            "/files/*path",
+            get(move |Path(path): Path<String>, uri: Uri| {
+                queries
+                    .lock()
+                    .unwrap()
+                    .push(uri.query().unwrap_or_default().to_string());
+                async move {
+                    match path.as_str() {
+                        "remote.md" => Ok("# Remote\nfrom upstream"),
+                        _ => Err(StatusCode::NOT_FOUND),
+                    }
+                }
+            }),
+        ))
+        .await
+    }
+
+    fn with_request_header(mut request: Request, name: HeaderName, value: &str) -> Request {
+        request
+            .headers_mut()
+            .insert(name, HeaderValue::from_str(value).unwrap());
+        request
+    }
+
+    fn admin_request(method: Method, uri: &str, body: impl Into<Body>) -> Request {
+        with_request_header(request(method, uri, body), header::AUTHORIZATION, "Bearer secret")
+    }
+
+    fn snapshot_of(files: &[(&str, &str)]) -> serde_json::Value {
+        let mut kb = KnowledgeBase::new();
+        for (path, content) in files {
+            kb.write_file(path, content, Vec::new(), &IngestOptions::default())
+                .unwrap();
+        }
+        serde_json::from_slice(&kb.to_snapshot_json().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reindex_requires_admin_and_reports_drift() {
+        let mut kb = KnowledgeBase::new();
+        let source = Node::new("source".to_string(), String::new());
+        kb.write_file("a.md", "one", vec![source], &IngestOptions::default())
+            .unwrap();
+        let state = test_state(kb, &temp_dir("reindex"));
+        let disabled = app(state.clone());
+        let request = admin_request(Method::POST, "/maintenance/reindex", ());
+        assert_eq!(send(&disabled, request).await.0, StatusCode::FORBIDDEN);
+
+        let app = app(AppState {
+            admin_token: Some("secret".to_string()),
+            ..state
+        });
+        let request = admin_request(Method::POST, "/maintenance/reindex", ());
+        let (status, _, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["drifted"], false);
+        assert_eq!(body["references"], 1);
+    }
+
+    fn conditional_get(uri: &str, etag: &HeaderValue) -> Request {
+        let mut request = request(Method::GET, uri, ());
+        request.headers_mut().insert(header::IF_NONE_MATCH, etag.clone());
+        request
+    }
+
+    #[tokio::test]
+    async fn not_modified_read_neither_saves_nor_records() {
+        let mut kb = KnowledgeBase::new();
+        kb.write_file("a.md", "hello", Vec::new(), &IngestOptions::default())
+            .unwrap();
+        let file_dir = temp_dir("not-modified");
+        let app = app(test_state(kb, &file_dir));
+        let etag = HeaderValue::from_str(&format!("\"{}\"", content_checksum("hello"))).unwrap();
+
+        let (status, _, _) = send(&app, conditional_get("/files/a.md", &etag)).await;
+
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert!(!std::path::Path::new(&format!("{}/a.md", file_dir)).exists());
+        assert!(!std::path::Path::new(&format!("{}/.ledger", file_dir)).exists());
+    }
+
+    #[tokio::test]
+    async fn listing_etag_changes_only_when_the_graph_does() {
+        let state = test_state(KnowledgeBase::new(), &temp_dir("revision"));
+        let app = app(state);
+        let write = |content: &str| {
+            json_request(Method::POST, "/files/a.md", serde_json::json!({ "content": content }))
+        };
+        send(&app, write("one")).await;
+
+        let (status, headers, _) = send(&app, request(Method::GET, "/files", ())).await;
+        assert_eq!(status, StatusCode::OK);
+        let etag = headers[header::ETAG].clone();
+        assert_eq!(send(&app, conditional_get("/files", &etag)).await.0, StatusCode::NOT_MODIFIED);
+
+        // Read-only POSTs and rejected writes change nothing
+        let indices = serde_json::json!({ "indices": [0] });
+        let batch = json_request(Method::POST, "/nodes/batch", indices);
+        assert_eq!(send(&app, batch).await.0, StatusCode::OK);
+        let invalid = json_request(
+            Method::POST,
+            "/files/a.md",
+            serde_json::json!({ "content": "two", "weight": -1.0 }),
+        );
+        assert_eq!(send(&app, invalid).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(send(&app, conditional_get("/files", &etag)).await.0, StatusCode::NOT_MODIFIED);
+
+        assert!(send(&app, write("two")).await.0.is_success());
+        let (status, headers, _) = send(&app, conditional_get("/files", &etag)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(headers[header::ETAG], etag);
+    }
+
+    #[tokio::test]
+    async fn stats_etag_keeps_the_encodings_apart() {
+        let app = app(test_state(KnowledgeBase::new(), &temp_dir("stats")));
+        let (status, headers, _) = send(&app, request(Method::GET, "/stats", ())).await;
+        assert_eq!(status, StatusCode::OK);
+        let json_etag = headers[header::ETAG].clone();
+
+        // The MessagePack body isn't UTF-8, so only the head of the response is read
+        let msgpack = |request| with_request_header(request, header::ACCEPT, MSGPACK_CONTENT_TYPE);
+        let response = app.clone().oneshot(msgpack(request(Method::GET, "/stats", ()))).await;
+        let response = response.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], MSGPACK_CONTENT_TYPE);
+        assert_ne!(response.headers()[header::ETAG], json_etag);
+
+        // A JSON tag doesn't validate a MessagePack body
+        let conditional = msgpack(conditional_get("/stats", &json_etag));
+        let response = app.clone().oneshot(conditional).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn import_snapshot_replaces_the_knowledge_base() {
+        let mut state = test_state(KnowledgeBase::new(), &temp_dir("snapshot"));
+        state.admin_token = Some("secret".to_string());
+        let app = app(state.clone());
+        let snapshot = snapshot_of(&[("a.md", "one\ntwo")]).to_string();
+
+        let unauthorized = request(Method::POST, "/import/snapshot", snapshot.clone());
+        assert_eq!(send(&app, unauthorized).await.0, StatusCode::UNAUTHORIZED);
+
+        let authorized = admin_request(Method::POST, "/import/snapshot", snapshot);
+        assert_eq!(send(&app, authorized).await.0, StatusCode::OK);
+        let (status, _, body) = send(&app, request(Method::GET, "/files/a.md", ())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "one\ntwo");
+    }
+
+    #[tokio::test]
+    async fn import_snapshot_rejects_malformed_dump_unchanged() {
+        let mut kb = KnowledgeBase::new();
+        kb.write_file("kept.md", "kept", Vec::new(), &IngestOptions::default())
+            .unwrap();
+        let mut state = test_state(kb, &temp_dir("snapshot"));
+        state.admin_token = Some("secret".to_string());
+        let app = app(state.clone());
+        let mut snapshot = snapshot_of(&[("a.md", "one\ntwo")]);
+        snapshot["edges"][0]["to"] = serde_json::json!(999);
+
+        let (status, _, body) = send(
+            &app,
+            admin_request(Method::POST, "/import/snapshot", snapshot.to_string()),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["location"], "edges[0].to");
+        let kb = state.kb.read().unwrap();
+        assert!(kb.file_index("kept.md").is_some());
+        assert!(kb.file_index("a.md").is_none());
+    }
+
+    fn json_request(method: Method, uri: &str, body: serde_json::Value) -> Request {
+        with_request_header(
+            request(method, uri, body.to_string()),
+            header::CONTENT_TYPE,
+            "application/json",
+        )
+    }
+
+    #[tokio::test]
+    async fn file_locks_serialize_one_path_only() {
+        let locks = Arc::new(FileLocks::default());
+        let a_guard = locks.lock("a.md").await;
+
+        // Another path can be locked while a.md is held
+        let b_guard = tokio::time::timeout(Duration::from_secs(1), locks.lock("b.md")).await;
+        assert!(b_guard.is_ok());
+
+        // The same path waits until a.md is released
+        let waiter = tokio::spawn({
+            let locks = locks.clone();
+            async move {
+                let _guard = locks.lock("a.md").await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+        drop(a_guard);
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Released locks are forgotten
+        drop(b_guard);
+        let _c_guard = locks.lock("c.md").await;
+        assert_eq!(locks.locks.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writes_to_many_files_lose_no_update() {
+        let state = test_state(KnowledgeBase::new(), &temp_dir("locks"));
+        let app = app(state.clone());
+
+        let writes: Vec<_> = (0..4)
+            .flat_map(|file| (0..8).map(move |write| (file, write)))
+            .map(|(file, write)| {
+                let app = app.clone();
+                tokio::spawn(async move {
+                    let uri = format!("/files/f{}.md", file);
+                    let body = serde_json::json!({
+                        "content": format!("file {} write {}", file, write),
+                    });
+                    send(&app, json_request(Method::POST, &uri, body)).await.0
+                })
+            })
+            .collect();
+        for write in writes {
+            assert!(write.await.unwrap().is_success());
+        }
+
+        let kb = state.kb.read().unwrap();
+        assert_eq!(kb.list_files().len(), 4);
+        for file in 0..4 {
+            let path = format!("f{}.md", file);
+            let written: Vec<String> = (0..8)
+                .map(|write| format!("file {} write {}", file, write))
+                .collect();
+            // Every write left its node, and the file reads back as one of them
+            for content in &written {
+                assert!(kb
+                    .nodes()
+                    .iter()
+                    .any(|node| &node.content == content && node.filename == path));
+            }
+            assert!(written.contains(&kb.read_file_text(&path).unwrap()));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_retries_with_one_idempotency_key_write_once() {
+        let state = test_state(KnowledgeBase::new(), &temp_dir("locks"));
+        let app = app(state.clone());
+
+        let retries: Vec<_> = (0..8)
+            .map(|_| {
+                let app = app.clone();
+                tokio::spawn(async move {
+                    let body = serde_json::json!({ "content": "only once" });
+                    let request = json_request(Method::POST, "/files/once.md", body);
+                    let key = HeaderName::from_static("idempotency-key");
+                    let request = with_request_header(request, key, "k1");
+                    send(&app, request).await.2
+                })
+            })
+            .collect();
+        let mut bodies = Vec::new();
+        for retry in retries {
+            bodies.push(retry.await.unwrap());
+        }
+
+        // A second write would have answered "updated", so every retry got the
+        // first write's cached response
+        assert!(bodies.iter().all(|body| body == &bodies[0]));
+        let body: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(body["status"], "created");
+        assert_eq!(state.counters.ingests.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn idempotency_key_reused_for_another_file_still_writes() {
+        let state = test_state(KnowledgeBase::new(), &temp_dir("idempotency"));
+        let app = app(state.clone());
+        let write = |path: &str| {
+            let body = serde_json::json!({ "content": "hello" });
+            let request = json_request(Method::POST, &format!("/files/{}", path), body);
+            with_request_header(request, HeaderName::from_static("idempotency-key"), "k1")
+        };
+
+        let (_, _, first) = send(&app, write("a.md")).await;
+        let (status, _, second) = send(&app, write("b.md")).await;
+
+        assert_eq!(status, StatusCode::CREATED);
+        assert_ne!(first, second);
+        assert_eq!(state.kb.read().unwrap().list_files(), ["a.md", "b.md"]);
+    }
+
+    #[tokio::test]
+    async fn read_meta_describes_the_content_served() {
+        let mut kb = KnowledgeBase::new();
+        // The repeated line loops the chain back on itself
+        kb.write_file("loop.md", "a\nb\na", Vec::new(), &IngestOptions::default())
+            .unwrap();
+        let app = app(test_state(kb, &temp_dir("meta")));
+
+        for uri in ["/files/loop.md?meta=true", "/files/loop.md?meta=true&persist=false"] {
+            let (status, headers, body) = send(&app, request(Method::GET, uri, ())).await;
+
+            assert_eq!((status, body.as_str()), (StatusCode::OK, "a\nb"));
+            assert_eq!(headers["x-traversal-hops"], "2");
+            assert_eq!(headers["x-traversal-truncated"], "true");
+            assert_eq!(headers["x-traversal-termination-reason"], "cycle");
+        }
+    }
+
+    #[tokio::test]
+    async fn annotated_read_reports_why_the_traversal_stopped() {
+        let mut kb = KnowledgeBase::new();
+        kb.write_file("loop.md", "a\nb\na", Vec::new(), &IngestOptions::default())
+            .unwrap();
+        let app = app(test_state(kb, &temp_dir("annotate")));
+
+        let uri = "/files/loop.md?annotate=true";
+        let (status, _, body) = send(&app, request(Method::GET, uri, ())).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["termination_reason"], "cycle");
+        assert_eq!(body["truncated"], true);
+        let lines: Vec<&str> = body["lines"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|line| line["content"].as_str().unwrap())
+            .collect();
+        assert_eq!(lines, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn read_through_fetches_and_ingests_upstream_file() {
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let mut state = test_state(KnowledgeBase::new(), &temp_dir("upstream"));
+        state.upstream_url = Some(stub_upstream(queries.clone()).await);
+        let app = app(state.clone());
+
+        let (status, _, body) = send(&app, request(Method::GET, "/files/remote.md", ())).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "# Remote\nfrom upstream");
+        assert!(state.kb.read().unwrap().file_index("remote.md").is_some());
+        assert_eq!(*queries.lock().unwrap(), ["persist=false"]);
+    }
+
+    #[tokio::test]
+    async fn read_through_miss_is_not_found() {
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let mut state = test_state(KnowledgeBase::new(), &temp_dir("upstream"));
+        state.upstream_url = Some(stub_upstream(queries.clone()).await);
+        let app = app(state.clone());
+
+        let (status, _, _) = send(&app, request(Method::GET, "/files/missing.md", ())).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(queries.lock().unwrap().len(), 1);
+        assert!(state.kb.read().unwrap().file_index("missing.md").is_none());
+    }
+
+    #[tokio::test]
+    async fn read_through_skips_deleted_files() {
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let mut kb = KnowledgeBase::new();
+        kb.write_file("remote.md", "local", Vec::new(), &IngestOptions::default())
+            .unwrap();
+        kb.delete_file("remote.md");
+        let mut state = test_state(kb, &temp_dir("upstream"));
+        state.upstream_url = Some(stub_upstream(queries.clone()).await);
+        let app = app(state.clone());
+
+        let (status, _, _) = send(&app, request(Method::GET, "/files/remote.md", ())).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(queries.lock().unwrap().is_empty());
+        assert!(state.kb.read().unwrap().is_deleted("remote.md"));
+    }
+
+    #[tokio::test]
+    async fn read_through_gives_up_on_stalled_upstream() {
+        let upstream = serve_stub(Router::new().route(
+            "/files/*path",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                "too late"
+            }),
+        ))
+        .await;
+        let mut state = test_state(KnowledgeBase::new(), &temp_dir("upstream"));
+        state.upstream_url = Some(upstream);
+        state.upstream_client =
+            upstream_client(Duration::from_millis(200), Duration::from_millis(200));
+        let app = app(state);
+
+        let started = Instant::now();
+        let (status, _, _) = send(&app, request(Method::GET, "/files/slow.md", ())).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    /// Sends `POST /fetch` for `{base}/doc.md` from a state allowing the local host.
+    async fn fetch_from(mut state: AppState, base: &str) -> StatusCode {
+        state.fetch_allowed_hosts = vec!["127.0.0.1".to_string()];
+        let body = serde_json::json!({ "url": format!("{}/doc.md", base), "path": "doc.md" });
+        let (status, _, _) = send(&app(state), json_request(Method::POST, "/fetch", body)).await;
+        status
+    }
+
+    #[tokio::test]
+    async fn fetch_rejects_body_over_the_limit() {
+        let oversized = get(|| async { "x".repeat(2048) });
+        let base = serve_stub(Router::new().route("/doc.md", oversized)).await;
+        let mut state = test_state(KnowledgeBase::new(), &temp_dir("fetch"));
+        state.fetch_max_bytes = 1024;
+
+        let status = fetch_from(state.clone(), &base).await;
+
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(state.kb.read().unwrap().file_index("doc.md").is_none());
+    }
+
+    #[tokio::test]
+    async fn fetch_gives_up_on_stalled_host() {
+        let base = serve_stub(Router::new().route(
+            "/doc.md",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                "too late"
+            }),
+        ))
+        .await;
+        let mut state = test_state(KnowledgeBase::new(), &temp_dir("fetch"));
+        state.fetch_client = fetch_client(Duration::from_millis(200), Duration::from_millis(200));
+
+        let started = Instant::now();
+        let status = fetch_from(state, &base).await;
+
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn oversized_line_is_rejected_as_bad_request() {
+        let mut state = test_state(KnowledgeBase::new(), &temp_dir("oversize"));
+        state.ingest_options.max_node_len = Some(1024);
+        let app = app(state.clone());
+        let content = "x".repeat(64 * 1024);
+
+        let body = serde_json::json!({ "content": content });
+        let (status, _, _) = send(&app, json_request(Method::POST, "/files/min.js", body)).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(state.kb.read().unwrap().node_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn read_never_overwrites_the_ops_log() {
+        let mut kb = KnowledgeBase::new();
+        kb.write_file("ops.log", "not the log", Vec::new(), &IngestOptions::default())
+            .unwrap();
+        let file_dir = temp_dir("ops-log");
+        let ops_log_path = format!("{}/ops.log", file_dir);
+        let mut state = test_state(kb, &file_dir);
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        state.ops_log = Some(Arc::new(OpsLog::open(&ops_log_path, clock).unwrap()));
+        let app = app(state);
+
+        for uri in ["/files/ops.log", "/files/ops.log?workspace=."] {
+            let (status, _, _) = send(&app, request(Method::GET, uri, ())).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+        }
+        assert_eq!(std::fs::read_to_string(&ops_log_path).unwrap(), "");
+    }
+
+    #[test]
+    fn disk_path_encodes_problematic_filenames() {
+        assert_eq!(disk_path("notes: draft?.md"), "notes%3A draft%3F.md");
+        assert_eq!(disk_path("100%\tdone"), "100%25%09done");
+        assert_eq!(disk_path("../etc/./passwd"), ".%2E/etc/%2E/passwd");
+        assert_eq!(disk_path("/docs//trailing. "), "docs/trailing.%20");
+
+        let long = "a".repeat(300);
+        let longer = "a".repeat(301);
+        assert!(disk_path(&long).len() <= MAX_DISK_COMPONENT_LEN);
+        assert_ne!(disk_path(&long), disk_path(&longer));
+    }
+
+    #[tokio::test]
+    async fn read_saves_problematic_filename_under_encoded_name() {
+        let file_dir = temp_dir("disk-path");
+        let state = test_state(KnowledgeBase::new(), &file_dir);
+        let app = app(state.clone());
+        let uri = "/files/notes%3A%20draft%3F.md";
+        let body = serde_json::json!({ "content": "hello" });
+        assert_eq!(send(&app, json_request(Method::POST, uri, body)).await.0, StatusCode::CREATED);
+
+        let (status, _, body) = send(&app, request(Method::GET, uri, ())).await;
+
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "hello"));
+        let saved = std::fs::read_to_string(format!("{}/notes%3A draft%3F.md", file_dir));
+        assert_eq!(saved.unwrap(), "hello");
+        assert_eq!(state.kb.read().unwrap().list_files(), ["notes: draft?.md"]);
+    }
+
+    #[tokio::test]
+    async fn unsavable_path_is_unprocessable() {
+        // A regular file where the workspace directory should be
+        let file_dir = format!("{}/not-a-dir", temp_dir("unsavable"));
+        std::fs::write(&file_dir, "").unwrap();
+        let mut kb = KnowledgeBase::new();
+        kb.write_file("a.md", "hello", Vec::new(), &IngestOptions::default())
+            .unwrap();
+        let app = app(test_state(kb, &file_dir));
+
+        let (status, _, _) = send(&app, request(Method::GET, "/files/a.md", ())).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn contamination_since_filters_by_reference_version() {
+        let mut kb = KnowledgeBase::new();
+        let dir_idx = kb.insert_directory(".");
+        let source = Node::new("source".to_string(), String::new());
+        kb.insert_markdown("old", "a.md", dir_idx, vec![source.clone()], 1, "version-1");
+        kb.insert_markdown("new", "b.md", dir_idx, vec![source.clone()], 5, "version-5");
+        let source_idx = kb.node_index(&source).unwrap();
+        let app = app(test_state(kb, &temp_dir("contaminated")));
+        let contaminated = |query: &str| {
+            let uri = format!("/nodes/{}/contaminated{}", source_idx, query);
+            let app = app.clone();
+            async move {
+                let (status, _, body) = send(&app, request(Method::GET, &uri, ())).await;
+                assert_eq!(status, StatusCode::OK);
+                let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                let mut contents: Vec<String> = body["contaminated"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|node| node["content"].as_str().unwrap().to_string())
+                    .collect();
+                contents.sort();
+                contents
+            }
+        };
+
+        assert_eq!(contaminated("").await, ["new", "old"]);
+        assert_eq!(contaminated("?since=5").await, ["new"]);
+    }
+
+    #[tokio::test]
+    async fn write_references_only_the_most_recent_ledger_nodes() {
+        let mut kb = KnowledgeBase::new();
+        for (path, content) in [("r1.md", "one"), ("r2.md", "two"), ("r3.md", "three")] {
+            kb.write_file(path, content, Vec::new(), &IngestOptions::default())
+                .unwrap();
+        }
+        let mut state = test_state(kb, &temp_dir("ledger-cap"));
+        state.ledger_max_refs = Some(2);
+        let app = app(state.clone());
+        for path in ["r1.md", "r2.md", "r3.md"] {
+            let uri = format!("/files/{}", path);
+            assert_eq!(send(&app, request(Method::GET, &uri, ())).await.0, StatusCode::OK);
+        }
+
+        let body = serde_json::json!({ "content": "result" });
+        let (status, _, _) = send(&app, json_request(Method::POST, "/files/out.md", body)).await;
+
+        assert_eq!(status, StatusCode::CREATED);
+        let kb = state.kb.read().unwrap();
+        let mut sources: Vec<&str> = kb
+            .references()
+            .keys()
+            .map(|(from, _)| kb.nodes()[*from].content.as_str())
+            .collect();
+        sources.sort();
+        assert_eq!(sources, ["three", "two"]);
+    }
+
+    #[tokio::test]
+    async fn ledger_references_survive_index_changes_between_read_and_write() {
+        let mut kb = KnowledgeBase::new();
+        kb.write_file("src.md", "fact\ngone", Vec::new(), &IngestOptions::default())
+            .unwrap();
+        let mut state = test_state(kb, &temp_dir("ledger-ids"));
+        state.admin_token = Some("secret".to_string());
+        let app = app(state.clone());
+        let (status, _, _) = send(&app, request(Method::GET, "/files/src.md", ())).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Replace the graph with one where `fact` sits at another index and `gone` is
+        // removed, keeping the ledger
+        let snapshot = snapshot_of(&[("other.md", "noise\nmore"), ("src.md", "fact")]);
+        let import = admin_request(Method::POST, "/import/snapshot", snapshot.to_string());
+        assert_eq!(send(&app, import).await.0, StatusCode::OK);
+        let body = serde_json::json!({ "content": "claim" });
+        let (status, _, _) = send(&app, json_request(Method::POST, "/files/out.md", body)).await;
+
+        assert_eq!(status, StatusCode::CREATED);
+        let kb = state.kb.read().unwrap();
+        let references: Vec<(&str, &str)> = kb
+            .references()
+            .keys()
+            .map(|(from, to)| {
+                (kb.nodes()[*from].content.as_str(), kb.nodes()[*to].content.as_str())
+            })
+            .collect();
+        assert_eq!(references, [("fact", "claim")]);
+    }
+
+    #[test]
+    fn fold_path_case_only_folds_when_case_insensitive() {
+        assert_eq!(fold_path_case("Docs/A.md", true), "docs/a.md");
+        assert_eq!(fold_path_case("Docs/A.md", false), "Docs/A.md");
+    }
+
+    /// Writes `Docs/A.md`, then reads it back as `docs/a.md`.
+    async fn write_then_read_other_case(state: AppState) -> (StatusCode, String) {
+        let app = app(state);
+        let body = serde_json::json!({ "content": "hello" });
+        let write = json_request(Method::POST, "/files/Docs/A.md", body);
+        assert_eq!(send(&app, write).await.0, StatusCode::CREATED);
+        let (status, _, body) = send(&app, request(Method::GET, "/files/docs/a.md", ())).await;
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn case_insensitive_paths_share_one_file() {
+        let file_dir = temp_dir("case-insensitive");
+        let mut state = test_state(KnowledgeBase::new(), &file_dir);
+        state.case_insensitive_paths = true;
+
+        let (status, body) = write_then_read_other_case(state.clone()).await;
+
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "hello"));
+        assert_eq!(state.kb.read().unwrap().list_files(), ["docs/a.md"]);
+        let saved = std::fs::read_to_string(format!("{}/docs/a.md", file_dir));
+        assert_eq!(saved.unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn case_sensitive_paths_are_distinct_files() {
+        let state = test_state(KnowledgeBase::new(), &temp_dir("case-sensitive"));
+
+        let (status, _) = write_then_read_other_case(state.clone()).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(state.kb.read().unwrap().list_files(), ["Docs/A.md"]);
+    }
+
+    #[tokio::test]
+    async fn edge_pages_split_a_node_over_the_cap() {
+        // A hub with an edge to every spoke, and one edge back from the first spoke
+        let spokes: Vec<usize> = (1..=MAX_EDGES_PER_PAGE + 1).collect();
+        let mut snapshot = snapshot_of(&[]);
+        let node = |content: String| serde_json::to_value(Node::new(content, String::new()));
+        let mut nodes = vec![node("hub".to_string()).unwrap()];
+        nodes.extend(spokes.iter().map(|idx| node(format!("spoke {}", idx)).unwrap()));
+        let edge = |from: usize, to: usize| {
+            serde_json::json!({ "from": from, "to": to, "version": 0, "tag": "version-0" })
+        };
+        let mut edges: Vec<serde_json::Value> = spokes.iter().map(|to| edge(0, *to)).collect();
+        edges.push(edge(spokes[0], 0));
+        snapshot["nodes"] = serde_json::json!(nodes);
+        snapshot["edges"] = serde_json::json!(edges);
+        let kb = KnowledgeBase::load_from_json(snapshot.to_string().as_bytes()).unwrap();
+        let app = app(test_state(kb, &temp_dir("edge-pages")));
+        let page = |from_lo: usize, to_lo: usize| {
+            let uri = format!(
+                "/edges?from_lo={}&from_hi={}&to_lo={}",
+                from_lo,
+                spokes[0] + 1,
+                to_lo
+            );
+            let app = app.clone();
+            async move {
+                let (status, headers, body) = send(&app, request(Method::GET, &uri, ())).await;
+                assert_eq!(status, StatusCode::OK);
+                let edges: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+                let header = |name: &str| {
+                    headers.get(name).map(|value| value.to_str().unwrap().parse().unwrap())
+                };
+                let next: Option<(usize, usize)> =
+                    header("x-next-from-lo").zip(header("x-next-to-lo"));
+                (edges.len(), next)
+            }
+        };
+
+        // The hub alone exceeds the cap, so the first page stops inside it
+        let last_spoke = spokes[spokes.len() - 1];
+        assert_eq!(page(0, 0).await, (MAX_EDGES_PER_PAGE, Some((0, last_spoke))));
+        assert_eq!(page(0, last_spoke).await, (2, None));
+    }
+
+    #[tokio::test]
+    async fn edge_page_start_within_a_node_needs_a_range() {
+        let app = app(test_state(KnowledgeBase::new(), &temp_dir("edge-pages")));
+
+        let (status, _, _) = send(&app, request(Method::GET, "/edges?to_lo=3", ())).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    /// Downloads `/export/archive` with a query and returns each entry's name and content.
+    async fn export_archive_entries(app: &Router, query: &str) -> Vec<(String, String)> {
+        let uri = format!("/export/archive{}", query);
+        let response = app.clone().oneshot(request(Method::GET, &uri, ())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+        let mut entries = Vec::new();
+        for idx in 0..archive.len() {
+            let mut entry = archive.by_index(idx).unwrap();
+            let mut content = String::new();
+            std::io::Read::read_to_string(&mut entry, &mut content).unwrap();
+            entries.push((entry.name().to_string(), content));
+        }
+        entries
+    }
+
+    #[tokio::test]
+    async fn redacted_archive_holds_no_content() {
+        let mut kb = KnowledgeBase::new();
+        let files = [("a.md", "secret one\nshared secret"), ("docs/b.md", "shared secret")];
+        for (path, content) in files {
+            kb.write_file(path, content, Vec::new(), &IngestOptions::default())
+                .unwrap();
+        }
+        let app = app(test_state(kb, &temp_dir("redact")));
+
+        let entries = export_archive_entries(&app, "?redact=true").await;
+
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a.md", "docs/b.md", ARCHIVE_MANIFEST]);
+        assert!(entries.iter().all(|(_, content)| !content.contains("secret")));
+        assert_eq!(entries[0].1, "redacted-1\nredacted-2");
+        assert_eq!(entries[1].1, "redacted-2");
+        let manifest: serde_json::Value = serde_json::from_str(&entries[2].1).unwrap();
+        assert_eq!(manifest["redacted"], true);
+
+        let entries = export_archive_entries(&app, "").await;
+        assert_eq!(entries[0].1, "secret one\nshared secret");
+    }
+}
@@ -0,0 +1,1361 @@
+//! Hand-written OpenAPI 3 description of the HTTP API, served at `/openapi.json`.
+//!
+//! When adding or changing a route in `main`, update the matching entry here.
+
+use serde_json::{json, Map, Value};
+
+/// Builds the OpenAPI document for all routes
+pub fn spec() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "LiasionDB",
+            "description": "Graph-based version control database with provenance tracking",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths(),
+        "components": { "schemas": schemas() },
+    })
+}
+
+fn paths() -> Value {
+    let mut paths = Map::new();
+
+    paths.insert(
+        "/health".into(),
+        json!({
+            "get": operation("Health check", vec![], json!({
+                "200": text_response("Server is running"),
+            })),
+        }),
+    );
+
+    paths.insert(
+        "/stats".into(),
+        json!({
+            "get": operation(
+                "Graph size and nodes with too many divergent successors",
+                vec![
+                    query_param(
+                        "max_fan_out",
+                        "integer",
+                        "List nodes with more successors than this (default MAX_FAN_OUT)",
+                    ),
+                    header_param("If-None-Match", "ETag from a previous response"),
+                ],
+                json!({
+                    "200": json_response("Graph statistics", json!({
+                        "type": "object",
+                        "properties": {
+                            "nodes": { "type": "integer" },
+                            "edges": { "type": "integer" },
+                            "references": { "type": "integer" },
+                            "max_fan_out": { "type": ["integer", "null"] },
+                            "high_fan_out": array_of(schema_ref("NodeSummary")),
+                        },
+                    })),
+                    "304": { "description": "Graph unchanged since the given ETag" },
+                }),
+            ),
+        }),
+    );
+
+    let selftest_report = json!({
+        "type": "object",
+        "properties": {
+            "status": { "type": "string", "enum": ["passed", "failed"] },
+            "steps": array_of(json!({
+                "type": "object",
+                "properties": {
+                    "step": { "type": "string", "enum": ["ingest", "read", "contamination"] },
+                    "passed": { "type": "boolean" },
+                    "detail": { "type": "string" },
+                },
+            })),
+        },
+    });
+    paths.insert(
+        "/selftest".into(),
+        json!({
+            "post": operation(
+                "Ingest, read back, and trace a known document in a scratch knowledge base",
+                vec![],
+                json!({
+                    "200": json_response("Every step passed", selftest_report.clone()),
+                    "403": { "description": "Self-test is disabled" },
+                    "500": json_response(
+                        "A step failed; later steps are left out",
+                        selftest_report,
+                    ),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/version".into(),
+        json!({
+            "get": operation("Crate and snapshot schema version", vec![], json!({
+                "200": json_response("Versions", json!({
+                    "type": "object",
+                    "properties": {
+                        "version": { "type": "string" },
+                        "schema_version": { "type": "integer" },
+                    },
+                })),
+            })),
+        }),
+    );
+
+    paths.insert(
+        "/openapi.json".into(),
+        json!({
+            "get": operation("This OpenAPI document", vec![], json!({
+                "200": json_response("OpenAPI document", json!({ "type": "object" })),
+            })),
+        }),
+    );
+
+    paths.insert(
+        "/files".into(),
+        json!({
+            "get": operation(
+                "List all files",
+                vec![
+                    query_param("sizes", "boolean", "List objects with sizes and fork status"),
+                    header_param("If-None-Match", "ETag from a previous listing"),
+                ],
+                json!({
+                    "200": json_response("Filenames, or file sizes with sizes=true", array_of(json!({
+                        "oneOf": [{ "type": "string" }, schema_ref("FileSize")],
+                    }))),
+                    "304": { "description": "Graph unchanged since the given ETag" },
+                }),
+            ),
+        }),
+    );
+
+    let read_file = operation(
+        "Read a file, saving it to the workspace and recording it in the ledger",
+        vec![
+            filepath_param(),
+            workspace_param(),
+            query_param("persist", "boolean", "Save to disk and record in the ledger (default true)"),
+            query_param("annotate", "boolean", "Return JSON lines annotated with edge version and tag, with traversal metadata"),
+            query_param("meta", "boolean", "Include X-Traversal-* metadata headers"),
+            query_param("checkpoint", "string", "Read the file as it was at this named checkpoint"),
+            query_param("with_references", "boolean", "Append a References section"),
+            header_param("If-None-Match", "Checksum from a previous read"),
+        ],
+        json!({
+            "200": text_response("File content, with X-Content-SHA256, ETag, and X-Line-Count headers"),
+            "304": { "description": "Content unchanged since the given checksum" },
+            "404": { "description": "File not found" },
+            "409": { "description": "A saved file and directory compete for the file's path" },
+            "422": { "description": "File could not be saved to disk under its sanitized name" },
+        }),
+    );
+    let write_file = json!({
+        "summary": "Write a file, referencing every node in the workspace ledger",
+        "parameters": [
+            filepath_param(),
+            workspace_param(),
+            header_param("Idempotency-Key", "Replays with the same key return the first result"),
+        ],
+        "requestBody": json_body(schema_ref("WriteFileRequest")),
+        "responses": {
+            "200": json_response("Existing file updated", schema_ref("WriteFileResponse")),
+            "201": json_response("New file created", schema_ref("WriteFileResponse")),
+            "400": { "description": "Content rejected on ingest, or negative version" },
+            "409": { "description": "Explicit version doesn't exceed the current one" },
+            "500": { "description": "Ledger unreadable" },
+        },
+    });
+    let head_file = operation(
+        "A file's read headers and X-File-Version, without content or side effects",
+        vec![
+            filepath_param(),
+            header_param("If-None-Match", "Checksum from a previous read"),
+        ],
+        json!({
+            "200": { "description": "File exists; Content-Length is the size of its content" },
+            "304": { "description": "Content unchanged since the given checksum" },
+            "404": { "description": "File not found" },
+        }),
+    );
+    let delete_file = operation(
+        "Delete a file by writing a tombstone",
+        vec![filepath_param()],
+        json!({
+            "200": json_response("File deleted", schema_ref("StatusResponse")),
+            "404": json_response("File never existed", schema_ref("StatusResponse")),
+            "409": json_response("File already deleted", schema_ref("StatusResponse")),
+        }),
+    );
+    paths.insert(
+        "/files/{filepath}".into(),
+        json!({
+            "get": read_file,
+            "head": head_file,
+            "post": write_file,
+            "delete": delete_file,
+        }),
+    );
+
+    paths.insert(
+        "/files/{filepath}/checkpoints".into(),
+        json!({
+            "get": operation(
+                "A file's named checkpoints, oldest version first",
+                vec![filepath_param()],
+                json!({
+                    "200": json_response("Checkpoints", array_of(schema_ref("Checkpoint"))),
+                    "404": { "description": "File was never written" },
+                }),
+            ),
+            "post": {
+                "summary": "Name the file's current state as a checkpoint",
+                "parameters": [filepath_param()],
+                "requestBody": json_body(json!({
+                    "type": "object",
+                    "required": ["name"],
+                    "properties": { "name": { "type": "string" } },
+                })),
+                "responses": {
+                    "201": json_response("Checkpoint set", schema_ref("Checkpoint")),
+                    "400": { "description": "Empty name" },
+                    "404": { "description": "File not found" },
+                },
+            },
+        }),
+    );
+
+    paths.insert(
+        "/files/{filepath}/rename".into(),
+        json!({
+            "post": operation(
+                "Rename a file, linking the new chain back to the old one",
+                vec![
+                    filepath_param(),
+                    json!({
+                        "name": "to",
+                        "in": "query",
+                        "required": true,
+                        "description": "New path of the file",
+                        "schema": { "type": "string" },
+                    }),
+                ],
+                json!({
+                    "200": json_response("File renamed", schema_ref("StatusResponse")),
+                    "404": json_response("File not found", schema_ref("StatusResponse")),
+                    "409": json_response("New path already exists", schema_ref("StatusResponse")),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/files/{filepath}/history".into(),
+        json!({
+            "get": operation(
+                "Number of edges introduced at each version of a file",
+                vec![filepath_param()],
+                json!({
+                    "200": json_response(
+                        "Edge count per version, sorted by version",
+                        array_of(json!({
+                            "type": "object",
+                            "properties": {
+                                "version": { "type": "integer" },
+                                "edges": { "type": "integer" },
+                            },
+                        })),
+                    ),
+                    "404": { "description": "File not found" },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/files/{filepath}/references".into(),
+        json!({
+            "get": operation(
+                "Nodes that influenced any line of a file, with the lines they influenced",
+                vec![filepath_param()],
+                json!({
+                    "200": json_response(
+                        "References sorted by node index",
+                        array_of(json!({
+                            "type": "object",
+                            "properties": {
+                                "node_idx": { "type": "integer" },
+                                "content": { "type": "string" },
+                                "filename": { "type": "string" },
+                                "lines": array_of(json!({ "type": "integer" })),
+                            },
+                        })),
+                    ),
+                    "404": { "description": "File not found" },
+                    "503": json_response("Query exceeded QUERY_TIMEOUT_MS", schema_ref("StatusResponse")),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/files/{filepath}/nodes".into(),
+        json!({
+            "get": operation(
+                "Every content node of a file across all versions",
+                vec![filepath_param()],
+                json!({
+                    "200": json_response(
+                        "Nodes in insertion order",
+                        array_of(schema_ref("NodeSummary")),
+                    ),
+                    "404": { "description": "File was never written" },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/export/prov".into(),
+        json!({
+            "get": operation(
+                "The reference graph as W3C PROV-JSON",
+                vec![query_param("redact", "boolean", "Replace content with placeholders")],
+                json!({
+                    "200": {
+                        "description": "PROV-JSON document",
+                        "content": { "application/prov+json": { "schema": { "type": "object" } } },
+                    },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/export/archive".into(),
+        json!({
+            "get": operation(
+                "Every file's latest content as a streamed zip, with a manifest",
+                vec![query_param("redact", "boolean", "Replace content with placeholders")],
+                json!({
+                    "200": {
+                        "description": "Zip archive ending with .manifest.json",
+                        "content": {
+                            "application/zip": {
+                                "schema": { "type": "string", "format": "binary" },
+                            },
+                        },
+                    },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/files/{filepath}/graph.dot".into(),
+        json!({
+            "get": operation(
+                "The file's version graph and its references as Graphviz DOT",
+                vec![
+                    filepath_param(),
+                    query_param("redact", "boolean", "Replace content with placeholders"),
+                ],
+                json!({
+                    "200": {
+                        "description": "DOT source",
+                        "content": { "text/vnd.graphviz": { "schema": { "type": "string" } } },
+                    },
+                    "404": { "description": "File was never written" },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/files/popular".into(),
+        json!({
+            "get": operation(
+                "Most read files with their read counts",
+                vec![query_param("n", "integer", "Number of files to return (default 10)")],
+                json!({
+                    "200": json_response(
+                        "Files sorted by read count, most read first",
+                        array_of(json!({
+                            "type": "object",
+                            "properties": {
+                                "path": { "type": "string" },
+                                "reads": { "type": "integer" },
+                            },
+                        })),
+                    ),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/files/changed".into(),
+        json!({
+            "get": operation(
+                "Files changed after a version, for incremental sync",
+                vec![json!({
+                    "name": "since",
+                    "in": "query",
+                    "required": true,
+                    "description": "Only list files whose latest version exceeds this",
+                    "schema": { "type": "integer" },
+                })],
+                json!({
+                    "200": json_response(
+                        "Changed files sorted by path",
+                        array_of(json!({
+                            "type": "object",
+                            "properties": {
+                                "path": { "type": "string" },
+                                "version": { "type": "integer" },
+                                "deleted": { "type": "boolean" },
+                            },
+                        })),
+                    ),
+                    "400": { "description": "Missing or invalid since" },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/files/{filepath}/dead-branches".into(),
+        json!({
+            "get": operation(
+                "Divergent branches that never rejoin the latest path",
+                vec![filepath_param()],
+                json!({
+                    "200": json_response(
+                        "Branches as paths of nodes",
+                        array_of(array_of(schema_ref("NodeSummary"))),
+                    ),
+                    "404": { "description": "File not found" },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/kb".into(),
+        json!({
+            "delete": operation(
+                "Clear the whole knowledge base and all ledgers (admin only)",
+                vec![
+                    query_param("reseed", "boolean", "Re-insert the example document"),
+                    header_param("Authorization", "Bearer token matching ADMIN_TOKEN"),
+                ],
+                json!({
+                    "200": json_response("Counts of what was cleared", schema_ref("StatusResponse")),
+                    "401": { "description": "Missing or wrong admin token" },
+                    "403": { "description": "Admin endpoints are disabled" },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/maintenance/reindex".into(),
+        json!({
+            "post": operation(
+                "Rebuild the reverse index of reference edges (admin only)",
+                vec![header_param("Authorization", "Bearer token matching ADMIN_TOKEN")],
+                json!({
+                    "200": json_response(
+                        "Whether the index had drifted",
+                        json!({
+                            "type": "object",
+                            "properties": {
+                                "status": { "type": "string" },
+                                "drifted": { "type": "boolean" },
+                                "references": { "type": "integer" },
+                            },
+                        }),
+                    ),
+                    "401": { "description": "Missing or wrong admin token" },
+                    "403": { "description": "Admin endpoints are disabled" },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/ledger".into(),
+        json!({
+            "delete": operation("Clear a workspace ledger", vec![workspace_param()], json!({
+                "200": json_response("Ledger cleared", schema_ref("StatusResponse")),
+            })),
+        }),
+    );
+
+    paths.insert(
+        "/ledger/merge".into(),
+        json!({
+            "post": {
+                "summary": "Union several workspace ledgers into one",
+                "requestBody": json_body(schema_ref("MergeLedgerRequest")),
+                "responses": {
+                    "200": json_response("Ledgers merged", schema_ref("StatusResponse")),
+                },
+            },
+        }),
+    );
+
+    paths.insert(
+        "/search".into(),
+        json!({
+            "get": operation(
+                "Case-insensitive content search",
+                vec![
+                    query_param("q", "string", "Substring to search for"),
+                    query_param("rank", "boolean", "Order by relevance and include a score"),
+                    query_param("limit", "integer", "Maximum number of results (default 50)"),
+                ],
+                json!({
+                    "200": json_response("Matching nodes", array_of(schema_ref("NodeSummary"))),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/search/regex".into(),
+        json!({
+            "get": operation(
+                "Regular expression content search",
+                vec![
+                    query_param("pattern", "string", "Regular expression (regex crate syntax)"),
+                    query_param("limit", "integer", "Maximum number of results (default 50)"),
+                ],
+                json!({
+                    "200": json_response("Matching nodes", array_of(schema_ref("NodeSummary"))),
+                    "400": { "description": "Invalid or too large pattern" },
+                    "503": { "description": "Search exceeded QUERY_TIMEOUT_MS" },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/search/vector".into(),
+        json!({
+            "post": {
+                "summary": "Nearest nodes by cosine similarity of stored embeddings",
+                "requestBody": json_body(schema_ref("VectorSearchRequest")),
+                "responses": {
+                    "200": json_response(
+                        "Most similar nodes first, with a score",
+                        array_of(schema_ref("NodeSummary")),
+                    ),
+                    "422": json_response(
+                        "Invalid query or dimension mismatch",
+                        schema_ref("StatusResponse"),
+                    ),
+                },
+            },
+        }),
+    );
+
+    paths.insert(
+        "/references/bulk".into(),
+        json!({
+            "post": {
+                "summary": "Create many reference edges at one version",
+                "requestBody": json_body(array_of(schema_ref("BulkReference"))),
+                "responses": {
+                    "200": json_response("Edges created", schema_ref("BulkReferenceStats")),
+                    "404": { "description": "A referenced node index is out of range" },
+                },
+            },
+        }),
+    );
+
+    paths.insert(
+        "/references/top".into(),
+        json!({
+            "get": operation(
+                "Most referenced nodes",
+                vec![query_param("n", "integer", "Maximum number of nodes (default 10)")],
+                json!({
+                    "200": json_response(
+                        "Nodes with reference counts",
+                        array_of(schema_ref("NodeSummary")),
+                    ),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/edges".into(),
+        json!({
+            "get": operation(
+                "Structural edges whose tag starts with a prefix",
+                vec![
+                    query_param("tag_prefix", "string", "Tag prefix (default: all edges)"),
+                    query_param("from_lo", "integer", "First source node index of a page"),
+                    query_param("from_hi", "integer", "Source node index the page ends before"),
+                    query_param("to_lo", "integer", "First target node index within from_lo"),
+                ],
+                json!({
+                    "200": json_response(
+                        "Edges sorted by key; X-Next-From-Lo and X-Next-To-Lo are set if a page stopped early",
+                        array_of(schema_ref("EdgeRecord")),
+                    ),
+                    "400": json_response(
+                        "from_lo or from_hi missing, from_lo > from_hi, or to_lo without them",
+                        schema_ref("StatusResponse"),
+                    ),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/tags".into(),
+        json!({
+            "get": operation(
+                "Number of structural and reference edges per tag",
+                vec![],
+                json!({
+                    "200": json_response("Tags, most used first", array_of(json!({
+                        "type": "object",
+                        "properties": {
+                            "tag": { "type": "string" },
+                            "count": { "type": "integer" },
+                        },
+                    }))),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/nodes/{idx}".into(),
+        json!({
+            "get": operation(
+                "A single node",
+                vec![
+                    idx_param(),
+                    query_param("positions", "boolean", "Include the node's positions in files"),
+                ],
+                json!({
+                    "200": json_response("The node", schema_ref("NodeSummary")),
+                    "404": { "description": "Node index out of range" },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/reconstruct/{idx}".into(),
+        json!({
+            "get": operation(
+                "Reconstruct the content downstream of any node",
+                vec![idx_param(), header_param("If-None-Match", "Checksum from a previous read")],
+                json!({
+                    "200": text_response("Content, with X-Content-SHA256 and X-Traversal-* headers"),
+                    "304": { "description": "Content unchanged since the given checksum" },
+                    "404": { "description": "Node index out of range" },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/nodes/batch".into(),
+        json!({
+            "post": {
+                "summary": "Several nodes by index, in request order",
+                "requestBody": json_body(schema_ref("NodeBatchRequest")),
+                "responses": {
+                    "200": json_response(
+                        "One entry per requested index, null if out of range",
+                        array_of(json!({
+                            "allOf": [schema_ref("NodeSummary")],
+                            "nullable": true,
+                        })),
+                    ),
+                },
+            },
+        }),
+    );
+
+    paths.insert(
+        "/nodes/{idx}/provenance".into(),
+        json!({
+            "get": operation(
+                "Structural and reference provenance tree of a node",
+                vec![idx_param()],
+                json!({
+                    "200": json_response("Provenance tree", schema_ref("ProvenanceTree")),
+                    "404": { "description": "Node index out of range" },
+                    "503": json_response("Query exceeded QUERY_TIMEOUT_MS", schema_ref("StatusResponse")),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/diff".into(),
+        json!({
+            "get": operation(
+                "Line-level diff between the latest content of two files",
+                vec![
+                    query_param("a", "string", "Filepath of the old side"),
+                    query_param("b", "string", "Filepath of the new side"),
+                ],
+                json!({
+                    "200": json_response("Counts and lines in order", schema_ref("FileDiff")),
+                    "404": json_response("A file doesn't exist", schema_ref("StatusResponse")),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/consistency/{filepath}".into(),
+        json!({
+            "get": operation(
+                "Compare a file's latest content with its materialized copy on disk",
+                vec![filepath_param(), workspace_param()],
+                json!({
+                    "200": json_response(
+                        "Match status and the diff from the disk copy to the graph",
+                        schema_ref("ConsistencyReport"),
+                    ),
+                    "404": json_response("File not found", schema_ref("StatusResponse")),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/impact".into(),
+        json!({
+            "get": operation(
+                "Impact report for a source: affected nodes, files, and depth",
+                vec![query_param("content", "string", "Exact content of the source node")],
+                json!({
+                    "200": json_response("The impact report", schema_ref("ImpactReport")),
+                    "404": { "description": "No node has the given content" },
+                    "503": json_response("Query exceeded QUERY_TIMEOUT_MS", schema_ref("StatusResponse")),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/nodes/{idx}/next-edge".into(),
+        json!({
+            "get": operation(
+                "The structural edge latest-path traversal follows from a node",
+                vec![idx_param()],
+                json!({
+                    "200": json_response(
+                        "The node, its out-degree, and the edge followed (null at a chain's end)",
+                        json!({
+                            "type": "object",
+                            "properties": {
+                                "node": schema_ref("NodeSummary"),
+                                "out_degree": { "type": "integer" },
+                                "next_edge": {
+                                    "type": "object",
+                                    "nullable": true,
+                                    "properties": {
+                                        "from": { "type": "integer" },
+                                        "to": { "type": "integer" },
+                                        "version": { "type": "integer" },
+                                        "tag": { "type": "string" },
+                                        "weight": { "type": "number" },
+                                    },
+                                },
+                            },
+                        }),
+                    ),
+                    "404": { "description": "Node index out of range" },
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/nodes/{idx}/contaminated".into(),
+        json!({
+            "get": operation(
+                "Nodes influenced by a node through reference edges",
+                vec![
+                    idx_param(),
+                    query_param("since", "integer", "Only follow reference edges with at least this version"),
+                ],
+                json!({
+                    "200": json_response(
+                        "The node itself as the source, and every node it influenced",
+                        json!({
+                            "type": "object",
+                            "properties": {
+                                "source": schema_ref("NodeSummary"),
+                                "contaminated": array_of(schema_ref("NodeSummary")),
+                            },
+                        }),
+                    ),
+                    "404": { "description": "Node index out of range" },
+                    "503": json_response("Query exceeded QUERY_TIMEOUT_MS", schema_ref("StatusResponse")),
+                }),
+            ),
+        }),
+    );
+
+    paths.insert(
+        "/nodes/{idx}/embedding".into(),
+        json!({
+            "post": {
+                "summary": "Store an externally computed embedding for a node",
+                "parameters": [idx_param()],
+                "requestBody": json_body(schema_ref("EmbeddingRequest")),
+                "responses": {
+                    "200": json_response("Embedding stored", schema_ref("StatusResponse")),
+                    "404": json_response("Node index out of range", schema_ref("StatusResponse")),
+                    "422": json_response(
+                        "Empty, non-finite, or wrong dimension",
+                        schema_ref("StatusResponse"),
+                    ),
+                },
+            },
+        }),
+    );
+
+    paths.insert(
+        "/import/archive".into(),
+        json!({
+            "post": {
+                "summary": "Import every file in a zip or tar.gz archive",
+                "requestBody": {
+                    "content": {
+                        "multipart/form-data": {
+                            "schema": {
+                                "type": "object",
+                                "properties": {
+                                    "archive": { "type": "string", "format": "binary" },
+                                },
+                            },
+                        },
+                    },
+                },
+                "responses": {
+                    "200": json_response("Per-file import results", json!({ "type": "object" })),
+                    "400": { "description": "Not a readable zip or tar.gz archive" },
+                    "413": { "description": "Archive too large" },
+                },
+            },
+        }),
+    );
+
+    paths.insert(
+        "/import/snapshot".into(),
+        json!({
+            "post": {
+                "summary": "Replace the knowledge base with a validated snapshot (admin only)",
+                "parameters": [header_param("Authorization", "Bearer token matching ADMIN_TOKEN")],
+                "requestBody": json_body(json!({ "type": "object" })),
+                "responses": {
+                    "200": json_response("Counts of what was imported", schema_ref("StatusResponse")),
+                    "400": json_response(
+                        "Malformed snapshot, with the location of its first problem",
+                        json!({
+                            "type": "object",
+                            "properties": {
+                                "error": { "type": "string" },
+                                "location": { "type": "string", "nullable": true },
+                            },
+                        }),
+                    ),
+                    "401": { "description": "Missing or wrong admin token" },
+                    "403": { "description": "Admin endpoints are disabled" },
+                },
+            },
+        }),
+    );
+
+    paths.insert(
+        "/fetch".into(),
+        json!({
+            "post": {
+                "summary": "Download a URL and ingest it, recording the URL as a reference node",
+                "requestBody": json_body(schema_ref("FetchRequest")),
+                "responses": {
+                    "200": json_response("Ingest stats and source node", json!({ "type": "object" })),
+                    "400": { "description": "Invalid URL or content rejected on ingest" },
+                    "403": { "description": "Scheme or host not allowed" },
+                    "413": { "description": "Body larger than FETCH_MAX_BYTES" },
+                    "502": { "description": "Download failed or timed out" },
+                },
+            },
+        }),
+    );
+
+    paths.insert(
+        "/transaction".into(),
+        json!({
+            "post": {
+                "summary": "Apply writes, deletes, and references atomically at one version",
+                "requestBody": json_body(schema_ref("TransactionRequest")),
+                "responses": {
+                    "200": json_response("Transaction committed", schema_ref("TransactionResponse")),
+                    "400": { "description": "Content rejected on ingest or a file touched twice" },
+                    "404": { "description": "Deleted file or referenced node not found" },
+                    "409": { "description": "Deleted file is already deleted" },
+                },
+            },
+        }),
+    );
+
+    Value::Object(paths)
+}
+
+fn schemas() -> Value {
+    let mut schemas = Map::new();
+
+    schemas.insert(
+        "WriteFileRequest".into(),
+        json!({
+            "type": "object",
+            "required": ["content"],
+            "properties": {
+                "content": { "type": "string" },
+                "version": {
+                    "type": "integer",
+                    "description": "Explicit version; must exceed the file's current version",
+                },
+                "force": {
+                    "type": "boolean",
+                    "description": "Accept a version that doesn't exceed the current one",
+                },
+                "weight": {
+                    "type": "number",
+                    "description": "Confidence weight of the new structural edges (default 1.0)",
+                },
+            },
+        }),
+    );
+
+    schemas.insert(
+        "WriteFileResponse".into(),
+        json!({
+            "type": "object",
+            "properties": {
+                "status": { "type": "string", "enum": ["created", "updated"] },
+                "file_idx": { "type": "integer" },
+                "parent_idx": { "type": "integer" },
+                "directory": { "type": "string" },
+            },
+        }),
+    );
+
+    schemas.insert(
+        "MergeLedgerRequest".into(),
+        json!({
+            "type": "object",
+            "required": ["into", "from"],
+            "properties": {
+                "into": { "type": "string" },
+                "from": array_of(json!({ "type": "string" })),
+            },
+        }),
+    );
+
+    schemas.insert(
+        "FetchRequest".into(),
+        json!({
+            "type": "object",
+            "required": ["url", "path"],
+            "properties": {
+                "url": { "type": "string" },
+                "path": { "type": "string" },
+            },
+        }),
+    );
+
+    schemas.insert(
+        "NodeBatchRequest".into(),
+        json!({
+            "type": "object",
+            "required": ["indices"],
+            "properties": { "indices": array_of(json!({ "type": "integer" })) },
+        }),
+    );
+
+    schemas.insert(
+        "EmbeddingRequest".into(),
+        json!({
+            "type": "object",
+            "required": ["embedding"],
+            "properties": { "embedding": array_of(json!({ "type": "number" })) },
+        }),
+    );
+
+    schemas.insert(
+        "VectorSearchRequest".into(),
+        json!({
+            "type": "object",
+            "required": ["embedding"],
+            "properties": {
+                "embedding": array_of(json!({ "type": "number" })),
+                "k": { "type": "integer", "default": 10 },
+            },
+        }),
+    );
+
+    schemas.insert(
+        "StatusResponse".into(),
+        json!({
+            "type": "object",
+            "properties": { "status": { "type": "string" } },
+            "additionalProperties": true,
+        }),
+    );
+
+    schemas.insert(
+        "IngestStats".into(),
+        json!({
+            "type": "object",
+            "properties": {
+                "file_idx": { "type": "integer" },
+                "parent_idx": { "type": "integer" },
+                "version": { "type": "integer" },
+                "nodes_added": { "type": "integer" },
+                "edges_added": { "type": "integer" },
+                "references_added": { "type": "integer" },
+                "high_fan_out_nodes": {
+                    "type": "integer",
+                    "description": "Nodes on the written chain with more successors than MAX_FAN_OUT",
+                },
+                "edges_evicted": {
+                    "type": "integer",
+                    "description": "Structural edges evicted to stay within MAX_FORK_WIDTH",
+                },
+            },
+        }),
+    );
+
+    schemas.insert(
+        "FileDiff".into(),
+        json!({
+            "type": "object",
+            "properties": {
+                "a": { "type": "string" },
+                "b": { "type": "string" },
+                "added": { "type": "integer" },
+                "removed": { "type": "integer" },
+                "common": { "type": "integer" },
+                "lines": array_of(json!({
+                    "type": "object",
+                    "properties": {
+                        "op": { "type": "string", "enum": ["common", "removed", "added"] },
+                        "line": { "type": "string" },
+                    },
+                })),
+            },
+        }),
+    );
+
+    schemas.insert(
+        "ConsistencyReport".into(),
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string" },
+                "disk_path": { "type": "string" },
+                "status": { "type": "string", "enum": ["match", "mismatch", "missing"] },
+                "added": { "type": "integer" },
+                "removed": { "type": "integer" },
+                "lines": array_of(json!({
+                    "type": "object",
+                    "properties": {
+                        "op": { "type": "string", "enum": ["common", "removed", "added"] },
+                        "line": { "type": "string" },
+                    },
+                })),
+            },
+        }),
+    );
+
+    schemas.insert(
+        "Checkpoint".into(),
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string" },
+                "name": { "type": "string" },
+                "version": { "type": "integer" },
+            },
+        }),
+    );
+
+    schemas.insert(
+        "ImpactReport".into(),
+        json!({
+            "type": "object",
+            "properties": {
+                "sources": array_of(json!({ "type": "integer" })),
+                "affected_nodes": { "type": "integer" },
+                "files": array_of(json!({
+                    "type": "object",
+                    "properties": {
+                        "filename": { "type": "string" },
+                        "affected_nodes": { "type": "integer" },
+                    },
+                })),
+                "max_depth": { "type": "integer" },
+            },
+        }),
+    );
+
+    schemas.insert(
+        "FileSize".into(),
+        json!({
+            "type": "object",
+            "properties": {
+                "id": {
+                    "type": "string",
+                    "description": "Stable document ID, a 64-bit hash of the path in hex",
+                },
+                "path": { "type": "string" },
+                "lines": { "type": "integer" },
+                "bytes": { "type": "integer" },
+                "forked": {
+                    "type": "boolean",
+                    "description": "Whether the file's versions have diverged",
+                },
+            },
+        }),
+    );
+
+    schemas.insert(
+        "TransactionOp".into(),
+        json!({
+            "type": "object",
+            "required": ["op"],
+            "properties": {
+                "op": { "type": "string", "enum": ["write", "delete", "reference"] },
+                "path": { "type": "string", "description": "For write and delete" },
+                "content": { "type": "string", "description": "For write" },
+                "from": { "type": "integer", "description": "For reference" },
+                "to": { "type": "integer", "description": "For reference" },
+            },
+        }),
+    );
+
+    schemas.insert(
+        "TransactionRequest".into(),
+        json!({
+            "type": "object",
+            "required": ["operations"],
+            "properties": {
+                "workspace": { "type": "string" },
+                "operations": array_of(schema_ref("TransactionOp")),
+            },
+        }),
+    );
+
+    schemas.insert(
+        "TransactionResponse".into(),
+        json!({
+            "type": "object",
+            "properties": {
+                "status": { "type": "string", "enum": ["committed"] },
+                "version": { "type": "integer" },
+                "results": array_of(json!({
+                    "type": "object",
+                    "properties": {
+                        "op": { "type": "string", "enum": ["write", "delete", "reference"] },
+                        "path": { "type": "string" },
+                        "stats": schema_ref("IngestStats"),
+                        "from": { "type": "integer" },
+                        "to": { "type": "integer" },
+                        "added": { "type": "boolean" },
+                    },
+                })),
+            },
+        }),
+    );
+
+    schemas.insert(
+        "EdgeRecord".into(),
+        json!({
+            "type": "object",
+            "properties": {
+                "from": { "type": "integer" },
+                "to": { "type": "integer" },
+                "version": { "type": "integer" },
+                "tag": { "type": "string" },
+            },
+        }),
+    );
+
+    schemas.insert(
+        "BulkReference".into(),
+        json!({
+            "type": "object",
+            "required": ["from", "to"],
+            "properties": {
+                "from": {
+                    "type": "string",
+                    "description": "Content of the influencing node; created if not in the graph",
+                },
+                "to": { "type": "integer", "description": "Index of the influenced node" },
+            },
+        }),
+    );
+
+    schemas.insert(
+        "BulkReferenceStats".into(),
+        json!({
+            "type": "object",
+            "properties": {
+                "version": { "type": "integer" },
+                "created": { "type": "integer" },
+                "skipped": { "type": "integer" },
+                "nodes_added": { "type": "integer" },
+            },
+        }),
+    );
+
+    schemas.insert(
+        "NodeSummary".into(),
+        json!({
+            "type": "object",
+            "properties": {
+                "node_idx": { "type": "integer" },
+                "kind": {
+                    "type": "string",
+                    "enum": ["directory", "file", "tombstone", "content", "reference"],
+                },
+                "content": {
+                    "type": "string",
+                    "description": "Node content without its DIR/FILE/TOMBSTONE prefix",
+                },
+                "filename": { "type": "string" },
+                "positions": {
+                    "type": "array",
+                    "description": "Only with ?positions=true on GET /nodes/{idx}",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "path": { "type": "string" },
+                            "position": {
+                                "type": "integer",
+                                "description": "1-based line on the file's latest path",
+                            },
+                        },
+                    },
+                },
+            },
+            "additionalProperties": true,
+        }),
+    );
+
+    schemas.insert(
+        "ProvenanceTree".into(),
+        json!({
+            "type": "object",
+            "properties": {
+                "node_idx": { "type": "integer" },
+                "content": { "type": "string" },
+                "filename": { "type": "string" },
+                "structural": array_of(schema_ref("NodeSummary")),
+                "references": array_of(schema_ref("ProvenanceTree")),
+            },
+        }),
+    );
+
+    Value::Object(schemas)
+}
+
+fn operation(summary: &str, parameters: Vec<Value>, responses: Value) -> Value {
+    json!({
+        "summary": summary,
+        "parameters": parameters,
+        "responses": responses,
+    })
+}
+
+fn path_param(name: &str, schema_type: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": { "type": schema_type },
+    })
+}
+
+fn query_param(name: &str, schema_type: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "description": description,
+        "schema": { "type": schema_type },
+    })
+}
+
+fn header_param(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "header",
+        "required": false,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+fn filepath_param() -> Value {
+    path_param("filepath", "string", "Path of the file, may contain slashes")
+}
+
+fn idx_param() -> Value {
+    path_param("idx", "integer", "Node index")
+}
+
+fn workspace_param() -> Value {
+    query_param("workspace", "string", "Workspace name (default: no workspace)")
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn array_of(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn json_body(schema: Value) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+fn text_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } },
+    })
+}
@@ -0,0 +1,405 @@
+//! Storage backends behind a common `KnowledgeStore` trait.
+//!
+//! `KnowledgeBase` keeps the whole graph in memory. With the `persistent` feature,
+//! `SledStore` keeps nodes, structural edges, and reference edges in an embedded
+//! sled database instead, so graphs survive restarts and can grow beyond RAM.
+
+use crate::{Edge, EdgeTable, KnowledgeBase, Node};
+use std::collections::BTreeMap;
+
+/// Storage-level operations shared by every backend.
+///
+/// Node indices are dense and assigned in insertion order. Edges are keyed by
+/// `(from, to)` within each table, and outgoing edges are returned sorted by key.
+pub trait KnowledgeStore {
+    type Error: std::error::Error;
+
+    /// Inserts a node if it isn't stored yet, returning its index either way.
+    fn insert_node(&mut self, node: &Node) -> Result<usize, Self::Error>;
+
+    /// Returns the index of a stored node.
+    fn node_index(&self, node: &Node) -> Result<Option<usize>, Self::Error>;
+
+    /// Returns the node at an index.
+    fn node(&self, idx: usize) -> Result<Option<Node>, Self::Error>;
+
+    /// Returns the number of stored nodes.
+    fn node_count(&self) -> Result<usize, Self::Error>;
+
+    /// Stores an edge, returning the edge it replaced.
+    fn put_edge(
+        &mut self,
+        table: EdgeTable,
+        edge_key: (usize, usize),
+        edge: Edge,
+    ) -> Result<Option<Edge>, Self::Error>;
+
+    /// Returns the edge stored under a key.
+    fn edge(&self, table: EdgeTable, edge_key: (usize, usize)) -> Result<Option<Edge>, Self::Error>;
+
+    /// Returns all edges leaving a node, sorted by key.
+    fn outgoing_edges(
+        &self,
+        table: EdgeTable,
+        from_idx: usize,
+    ) -> Result<Vec<((usize, usize), Edge)>, Self::Error>;
+}
+
+impl KnowledgeBase {
+    pub(crate) fn table(&self, table: EdgeTable) -> &BTreeMap<(usize, usize), Edge> {
+        match table {
+            EdgeTable::Structural => &self.edge_table,
+            EdgeTable::Reference => &self.ref_table,
+        }
+    }
+
+    pub(crate) fn table_mut(&mut self, table: EdgeTable) -> &mut BTreeMap<(usize, usize), Edge> {
+        match table {
+            EdgeTable::Structural => &mut self.edge_table,
+            EdgeTable::Reference => &mut self.ref_table,
+        }
+    }
+}
+
+impl KnowledgeStore for KnowledgeBase {
+    type Error = std::convert::Infallible;
+
+    fn insert_node(&mut self, node: &Node) -> Result<usize, Self::Error> {
+        Ok(self.push_node(node.clone()).0)
+    }
+
+    fn node_index(&self, node: &Node) -> Result<Option<usize>, Self::Error> {
+        Ok(KnowledgeBase::node_index(self, node))
+    }
+
+    fn node(&self, idx: usize) -> Result<Option<Node>, Self::Error> {
+        Ok(self.node_table.get_index(idx).cloned())
+    }
+
+    fn node_count(&self) -> Result<usize, Self::Error> {
+        Ok(self.node_table.len())
+    }
+
+    fn put_edge(
+        &mut self,
+        table: EdgeTable,
+        edge_key: (usize, usize),
+        edge: Edge,
+    ) -> Result<Option<Edge>, Self::Error> {
+        Ok(self.set_edge(table, edge_key, edge))
+    }
+
+    fn edge(
+        &self,
+        table: EdgeTable,
+        edge_key: (usize, usize),
+    ) -> Result<Option<Edge>, Self::Error> {
+        Ok(self.table(table).get(&edge_key).cloned())
+    }
+
+    fn outgoing_edges(
+        &self,
+        table: EdgeTable,
+        from_idx: usize,
+    ) -> Result<Vec<((usize, usize), Edge)>, Self::Error> {
+        Ok(self
+            .table(table)
+            .range((from_idx, usize::MIN)..(from_idx + 1, usize::MIN))
+            .map(|(edge_key, edge)| (*edge_key, edge.clone()))
+            .collect())
+    }
+}
+
+#[cfg(feature = "persistent")]
+pub use sled_store::{SledStore, SledStoreError};
+
+#[cfg(feature = "persistent")]
+mod sled_store {
+    use super::KnowledgeStore;
+    use crate::{Edge, EdgeTable, Node};
+    use sled::transaction::{ConflictableTransactionError, TransactionError};
+    use sled::Transactional;
+
+    /// Key in the `node_ids` tree holding the next free node index. Node keys are
+    /// JSON objects, so they never collide with it.
+    const NEXT_IDX_KEY: &[u8] = b"#next";
+
+    /// Errors from the sled-backed store.
+    #[derive(Debug)]
+    pub enum SledStoreError {
+        /// The database failed to read or write
+        Sled(sled::Error),
+        /// A stored node or edge could not be encoded or decoded
+        Json(serde_json::Error),
+    }
+
+    impl std::fmt::Display for SledStoreError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                SledStoreError::Sled(err) => write!(f, "sled store failed: {}", err),
+                SledStoreError::Json(err) => write!(f, "sled store record is invalid: {}", err),
+            }
+        }
+    }
+
+    impl std::error::Error for SledStoreError {}
+
+    impl From<sled::Error> for SledStoreError {
+        fn from(err: sled::Error) -> Self {
+            SledStoreError::Sled(err)
+        }
+    }
+
+    impl From<serde_json::Error> for SledStoreError {
+        fn from(err: serde_json::Error) -> Self {
+            SledStoreError::Json(err)
+        }
+    }
+
+    /// A `KnowledgeStore` persisted in an embedded sled database.
+    ///
+    /// Layout, one sled tree each:
+    /// - `nodes`: big-endian node index -> node JSON
+    /// - `node_ids`: node JSON -> big-endian node index, plus the next free index
+    /// - `edges` / `references`: big-endian `from` then `to` -> edge JSON
+    ///
+    /// Big-endian keys sort like the in-memory `(from, to)` keys, so a node's
+    /// outgoing edges are a key-prefix scan.
+    #[derive(Debug, Clone)]
+    pub struct SledStore {
+        db: sled::Db,
+        nodes: sled::Tree,
+        node_ids: sled::Tree,
+        edges: sled::Tree,
+        references: sled::Tree,
+    }
+
+    impl SledStore {
+        /// Opens (or creates) a store in the given directory.
+        pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self, SledStoreError> {
+            let db = sled::open(path)?;
+            Ok(Self {
+                nodes: db.open_tree("nodes")?,
+                node_ids: db.open_tree("node_ids")?,
+                edges: db.open_tree("edges")?,
+                references: db.open_tree("references")?,
+                db,
+            })
+        }
+
+        /// Flushes all pending writes to disk.
+        pub fn flush(&self) -> Result<(), SledStoreError> {
+            self.db.flush()?;
+            Ok(())
+        }
+
+        fn tree(&self, table: EdgeTable) -> &sled::Tree {
+            match table {
+                EdgeTable::Structural => &self.edges,
+                EdgeTable::Reference => &self.references,
+            }
+        }
+    }
+
+    fn idx_key(idx: usize) -> [u8; 8] {
+        (idx as u64).to_be_bytes()
+    }
+
+    fn decode_idx(bytes: &[u8]) -> usize {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(&bytes[..8]);
+        u64::from_be_bytes(buf) as usize
+    }
+
+    fn edge_key_bytes((from_idx, to_idx): (usize, usize)) -> [u8; 16] {
+        let mut key = [0; 16];
+        key[..8].copy_from_slice(&idx_key(from_idx));
+        key[8..].copy_from_slice(&idx_key(to_idx));
+        key
+    }
+
+    impl KnowledgeStore for SledStore {
+        type Error = SledStoreError;
+
+        fn insert_node(&mut self, node: &Node) -> Result<usize, Self::Error> {
+            let node_json = serde_json::to_vec(node)?;
+
+            // Index allocation and both mappings commit together or not at all
+            let result = (&self.nodes, &self.node_ids).transaction(|(nodes, node_ids)| {
+                if let Some(existing) = node_ids.get(node_json.as_slice())? {
+                    return Ok(decode_idx(&existing));
+                }
+                let idx = node_ids
+                    .get(NEXT_IDX_KEY)?
+                    .map_or(0, |next| decode_idx(&next));
+                node_ids.insert(NEXT_IDX_KEY, &idx_key(idx + 1)[..])?;
+                node_ids.insert(node_json.as_slice(), &idx_key(idx)[..])?;
+                nodes.insert(&idx_key(idx)[..], node_json.as_slice())?;
+                Ok::<_, ConflictableTransactionError<()>>(idx)
+            });
+
+            result.map_err(|err| match err {
+                TransactionError::Storage(err) => SledStoreError::Sled(err),
+                TransactionError::Abort(()) => unreachable!("node inserts never abort"),
+            })
+        }
+
+        fn node_index(&self, node: &Node) -> Result<Option<usize>, Self::Error> {
+            let node_json = serde_json::to_vec(node)?;
+            Ok(self.node_ids.get(node_json)?.map(|idx| decode_idx(&idx)))
+        }
+
+        fn node(&self, idx: usize) -> Result<Option<Node>, Self::Error> {
+            match self.nodes.get(idx_key(idx))? {
+                Some(node_json) => Ok(Some(serde_json::from_slice(&node_json)?)),
+                None => Ok(None),
+            }
+        }
+
+        fn node_count(&self) -> Result<usize, Self::Error> {
+            Ok(self
+                .node_ids
+                .get(NEXT_IDX_KEY)?
+                .map_or(0, |next| decode_idx(&next)))
+        }
+
+        fn put_edge(
+            &mut self,
+            table: EdgeTable,
+            edge_key: (usize, usize),
+            edge: Edge,
+        ) -> Result<Option<Edge>, Self::Error> {
+            let edge_json = serde_json::to_vec(&edge)?;
+            match self.tree(table).insert(edge_key_bytes(edge_key), edge_json)? {
+                Some(previous) => Ok(Some(serde_json::from_slice(&previous)?)),
+                None => Ok(None),
+            }
+        }
+
+        fn edge(
+            &self,
+            table: EdgeTable,
+            edge_key: (usize, usize),
+        ) -> Result<Option<Edge>, Self::Error> {
+            match self.tree(table).get(edge_key_bytes(edge_key))? {
+                Some(edge_json) => Ok(Some(serde_json::from_slice(&edge_json)?)),
+                None => Ok(None),
+            }
+        }
+
+        fn outgoing_edges(
+            &self,
+            table: EdgeTable,
+            from_idx: usize,
+        ) -> Result<Vec<((usize, usize), Edge)>, Self::Error> {
+            self.tree(table)
+                .scan_prefix(idx_key(from_idx))
+                .map(|entry| {
+                    let (key, edge_json) = entry?;
+                    let edge = serde_json::from_slice(&edge_json)?;
+                    Ok(((from_idx, decode_idx(&key[8..])), edge))
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(all(test, feature = "persistent"))]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("liasiondb-sled-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn node(content: &str) -> Node {
+        Node::new(content.to_string(), "a.md".to_string())
+    }
+
+    fn edge(version: i32) -> Edge {
+        Edge::new(version, format!("version-{}", version))
+    }
+
+    #[test]
+    fn inserting_a_stored_node_returns_its_index() {
+        let dir = temp_dir("dedup");
+        let mut store = SledStore::open(&dir).unwrap();
+        assert_eq!(store.node_count().unwrap(), 0);
+
+        assert_eq!(store.insert_node(&node("one")).unwrap(), 0);
+        assert_eq!(store.insert_node(&node("two")).unwrap(), 1);
+        assert_eq!(store.insert_node(&node("one")).unwrap(), 0);
+
+        assert_eq!(store.node_count().unwrap(), 2);
+        assert_eq!(store.node_index(&node("two")).unwrap(), Some(1));
+        assert_eq!(store.node_index(&node("three")).unwrap(), None);
+        assert_eq!(store.node(1).unwrap(), Some(node("two")));
+        assert_eq!(store.node(2).unwrap(), None);
+        drop(store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn put_edge_returns_the_edge_it_replaced() {
+        let dir = temp_dir("put-edge");
+        let mut store = SledStore::open(&dir).unwrap();
+
+        let previous = store.put_edge(EdgeTable::Structural, (0, 1), edge(1)).unwrap();
+        assert_eq!(previous, None);
+        let previous = store.put_edge(EdgeTable::Structural, (0, 1), edge(2)).unwrap();
+        assert_eq!(previous, Some(edge(1)));
+
+        assert_eq!(store.edge(EdgeTable::Structural, (0, 1)).unwrap(), Some(edge(2)));
+        // The tables are kept apart
+        assert_eq!(store.edge(EdgeTable::Reference, (0, 1)).unwrap(), None);
+        drop(store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn outgoing_edges_are_sorted_and_stop_at_the_next_node() {
+        let dir = temp_dir("outgoing");
+        let mut store = SledStore::open(&dir).unwrap();
+        // 256 sorts before 255 and 2 if keys aren't big-endian
+        for edge_key in [(1, 256), (2, 0), (1, 2), (0, 9), (1, 255)] {
+            store.put_edge(EdgeTable::Structural, edge_key, edge(0)).unwrap();
+        }
+
+        let keys = |from_idx| -> Vec<(usize, usize)> {
+            let edges = store.outgoing_edges(EdgeTable::Structural, from_idx).unwrap();
+            edges.into_iter().map(|(edge_key, _)| edge_key).collect()
+        };
+        assert_eq!(keys(1), [(1, 2), (1, 255), (1, 256)]);
+        assert_eq!(keys(2), [(2, 0)]);
+        assert!(keys(3).is_empty());
+        drop(store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn data_survives_reopening() {
+        let dir = temp_dir("reopen");
+        let mut store = SledStore::open(&dir).unwrap();
+        store.insert_node(&node("one")).unwrap();
+        store.insert_node(&node("two")).unwrap();
+        store.put_edge(EdgeTable::Structural, (0, 1), edge(3)).unwrap();
+        store.put_edge(EdgeTable::Reference, (1, 0), edge(4)).unwrap();
+        store.flush().unwrap();
+        drop(store);
+
+        let mut store = SledStore::open(&dir).unwrap();
+
+        assert_eq!(store.node_count().unwrap(), 2);
+        assert_eq!(store.node(0).unwrap(), Some(node("one")));
+        assert_eq!(store.edge(EdgeTable::Structural, (0, 1)).unwrap(), Some(edge(3)));
+        assert_eq!(store.edge(EdgeTable::Reference, (1, 0)).unwrap(), Some(edge(4)));
+        // The next free index was persisted too
+        assert_eq!(store.insert_node(&node("three")).unwrap(), 2);
+        drop(store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
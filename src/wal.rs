@@ -0,0 +1,348 @@
+//! Write-ahead log persistence: an append-only log of journaled changes on top of
+//! periodic full snapshots, so each write costs one small append instead of a
+//! full snapshot.
+//!
+//! A log directory holds `wal.jsonl` and `snapshot-{seq}.json`. Each line of the log
+//! is one batch `{"seq": n, "records": [...]}` holding every `WalRecord` journaled
+//! since the previous batch. A batch is written with a single append, so a crash
+//! mid-append leaves at most one torn final line, which recovery discards whole.
+//!
+//! A snapshot's name carries the sequence number of the last batch it contains.
+//! Recovery loads the newest snapshot and replays only later batches, so a crash
+//! between writing a snapshot and truncating the log replays nothing twice.
+
+use crate::{KnowledgeBase, SnapshotError, WalRecord};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const WAL_FILE: &str = "wal.jsonl";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.json.tmp";
+
+/// One line of the log
+#[derive(Serialize, Deserialize)]
+struct WalBatch {
+    seq: u64,
+    records: Vec<WalRecord>,
+}
+
+/// What `WriteAheadLog::open` found on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalRecovery {
+    /// Sequence number of the snapshot that was loaded, if any
+    pub snapshot_seq: Option<u64>,
+    /// Number of batches replayed on top of the snapshot
+    pub batches_replayed: usize,
+    /// Whether a torn final batch from a crash was discarded
+    pub torn_tail: bool,
+}
+
+impl WalRecovery {
+    /// Returns true if there was nothing to recover, i.e. this is a fresh directory.
+    pub fn is_empty(&self) -> bool {
+        self.snapshot_seq.is_none() && self.batches_replayed == 0
+    }
+}
+
+/// An open write-ahead log for one knowledge base.
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    dir: PathBuf,
+    file: File,
+    /// Length of the log up to the end of the last complete batch
+    len: u64,
+    /// Sequence number of the last batch appended or replayed
+    seq: u64,
+    /// Batches in the log that no snapshot contains yet
+    batches_since_snapshot: usize,
+    /// Compact once this many batches have accumulated
+    snapshot_every: usize,
+}
+
+impl WriteAheadLog {
+    /// Opens the log in `dir`, creating the directory if needed, and recovers the
+    /// knowledge base from it.
+    ///
+    /// The newest snapshot is loaded, or `empty` is used if there is none yet, and
+    /// the batches logged after it are replayed. A torn final batch is discarded and
+    /// truncated away. Journaling is started on the returned knowledge base so its
+    /// changes can be passed to `append`.
+    ///
+    /// # Arguments
+    /// * `dir` - Directory holding the log and snapshots
+    /// * `snapshot_every` - Number of batches after which `append` compacts the log
+    /// * `empty` - Knowledge base to replay onto when there is no snapshot
+    pub fn open(
+        dir: impl AsRef<Path>,
+        snapshot_every: usize,
+        empty: KnowledgeBase,
+    ) -> Result<(Self, KnowledgeBase, WalRecovery), SnapshotError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(SnapshotError::Io)?;
+
+        let snapshot_seq = Self::snapshots(&dir)?.into_iter().max();
+        let mut kb = match snapshot_seq {
+            Some(seq) => KnowledgeBase::load_from_path(Self::snapshot_path(&dir, seq))?,
+            None => empty,
+        };
+
+        let wal_path = dir.join(WAL_FILE);
+        let bytes = match fs::read(&wal_path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(SnapshotError::Io(err)),
+        };
+
+        let mut seq = snapshot_seq.unwrap_or(0);
+        let mut batches_replayed = 0;
+        let mut len = 0;
+        for line in bytes.split_inclusive(|byte| *byte == b'\n') {
+            // A line without its newline was cut off by a crash mid-append
+            if !line.ends_with(b"\n") {
+                break;
+            }
+            let batch: WalBatch = serde_json::from_slice(line).map_err(SnapshotError::Json)?;
+            len += line.len();
+
+            // Batches up to the snapshot's sequence number are already in it
+            if batch.seq <= seq {
+                continue;
+            }
+            for record in batch.records {
+                kb.apply_wal_record(record)?;
+            }
+            seq = batch.seq;
+            batches_replayed += 1;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&wal_path)
+            .map_err(SnapshotError::Io)?;
+        let torn_tail = len < bytes.len();
+        if torn_tail {
+            file.set_len(len as u64)
+                .and_then(|_| file.sync_data())
+                .map_err(SnapshotError::Io)?;
+        }
+
+        kb.start_journal();
+        let wal = Self {
+            dir,
+            file,
+            len: len as u64,
+            seq,
+            batches_since_snapshot: batches_replayed,
+            snapshot_every: snapshot_every.max(1),
+        };
+        let recovery = WalRecovery {
+            snapshot_seq,
+            batches_replayed,
+            torn_tail,
+        };
+        Ok((wal, kb, recovery))
+    }
+
+    /// Appends the changes journaled in `kb` as one batch and syncs it to disk.
+    ///
+    /// Does nothing if nothing changed. Once `snapshot_every` batches have
+    /// accumulated, also compacts the log. If the append fails, the log is truncated
+    /// back to its last complete batch and the taken changes are not persisted.
+    pub fn append(&mut self, kb: &mut KnowledgeBase) -> Result<(), SnapshotError> {
+        let records = kb.take_journal();
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let batch = WalBatch {
+            seq: self.seq + 1,
+            records,
+        };
+        let mut line = serde_json::to_vec(&batch).map_err(SnapshotError::Json)?;
+        line.push(b'\n');
+
+        if let Err(err) = self.file.write_all(&line).and_then(|_| self.file.sync_data()) {
+            // Don't leave a partial line for the next batch to be appended after
+            let _ = self.file.set_len(self.len);
+            return Err(SnapshotError::Io(err));
+        }
+        self.len += line.len() as u64;
+        self.seq = batch.seq;
+        self.batches_since_snapshot += 1;
+
+        if self.batches_since_snapshot >= self.snapshot_every {
+            self.compact(kb)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a full snapshot of `kb`, then truncates the log and removes older
+    /// snapshots.
+    ///
+    /// `kb` must be the knowledge base this log was opened with, with every journaled
+    /// change already appended.
+    pub fn compact(&mut self, kb: &KnowledgeBase) -> Result<(), SnapshotError> {
+        // Write under a temporary name first so a crash never leaves a partial snapshot
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        kb.save_to_path(&tmp_path)?;
+        File::open(&tmp_path)
+            .and_then(|file| file.sync_all())
+            .and_then(|_| fs::rename(&tmp_path, Self::snapshot_path(&self.dir, self.seq)))
+            .map_err(SnapshotError::Io)?;
+
+        // The new snapshot contains every batch, so the log can start over
+        self.file
+            .set_len(0)
+            .and_then(|_| self.file.sync_data())
+            .map_err(SnapshotError::Io)?;
+        self.len = 0;
+        self.batches_since_snapshot = 0;
+
+        for seq in Self::snapshots(&self.dir)? {
+            if seq < self.seq {
+                let _ = fs::remove_file(Self::snapshot_path(&self.dir, seq));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the sequence number of the last batch appended or replayed.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    fn snapshot_path(dir: &Path, seq: u64) -> PathBuf {
+        dir.join(format!("snapshot-{}.json", seq))
+    }
+
+    /// Lists the sequence numbers of the snapshots in `dir`.
+    fn snapshots(dir: &Path) -> Result<Vec<u64>, SnapshotError> {
+        let entries = fs::read_dir(dir).map_err(SnapshotError::Io)?;
+        let mut seqs = Vec::new();
+        for entry in entries {
+            let name = entry.map_err(SnapshotError::Io)?.file_name();
+            let seq = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("snapshot-"))
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|seq| seq.parse().ok());
+            seqs.extend(seq);
+        }
+        Ok(seqs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IngestOptions, NodeIdentity};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("liasiondb-wal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn write(wal: &mut WriteAheadLog, kb: &mut KnowledgeBase, path: &str, content: &str) {
+        kb.write_file(path, content, Vec::new(), &IngestOptions::default())
+            .unwrap();
+        wal.append(kb).unwrap();
+    }
+
+    #[test]
+    fn recovery_discards_torn_tail() {
+        let dir = temp_dir("torn-tail");
+        let (mut wal, mut kb, recovery) =
+            WriteAheadLog::open(&dir, 100, KnowledgeBase::new()).unwrap();
+        assert!(recovery.is_empty());
+        write(&mut wal, &mut kb, "a.md", "one");
+        write(&mut wal, &mut kb, "b.md", "two");
+        let complete_len = fs::metadata(dir.join(WAL_FILE)).unwrap().len();
+        write(&mut wal, &mut kb, "c.md", "three");
+        drop(wal);
+
+        // Cut the last batch off partway, as a crash mid-append would
+        let full_len = fs::metadata(dir.join(WAL_FILE)).unwrap().len();
+        let file = OpenOptions::new().write(true).open(dir.join(WAL_FILE)).unwrap();
+        file.set_len(complete_len + (full_len - complete_len) / 2).unwrap();
+        drop(file);
+
+        let (mut wal, mut kb, recovery) =
+            WriteAheadLog::open(&dir, 100, KnowledgeBase::new()).unwrap();
+        assert!(recovery.torn_tail);
+        assert_eq!(recovery.batches_replayed, 2);
+        assert_eq!(kb.read_file_text("a.md").as_deref(), Some("one"));
+        assert_eq!(kb.read_file_text("b.md").as_deref(), Some("two"));
+        assert_eq!(kb.read_file_text("c.md"), None);
+        assert_eq!(fs::metadata(dir.join(WAL_FILE)).unwrap().len(), complete_len);
+
+        // The log continues cleanly after the truncated tail
+        write(&mut wal, &mut kb, "d.md", "four");
+        drop(wal);
+        let (_, kb, recovery) =
+            WriteAheadLog::open(&dir, 100, KnowledgeBase::new()).unwrap();
+        assert!(!recovery.torn_tail);
+        assert_eq!(recovery.batches_replayed, 3);
+        assert_eq!(kb.read_file_text("d.md").as_deref(), Some("four"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_counts_are_journaled_with_the_next_batch() {
+        let dir = temp_dir("read-counts");
+        let (mut wal, mut kb, _) = WriteAheadLog::open(&dir, 100, KnowledgeBase::new()).unwrap();
+        write(&mut wal, &mut kb, "a.md", "one");
+        kb.record_read("a.md");
+        kb.record_read("a.md");
+        write(&mut wal, &mut kb, "b.md", "two");
+        drop(wal);
+
+        let (_, kb, recovery) = WriteAheadLog::open(&dir, 100, KnowledgeBase::new()).unwrap();
+
+        assert_eq!(recovery.batches_replayed, 2);
+        assert_eq!(kb.read_count("a.md"), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_keeps_content_only_files_apart() {
+        let dir = temp_dir("content-only");
+        let empty = || KnowledgeBase::with_node_identity(NodeIdentity::ContentOnly);
+        let (mut wal, mut kb, _) = WriteAheadLog::open(&dir, 100, empty()).unwrap();
+        write(&mut wal, &mut kb, "a.md", "alpha\nshared\none");
+        write(&mut wal, &mut kb, "b.md", "beta\nshared\ntwo");
+        drop(wal);
+
+        let (_, kb, _) = WriteAheadLog::open(&dir, 100, empty()).unwrap();
+
+        assert_eq!(kb.read_file_text("a.md").as_deref(), Some("alpha\nshared\none"));
+        assert_eq!(kb.read_file_text("b.md").as_deref(), Some("beta\nshared\ntwo"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replayed_clear_drops_content_only_file_edges() {
+        let dir = temp_dir("content-only-clear");
+        let empty = || KnowledgeBase::with_node_identity(NodeIdentity::ContentOnly);
+        let (mut wal, mut kb, _) = WriteAheadLog::open(&dir, 100, empty()).unwrap();
+        write(&mut wal, &mut kb, "a.md", "one\ntwo\nzzz");
+        write(&mut wal, &mut kb, "a.md", "one\ntwo\nthree");
+        kb.clear();
+        wal.append(&mut kb).unwrap();
+        // The new nodes reuse the cleared indices, and "two -> three" is b.md's edge
+        write(&mut wal, &mut kb, "a.md", "one\ntwo");
+        write(&mut wal, &mut kb, "b.md", "two\nthree");
+        let live = kb.read_file_text("a.md");
+        drop(wal);
+
+        let (_, kb, _) = WriteAheadLog::open(&dir, 100, empty()).unwrap();
+
+        assert_eq!(live.as_deref(), Some("one\ntwo"));
+        assert_eq!(kb.read_file_text("a.md"), live);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}